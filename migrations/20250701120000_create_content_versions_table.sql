@@ -0,0 +1,20 @@
+-- every create, update, delete and restore of a content row is recorded here.
+-- content_id is intentionally not a foreign key so history outlives deletes.
+CREATE TABLE content_versions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    content_id INTEGER NOT NULL,
+    page_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    body TEXT NOT NULL,
+    action TEXT NOT NULL,
+    user_id INTEGER,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (page_id) REFERENCES pages(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_content_versions_content_id ON content_versions(content_id);
+
+-- seed a baseline revision for content that already exists
+INSERT INTO content_versions (content_id, page_id, name, body, action, created_at)
+SELECT id, page_id, name, body, 'create', created_at FROM content;
@@ -0,0 +1,7 @@
+-- body now holds the working draft; published_body is what gets delivered.
+-- a NULL published_body means the entry has never been published.
+ALTER TABLE content ADD COLUMN published_body TEXT;
+ALTER TABLE content ADD COLUMN published_at DATETIME;
+
+-- everything that exists today is already live, so keep it that way
+UPDATE content SET published_body = body, published_at = updated_at;
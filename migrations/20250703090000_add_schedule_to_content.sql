@@ -0,0 +1,7 @@
+-- publish_at promotes the draft once it passes, unpublish_at pulls the entry
+-- out of delivery once it passes. both are stored in UTC.
+ALTER TABLE content ADD COLUMN publish_at DATETIME;
+ALTER TABLE content ADD COLUMN unpublish_at DATETIME;
+
+CREATE INDEX IF NOT EXISTS idx_content_publish_at ON content(publish_at);
+CREATE INDEX IF NOT EXISTS idx_content_unpublish_at ON content(unpublish_at);
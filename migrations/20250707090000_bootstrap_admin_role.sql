@@ -0,0 +1,6 @@
+-- roles are now enforced: 0 viewer, 1 editor, 2 publisher, 3 admin.
+-- existing users keep the editor role they were created with, but someone has
+-- to be able to manage apps, so the earliest account becomes an admin.
+UPDATE users SET role = 3
+WHERE id = (SELECT MIN(id) FROM users)
+AND NOT EXISTS (SELECT 1 FROM users WHERE role = 3);
@@ -0,0 +1,17 @@
+-- who can see an app, and what they may do in it
+CREATE TABLE app_members (
+    app_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    role INTEGER NOT NULL DEFAULT 1,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (app_id, user_id),
+    FOREIGN KEY (app_id) REFERENCES apps(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_app_members_user_id ON app_members(user_id);
+
+-- existing installs keep working as before: everyone can see every app with
+-- the role they already had
+INSERT INTO app_members (app_id, user_id, role)
+SELECT apps.id, users.id, users.role FROM apps CROSS JOIN users;
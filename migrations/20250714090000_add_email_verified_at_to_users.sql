@@ -0,0 +1,5 @@
+ALTER TABLE users ADD COLUMN email_verified_at DATETIME;
+
+-- accounts that exist already have been signing in without verifying, so
+-- don't lock them out now
+UPDATE users SET email_verified_at = CURRENT_TIMESTAMP;
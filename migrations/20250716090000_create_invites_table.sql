@@ -0,0 +1,15 @@
+CREATE TABLE invites (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email TEXT NOT NULL,
+    role INTEGER NOT NULL DEFAULT 1,
+    token_hash TEXT NOT NULL,
+    invited_by INTEGER,
+    expires_at DATETIME NOT NULL,
+    accepted_at DATETIME,
+    revoked_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (invited_by) REFERENCES users(id) ON DELETE SET NULL,
+    UNIQUE (token_hash)
+);
+
+CREATE INDEX IF NOT EXISTS idx_invites_email ON invites(email);
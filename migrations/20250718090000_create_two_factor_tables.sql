@@ -0,0 +1,29 @@
+-- a user's TOTP secret. It is stored as is since codes can't be checked
+-- without it, and only counts once confirmed_at is set
+CREATE TABLE two_factor_secrets (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    secret TEXT NOT NULL,
+    -- the last 30 second time step a code was accepted for, so that a code
+    -- can't be used twice
+    last_used_step INTEGER NOT NULL DEFAULT 0,
+    confirmed_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE recovery_codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    code_hash TEXT NOT NULL,
+    used_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes(user_id);
+
+-- roles whose users have to set up two-factor authentication
+CREATE TABLE two_factor_required_roles (
+    role INTEGER PRIMARY KEY NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,28 @@
+CREATE TABLE passkeys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    -- base64url, the way browsers report it
+    credential_id TEXT NOT NULL,
+    -- the COSE_Key the authenticator created
+    public_key BLOB NOT NULL,
+    sign_count INTEGER NOT NULL DEFAULT 0,
+    last_used_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE (credential_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_passkeys_user_id ON passkeys(user_id);
+
+-- challenges handed to the browser for a passkey ceremony, each good for one
+-- attempt
+CREATE TABLE passkey_challenges (
+    challenge TEXT PRIMARY KEY NOT NULL,
+    -- 'register' or 'authenticate'
+    ceremony TEXT NOT NULL,
+    -- who is registering a passkey, unknown until the end for sign ins
+    user_id INTEGER,
+    expires_at DATETIME NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,15 @@
+-- password and two-factor sign in attempts, used to slow down guessing and
+-- shown to admins. `email` is kept even when no such user exists.
+CREATE TABLE sign_in_attempts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email TEXT NOT NULL,
+    user_id INTEGER,
+    ip TEXT NOT NULL,
+    -- 'failed', 'succeeded', 'blocked', 'locked' or 'unlocked'
+    outcome TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sign_in_attempts_email ON sign_in_attempts(email);
+CREATE INDEX IF NOT EXISTS idx_sign_in_attempts_ip ON sign_in_attempts(ip, created_at);
@@ -0,0 +1,8 @@
+-- images people uploaded as their avatar, served from /users/{id}/avatar
+CREATE TABLE user_avatars (
+    user_id INTEGER PRIMARY KEY,
+    content_type TEXT NOT NULL,
+    data BLOB NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- disabled accounts keep their data but can't sign in
+ALTER TABLE users ADD COLUMN disabled_at DATETIME;
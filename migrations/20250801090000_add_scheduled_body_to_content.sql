@@ -0,0 +1,6 @@
+-- a scheduled publish ships the body as it was when the publish was
+-- scheduled, not whatever the draft holds by then. existing schedules were
+-- made against the current draft, so that is their snapshot.
+ALTER TABLE content ADD COLUMN scheduled_body TEXT;
+
+UPDATE content SET scheduled_body = body WHERE publish_at IS NOT NULL;
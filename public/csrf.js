@@ -0,0 +1,10 @@
+// Sends the page's CSRF token along with every htmx request. The server
+// rejects requests that change anything without it.
+function csrfToken() {
+  const meta = document.querySelector('meta[name="csrf-token"]');
+  return meta ? meta.content : "";
+}
+
+document.addEventListener("htmx:configRequest", function (e) {
+  e.detail.headers["X-CSRF-Token"] = csrfToken();
+});
@@ -0,0 +1,110 @@
+// Runs the browser side of passkey registration and sign in. The server
+// sends and expects binary values as base64url strings.
+(function () {
+  function toBuffer(value) {
+    const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+    const binary = atob(base64.padEnd(Math.ceil(base64.length / 4) * 4, "="));
+    return Uint8Array.from(binary, (c) => c.charCodeAt(0)).buffer;
+  }
+
+  function toBase64Url(buffer) {
+    const binary = String.fromCharCode(...new Uint8Array(buffer));
+    return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+  }
+
+  function showError(form, message) {
+    let banner = form.querySelector(".banner.error");
+    if (!banner) {
+      banner = document.createElement("div");
+      banner.className = "banner error";
+      form.prepend(banner);
+    }
+    banner.textContent = message;
+  }
+
+  async function registerPasskey(form) {
+    const response = await fetch("/me/passkeys/options", {
+      method: "POST",
+      headers: { "X-CSRF-Token": csrfToken() },
+    });
+    const options = await response.json();
+    options.challenge = toBuffer(options.challenge);
+    options.user.id = toBuffer(options.user.id);
+    options.excludeCredentials = options.excludeCredentials.map((c) => ({
+      ...c,
+      id: toBuffer(c.id),
+    }));
+
+    const credential = await navigator.credentials.create({ publicKey: options });
+    const result = await fetch("/me/passkeys", {
+      method: "PUT",
+      headers: {
+        "Content-Type": "application/json",
+        "X-CSRF-Token": csrfToken(),
+      },
+      body: JSON.stringify({
+        name: form.querySelector("[name=name]").value,
+        client_data_json: toBase64Url(credential.response.clientDataJSON),
+        attestation_object: toBase64Url(credential.response.attestationObject),
+      }),
+    });
+
+    const section = document.getElementById("passkeys");
+    section.outerHTML = await result.text();
+    htmx.process(document.getElementById("passkeys"));
+  }
+
+  async function signInWithPasskey(form) {
+    const response = await fetch("/signin/passkey/options", {
+      method: "POST",
+      headers: { "X-CSRF-Token": csrfToken() },
+    });
+    const options = await response.json();
+    options.challenge = toBuffer(options.challenge);
+
+    const credential = await navigator.credentials.get({ publicKey: options });
+    const returnTo = form.querySelector("[name=return_to]");
+    const result = await fetch("/signin/passkey", {
+      method: "PUT",
+      headers: {
+        "Content-Type": "application/json",
+        "X-CSRF-Token": csrfToken(),
+      },
+      body: JSON.stringify({
+        id: credential.id,
+        client_data_json: toBase64Url(credential.response.clientDataJSON),
+        authenticator_data: toBase64Url(credential.response.authenticatorData),
+        signature: toBase64Url(credential.response.signature),
+        return_to: returnTo ? returnTo.value : null,
+      }),
+    });
+
+    const body = await result.json().catch(() => ({}));
+    if (body.redirect) {
+      window.location.assign(body.redirect);
+    } else {
+      showError(form, body.error ? body.error.message : "Couldn't sign in with a passkey.");
+    }
+  }
+
+  document.addEventListener("submit", function (event) {
+    const form = event.target.closest("[data-passkey-register]");
+    if (form) {
+      event.preventDefault();
+      registerPasskey(form).catch(() =>
+        showError(form, "Adding the passkey was cancelled or failed."),
+      );
+    }
+  });
+
+  document.addEventListener("click", function (event) {
+    const button = event.target.closest("[data-passkey-signin]");
+    if (button) {
+      event.preventDefault();
+      const form = button.closest("form");
+      signInWithPasskey(form).catch(() =>
+        showError(form, "Signing in with a passkey was cancelled or failed."),
+      );
+    }
+  });
+})();
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{StatusCode, header::AUTHORIZATION, request::Parts},
+};
+
+use crate::{
+    AppState,
+    extractors::current_user::ApiUser,
+    models::api_key::{ApiKey, ApiKeyScope},
+    repositories::api_keys::ApiKeyRepository,
+    routes::api::error::ApiError,
+    services::api_keys::{ApiKeyService, KEY_PREFIX},
+    user::User,
+};
+
+/// An app scoped API key presented as `Authorization: Bearer <key>`.
+pub struct AppApiKey(pub ApiKey);
+
+impl AppApiKey {
+    /// Rejects the request unless the key belongs to `app_id` and carries
+    /// at least the `required` scope.
+    pub fn require(&self, app_id: &i64, required: ApiKeyScope) -> Result<(), ApiError> {
+        if self.0.app_id != *app_id {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "This API key does not grant access to this app.",
+            ));
+        }
+        if !self.0.scope.allows(required) {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "insufficient_scope",
+                format!("This action requires a {} key.", required.as_str()),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for AppApiKey {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|hv| hv.to_str().ok())
+            .and_then(|hv| hv.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "missing_api_key",
+                "Provide an API key as 'Authorization: Bearer <key>'.",
+            ))?;
+
+        let api_key_service = ApiKeyService::new(ApiKeyRepository::new(&state.db));
+
+        match api_key_service.authenticate(token).await {
+            Ok(key) => Ok(AppApiKey(key)),
+            Err(sqlx::Error::RowNotFound) => Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_api_key",
+                "The API key is invalid or has been revoked.",
+            )),
+            Err(_) => Err(ApiError::internal()),
+        }
+    }
+}
+
+/// Whoever is calling the management API: someone with a personal access
+/// token, or an app's write key, which can only manage that app.
+pub enum ApiCaller {
+    User(User),
+    Key(ApiKey),
+}
+
+impl ApiCaller {
+    /// The user to act as before it's known which app the request is about.
+    pub fn user(&self) -> User {
+        match self {
+            ApiCaller::User(user) => user.clone(),
+            ApiCaller::Key(key) => User::for_api_key(key),
+        }
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for ApiCaller {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let is_api_key = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|hv| hv.to_str().ok())
+            .and_then(|hv| hv.strip_prefix("Bearer "))
+            .is_some_and(|token| token.trim().starts_with(KEY_PREFIX));
+        if !is_api_key {
+            let ApiUser(user) = ApiUser::from_request_parts(parts, state).await?;
+            return Ok(ApiCaller::User(user));
+        }
+
+        let AppApiKey(key) = AppApiKey::from_request_parts(parts, state).await?;
+        if !key.scope.allows(ApiKeyScope::Write) {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "insufficient_scope",
+                "Managing an app needs a write key.",
+            ));
+        }
+
+        Ok(ApiCaller::Key(key))
+    }
+}
@@ -0,0 +1,42 @@
+use std::{convert::Infallible, env, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+
+/// The address of whoever made the request. Behind a reverse proxy, set
+/// `TRUST_PROXY=true` to use the address the proxy appended to
+/// `X-Forwarded-For` instead of the proxy's own.
+pub struct ClientIp(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if trust_proxy()
+            && let Some(ip) = parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|hv| hv.to_str().ok())
+                // earlier entries come from the client and can't be trusted
+                .and_then(|forwarded| forwarded.rsplit(',').next())
+                .map(str::trim)
+                .filter(|ip| !ip.is_empty())
+        {
+            return Ok(ClientIp(ip.to_string()));
+        }
+
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        Ok(ClientIp(ip))
+    }
+}
+
+fn trust_proxy() -> bool {
+    env::var("TRUST_PROXY").is_ok_and(|value| value == "true")
+}
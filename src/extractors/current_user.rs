@@ -1,19 +1,38 @@
-use std::{convert::Infallible, env, sync::Arc};
+use std::{convert::Infallible, sync::Arc};
 
 use axum::{
     extract::FromRequestParts,
-    http::{StatusCode, request::Parts},
+    http::{StatusCode, header::AUTHORIZATION, request::Parts},
 };
 use jsonwebtoken::{DecodingKey, Validation, decode};
 
 use crate::{
     AppState,
-    user::{User, auth::UserClaims, repository::UserRepository, service::UserService},
+    routes::api::error::ApiError,
+    user::{
+        User,
+        auth::{AuthService, UserClaims, jwt_secret},
+        repository::UserRepository,
+        service::UserService,
+        tokens::TokenService,
+        two_factor::TwoFactorService,
+    },
 };
 
 pub struct CurrentUser(pub User);
 pub struct MaybeUser(pub Option<User>);
 
+/// The user behind a personal access token, for the management API. Session
+/// cookies aren't accepted, as `/api/` requests skip the CSRF check.
+pub struct ApiUser(pub User);
+
+/// The id of the session behind the request's `auth_token` cookie, when it
+/// is still live.
+pub struct CurrentSession(pub Option<String>);
+
+#[derive(Clone)]
+struct SessionId(String);
+
 impl FromRequestParts<Arc<AppState>> for CurrentUser {
     type Rejection = (StatusCode, &'static str);
 
@@ -21,37 +40,10 @@ impl FromRequestParts<Arc<AppState>> for CurrentUser {
         parts: &mut Parts,
         state: &Arc<AppState>,
     ) -> Result<Self, Self::Rejection> {
-        let cookie_header = parts
-            .headers
-            .get(axum::http::header::COOKIE)
-            .and_then(|hv| hv.to_str().ok())
-            .ok_or((StatusCode::UNAUTHORIZED, "Missing cookies"))?;
-
-        let token = cookie_header
-            .split(';')
-            .map(str::trim)
-            .find_map(|cookie| cookie.strip_prefix("auth_token=").map(|val| val.to_owned()))
-            .ok_or((StatusCode::UNAUTHORIZED, "auth_token cookie not found"))?;
-
-        let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be present");
-        let key = DecodingKey::from_secret(secret.as_bytes());
-
-        let token_data = decode::<UserClaims>(&token, &key, &Validation::default())
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token"))?;
-
-        let user_repository = UserRepository::new(&state.db);
-        let user_service = UserService::new(user_repository);
-        let user_id = token_data
-            .claims
-            .sub
-            .parse::<i64>()
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid user id in token"))?;
-        let user = user_service
-            .find_user_by_id(user_id)
+        authenticate(parts, state)
             .await
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Something went wrong"));
-
-        Ok(CurrentUser(user.unwrap()))
+            .map(CurrentUser)
+            .map_err(|reason| (StatusCode::UNAUTHORIZED, reason))
     }
 }
 
@@ -62,43 +54,153 @@ impl FromRequestParts<Arc<AppState>> for MaybeUser {
         parts: &mut Parts,
         state: &Arc<AppState>,
     ) -> Result<Self, Self::Rejection> {
-        let cookie_header = match parts
-            .headers
-            .get(axum::http::header::COOKIE)
-            .and_then(|hv| hv.to_str().ok())
-        {
-            Some(h) => h,
-            None => return Ok(MaybeUser(None)),
-        };
-
-        let token = match cookie_header
-            .split(';')
-            .map(str::trim)
-            .find_map(|cookie| cookie.strip_prefix("auth_token=").map(|val| val.to_owned()))
-        {
-            Some(t) => t,
-            None => return Ok(MaybeUser(None)),
-        };
-
-        let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be present");
-        let key = DecodingKey::from_secret(secret.as_bytes());
-
-        let token_data = match decode::<UserClaims>(&token, &key, &Validation::default()) {
-            Ok(s) => s,
-            Err(_) => return Ok(MaybeUser(None)),
-        };
-
-        let user_repository = UserRepository::new(&state.db);
-        let user_service = UserService::new(user_repository);
-        let user_id = match token_data.claims.sub.parse::<i64>() {
-            Ok(id) => id,
-            Err(_) => return Ok(MaybeUser(None)),
-        };
-        let user = match user_service.find_user_by_id(user_id).await {
-            Ok(user) => user,
-            Err(_) => return Ok(MaybeUser(None)),
-        };
-
-        Ok(MaybeUser(Some(user)))
+        Ok(MaybeUser(authenticate(parts, state).await.ok()))
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for ApiUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let secret = bearer_token(parts).ok_or(ApiError::unauthorized(
+            "Provide a personal access token as 'Authorization: Bearer <token>'.",
+        ))?;
+
+        let user = authenticate_token(parts, state, &secret)
+            .await
+            .map_err(ApiError::unauthorized)?;
+
+        // the management API isn't behind the admin pages' middleware, so
+        // it holds people who must set up two-factor authentication back
+        // here instead
+        let two_factor_service = TwoFactorService::new(&state.db);
+        match two_factor_service.must_enrol(&user).await {
+            Ok(false) => Ok(ApiUser(user)),
+            Ok(true) => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "two_factor_required",
+                "Set up two-factor authentication to continue.",
+            )),
+            Err(_) => Err(ApiError::internal()),
+        }
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for CurrentSession {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let _ = authenticate(parts, state).await;
+
+        Ok(CurrentSession(
+            parts.extensions.get::<SessionId>().map(|id| id.0.clone()),
+        ))
+    }
+}
+
+/// Resolves the `auth_token` cookie to its user, as long as the session it
+/// was issued for hasn't expired or been revoked. A personal access token in
+/// an `Authorization: Bearer` header is used instead when there is one. The
+/// result is kept in the request extensions so later extractors don't
+/// decode the token again.
+async fn authenticate(parts: &mut Parts, state: &AppState) -> Result<User, &'static str> {
+    if let Some(user) = parts.extensions.get::<User>() {
+        return Ok(user.clone());
     }
+
+    if let Some(secret) = bearer_token(parts) {
+        return authenticate_token(parts, state, &secret).await;
+    }
+
+    let cookie_header = parts
+        .headers
+        .get(axum::http::header::COOKIE)
+        .and_then(|hv| hv.to_str().ok())
+        .ok_or("Missing cookies")?;
+
+    let token = cookie_header
+        .split(';')
+        .map(str::trim)
+        .find_map(|cookie| cookie.strip_prefix("auth_token=").map(|val| val.to_owned()))
+        .ok_or("auth_token cookie not found")?;
+
+    let key = DecodingKey::from_secret(jwt_secret().as_bytes());
+
+    let claims = decode::<UserClaims>(&token, &key, &Validation::default())
+        .map_err(|_| "Invalid token")?
+        .claims;
+
+    let auth_service = AuthService::new(state.db.clone());
+    if !auth_service
+        .is_session_active(&claims)
+        .await
+        .unwrap_or(false)
+    {
+        return Err("Session has ended");
+    }
+
+    let user_repository = UserRepository::new(&state.db);
+    let user_service = UserService::new(user_repository);
+    let user_id = claims
+        .sub
+        .parse::<i64>()
+        .map_err(|_| "Invalid user id in token")?;
+    let user = user_service
+        .find_user_by_id(user_id)
+        .await
+        .map_err(|_| "User not found")?;
+    if user.is_disabled() {
+        return Err("Account is disabled");
+    }
+
+    parts.extensions.insert(user.clone());
+    parts.extensions.insert(SessionId(claims.jti));
+
+    Ok(user)
+}
+
+/// The user behind a personal access token, limited to the token's scopes.
+/// There's no session, so `CurrentSession` gives `None` for these requests.
+async fn authenticate_token(
+    parts: &mut Parts,
+    state: &AppState,
+    secret: &str,
+) -> Result<User, &'static str> {
+    let token_service = TokenService::new(&state.db);
+    let (user_id, scopes) = token_service
+        .authenticate(secret)
+        .await
+        .map_err(|_| "Invalid access token")?;
+
+    let user_repository = UserRepository::new(&state.db);
+    let user = user_repository
+        .find_by_id(user_id)
+        .await
+        .map_err(|_| "User not found")?;
+    if user.is_disabled() {
+        return Err("Account is disabled");
+    }
+
+    let user = User {
+        token_scopes: Some(scopes),
+        ..user
+    };
+    parts.extensions.insert(user.clone());
+
+    Ok(user)
+}
+
+fn bearer_token(parts: &Parts) -> Option<String> {
+    parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|hv| hv.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
 }
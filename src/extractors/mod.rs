@@ -1 +1,3 @@
+pub mod api_key;
+pub mod client_ip;
 pub mod current_user;
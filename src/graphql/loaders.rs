@@ -0,0 +1,101 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::dataloader::{DataLoader, HashMapCache, Loader};
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{app::App, content::Content, page::Page},
+    repositories::{apps::AppRepository, content::ContentRepository, pages::PageRepository},
+};
+
+/// Batches the lookups a query makes while it resolves, so that asking for
+/// every page of every app, and their content, costs one query per level
+/// rather than one per app and page.
+#[derive(Clone)]
+pub struct Loaders {
+    pub apps: Arc<DataLoader<AppsById, HashMapCache>>,
+    pub pages: Arc<DataLoader<PagesById, HashMapCache>>,
+    pub pages_by_app: Arc<DataLoader<PagesByAppId, HashMapCache>>,
+    pub content_by_page: Arc<DataLoader<ContentByPageId, HashMapCache>>,
+}
+
+impl Loaders {
+    pub fn new(db: &SqlitePool) -> Self {
+        Loaders {
+            apps: Arc::new(loader(AppsById(db.clone()))),
+            pages: Arc::new(loader(PagesById(db.clone()))),
+            pages_by_app: Arc::new(loader(PagesByAppId(db.clone()))),
+            content_by_page: Arc::new(loader(ContentByPageId(db.clone()))),
+        }
+    }
+}
+
+fn loader<T>(loader: T) -> DataLoader<T, HashMapCache> {
+    DataLoader::with_cache(loader, tokio::spawn, HashMapCache::default())
+}
+
+pub struct AppsById(SqlitePool);
+
+impl Loader<i64> for AppsById {
+    type Value = App;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, ids: &[i64]) -> Result<HashMap<i64, App>, Self::Error> {
+        let apps = AppRepository::new(&self.0).find_all_by_ids(ids).await?;
+
+        Ok(apps.into_iter().map(|app| (app.id, app)).collect())
+    }
+}
+
+pub struct PagesById(SqlitePool);
+
+impl Loader<i64> for PagesById {
+    type Value = Page;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, ids: &[i64]) -> Result<HashMap<i64, Page>, Self::Error> {
+        let pages = PageRepository::new(&self.0).find_all_by_ids(ids).await?;
+
+        Ok(pages.into_iter().map(|page| (page.id, page)).collect())
+    }
+}
+
+pub struct PagesByAppId(SqlitePool);
+
+impl Loader<i64> for PagesByAppId {
+    type Value = Vec<Page>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, app_ids: &[i64]) -> Result<HashMap<i64, Vec<Page>>, Self::Error> {
+        let pages = PageRepository::new(&self.0)
+            .find_all_by_app_ids(app_ids)
+            .await?;
+
+        let mut by_app: HashMap<i64, Vec<Page>> = HashMap::new();
+        for page in pages {
+            by_app.entry(page.app_id).or_default().push(page);
+        }
+
+        Ok(by_app)
+    }
+}
+
+pub struct ContentByPageId(SqlitePool);
+
+impl Loader<i64> for ContentByPageId {
+    type Value = Vec<Content>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, page_ids: &[i64]) -> Result<HashMap<i64, Vec<Content>>, Self::Error> {
+        let contents = ContentRepository::new(&self.0)
+            .find_all_by_page_ids(page_ids)
+            .await?;
+
+        let mut by_page: HashMap<i64, Vec<Content>> = HashMap::new();
+        for content in contents {
+            by_page.entry(content.page_id).or_default().push(content);
+        }
+
+        Ok(by_page)
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use async_graphql::{BatchRequest, Context, EmptySubscription, Error, ErrorExtensions, Schema};
+use sqlx::SqlitePool;
+
+use crate::{
+    graphql::{loaders::Loaders, mutation::Mutation, query::Query},
+    models::app_member::AppScope,
+    repositories::app_members::AppMemberRepository,
+    services::{app_members::AppMemberService, error::ServiceError},
+    user::User,
+};
+
+mod loaders;
+mod mutation;
+mod objects;
+mod query;
+
+pub type WordfordSchema = Schema<Query, Mutation, EmptySubscription>;
+
+/// How deeply a query may nest, e.g. `apps { pages { app { pages ... } } }`.
+const MAX_DEPTH: usize = 10;
+
+/// How many fields a single query may ask for in total.
+const MAX_COMPLEXITY: usize = 1000;
+
+pub fn schema() -> WordfordSchema {
+    Schema::build(Query, Mutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// Readies a request to run as `user`. Every request gets its own loaders,
+/// so nothing one user loaded is ever handed to another.
+pub fn prepare(request: BatchRequest, db: &SqlitePool, user: User) -> BatchRequest {
+    request.data(user).data(db.clone()).data(Loaders::new(db))
+}
+
+fn current_user<'a>(ctx: &Context<'a>) -> &'a User {
+    ctx.data_unchecked::<User>()
+}
+
+fn db<'a>(ctx: &Context<'a>) -> &'a SqlitePool {
+    ctx.data_unchecked::<SqlitePool>()
+}
+
+fn loaders<'a>(ctx: &Context<'a>) -> &'a Loaders {
+    ctx.data_unchecked::<Loaders>()
+}
+
+/// Resolves the user's role in the app `scope` belongs to, or `None` when
+/// they aren't a member.
+async fn membership(ctx: &Context<'_>, scope: AppScope) -> Result<Option<User>, Error> {
+    let app_member_service = AppMemberService::new(AppMemberRepository::new(db(ctx)));
+
+    match app_member_service.member(current_user(ctx), scope).await {
+        Ok(member) => Ok(Some(member)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(err) => Err(database_error(err)),
+    }
+}
+
+/// Like `membership`, for mutations, where not being a member is an error.
+async fn member_of(ctx: &Context<'_>, scope: AppScope) -> Result<User, Error> {
+    membership(ctx, scope)
+        .await?
+        .ok_or_else(|| not_found(scope))
+}
+
+fn not_found(scope: AppScope) -> Error {
+    let message = match scope {
+        AppScope::App(id) => format!("No app {} exists.", id),
+        AppScope::Page(id) => format!("No page {} exists.", id),
+        AppScope::Content(id) => format!("No content {} exists.", id),
+    };
+
+    Error::new(message).extend_with(|_, e| e.set("code", "NOT_FOUND"))
+}
+
+fn database_error(err: sqlx::Error) -> Error {
+    service_error(ServiceError::Database(err))
+}
+
+/// Turns a service failure into an error with a `code` extension clients can
+/// branch on. Validation failures also list what's wrong with each field.
+fn service_error(err: ServiceError) -> Error {
+    match err {
+        ServiceError::Forbidden(_) => Error::new("Your role or token doesn't allow this.")
+            .extend_with(|_, e| e.set("code", "FORBIDDEN")),
+        ServiceError::Invalid(message) => {
+            Error::new(message).extend_with(|_, e| e.set("code", "INVALID_REQUEST"))
+        }
+        ServiceError::Validation(fields) => {
+            let fields = serde_json::to_value(&fields).expect("field errors should serialize");
+            Error::new("Some fields are not valid.").extend_with(|_, e| {
+                e.set("code", "VALIDATION_FAILED");
+                e.set(
+                    "fields",
+                    async_graphql::Value::from_json(fields.clone()).unwrap_or_default(),
+                );
+            })
+        }
+        // names are unique within their parent, so a unique violation always
+        // means the name is taken
+        ServiceError::Database(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            Error::new("That name is already taken.")
+                .extend_with(|_, e| e.set("code", "NAME_TAKEN"))
+        }
+        ServiceError::Database(sqlx::Error::RowNotFound) => {
+            Error::new("The requested resource does not exist.")
+                .extend_with(|_, e| e.set("code", "NOT_FOUND"))
+        }
+        ServiceError::Database(_) | ServiceError::Hashing(_) => internal_error(),
+    }
+}
+
+// a failed batch is shared by everything waiting on it, so all that's left
+// to report is that it failed
+fn load_error(_: Arc<sqlx::Error>) -> Error {
+    internal_error()
+}
+
+fn internal_error() -> Error {
+    Error::new("Something went wrong on our end.")
+        .extend_with(|_, e| e.set("code", "INTERNAL_ERROR"))
+}
@@ -0,0 +1,239 @@
+use async_graphql::{Context, Error, InputObject, Object};
+
+use crate::{
+    graphql::{current_user, db, member_of, not_found, service_error},
+    models::{
+        app::{App, CreateAppForm, UpdateAppRequest},
+        app_member::AppScope,
+        content::{Content, NewContentRequest, UpdateContentRequest},
+        page::{NewPageRequest, Page, UpdatePageRequest},
+    },
+    repositories::{apps::AppRepository, pages::PageRepository},
+    services::{apps::AppService, content::ContentService, pages::PageService},
+};
+
+#[derive(InputObject)]
+pub struct CreateAppInput {
+    pub name: String,
+    #[graphql(default)]
+    pub description: String,
+    #[graphql(default)]
+    pub url: String,
+}
+
+/// Fields left out keep their current value.
+#[derive(InputObject)]
+pub struct UpdateAppInput {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(InputObject)]
+pub struct CreateContentInput {
+    pub page_id: i64,
+    pub name: String,
+    #[graphql(default)]
+    pub body: String,
+    /// Publish straight away instead of saving a draft.
+    #[graphql(default)]
+    pub publish: bool,
+    /// When to publish, in UTC, like `2025-07-01T09:30`.
+    pub publish_at: Option<String>,
+    /// When to stop delivering the content, in UTC.
+    pub unpublish_at: Option<String>,
+}
+
+/// Fields left out keep their current value. An empty `publishAt` or
+/// `unpublishAt` clears it.
+#[derive(InputObject)]
+pub struct UpdateContentInput {
+    pub name: Option<String>,
+    pub body: Option<String>,
+    #[graphql(default)]
+    pub publish: bool,
+    pub publish_at: Option<String>,
+    pub unpublish_at: Option<String>,
+}
+
+pub struct Mutation;
+
+/// Every change goes through the same services as the web UI, with the
+/// user's role in the app it touches, so it's allowed exactly when the UI
+/// would allow it.
+#[Object]
+impl Mutation {
+    /// Creates an app, with you as its first admin.
+    async fn create_app(&self, ctx: &Context<'_>, input: CreateAppInput) -> Result<App, Error> {
+        let app_service = AppService::new(AppRepository::new(db(ctx)));
+        let request = CreateAppForm {
+            name: input.name.trim().to_string(),
+            description: input.description,
+            url: input.url.trim().to_string(),
+        };
+
+        app_service
+            .create_app(current_user(ctx), request)
+            .await
+            .map_err(service_error)
+    }
+
+    async fn update_app(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        input: UpdateAppInput,
+    ) -> Result<App, Error> {
+        let member = member_of(ctx, AppScope::App(id)).await?;
+        let app_service = AppService::new(AppRepository::new(db(ctx)));
+
+        let existing = app_service
+            .find_by_id(&member, &id)
+            .await
+            .map_err(|_| not_found(AppScope::App(id)))?
+            .app;
+        let request = UpdateAppRequest {
+            name: input.name.unwrap_or(existing.name).trim().to_string(),
+            description: input.description.unwrap_or(existing.description),
+            url: input.url.unwrap_or(existing.url).trim().to_string(),
+        };
+
+        app_service
+            .update_app(&member, &id, request)
+            .await
+            .map_err(service_error)
+    }
+
+    /// Deletes an app with its pages and content. Returns the app's id.
+    async fn delete_app(&self, ctx: &Context<'_>, id: i64) -> Result<i64, Error> {
+        let member = member_of(ctx, AppScope::App(id)).await?;
+        let app_service = AppService::new(AppRepository::new(db(ctx)));
+        app_service
+            .delete_app(&member, &id)
+            .await
+            .map_err(service_error)?;
+
+        Ok(id)
+    }
+
+    async fn create_page(
+        &self,
+        ctx: &Context<'_>,
+        app_id: i64,
+        name: String,
+    ) -> Result<Page, Error> {
+        let member = member_of(ctx, AppScope::App(app_id)).await?;
+        let page_service = PageService::new(PageRepository::new(db(ctx)));
+
+        page_service
+            .create_page(&member, NewPageRequest { app_id, name })
+            .await
+            .map_err(service_error)
+    }
+
+    async fn rename_page(&self, ctx: &Context<'_>, id: i64, name: String) -> Result<Page, Error> {
+        let member = member_of(ctx, AppScope::Page(id)).await?;
+        let page_service = PageService::new(PageRepository::new(db(ctx)));
+
+        page_service
+            .update_page(&member, &id, UpdatePageRequest { name })
+            .await
+            .map_err(service_error)
+    }
+
+    /// Deletes a page with its content. Returns the page's id.
+    async fn delete_page(&self, ctx: &Context<'_>, id: i64) -> Result<i64, Error> {
+        let member = member_of(ctx, AppScope::Page(id)).await?;
+        let page_service = PageService::new(PageRepository::new(db(ctx)));
+        page_service
+            .delete_page(&member, &id)
+            .await
+            .map_err(service_error)?;
+
+        Ok(id)
+    }
+
+    /// Publishes every entry on the page that has unpublished changes, and
+    /// returns them.
+    async fn publish_page(&self, ctx: &Context<'_>, id: i64) -> Result<Vec<Content>, Error> {
+        let member = member_of(ctx, AppScope::Page(id)).await?;
+        let content_service = ContentService::new(db(ctx));
+
+        content_service
+            .publish_page(&member, &id)
+            .await
+            .map_err(service_error)
+    }
+
+    async fn create_content(
+        &self,
+        ctx: &Context<'_>,
+        input: CreateContentInput,
+    ) -> Result<Content, Error> {
+        let member = member_of(ctx, AppScope::Page(input.page_id)).await?;
+        let content_service = ContentService::new(db(ctx));
+        let request = NewContentRequest {
+            page_id: input.page_id,
+            name: input.name,
+            body: input.body,
+            publish: input.publish,
+            publish_at: input.publish_at,
+            unpublish_at: input.unpublish_at,
+        };
+
+        content_service
+            .create_content(&member, request)
+            .await
+            .map_err(service_error)
+    }
+
+    async fn update_content(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        input: UpdateContentInput,
+    ) -> Result<Content, Error> {
+        let member = member_of(ctx, AppScope::Content(id)).await?;
+        let content_service = ContentService::new(db(ctx));
+
+        let existing = content_service
+            .find_by_id(&id)
+            .await
+            .map_err(|_| not_found(AppScope::Content(id)))?;
+        let request = UpdateContentRequest {
+            content_id: id,
+            name: input.name.unwrap_or(existing.name),
+            body: input.body.unwrap_or(existing.body),
+            publish: input.publish,
+            publish_at: input.publish_at.or(existing.publish_at),
+            unpublish_at: input.unpublish_at.or(existing.unpublish_at),
+        };
+
+        content_service
+            .update_content(&member, request)
+            .await
+            .map_err(service_error)
+    }
+
+    async fn publish_content(&self, ctx: &Context<'_>, id: i64) -> Result<Content, Error> {
+        let member = member_of(ctx, AppScope::Content(id)).await?;
+        let content_service = ContentService::new(db(ctx));
+
+        content_service
+            .publish_content(&member, &id)
+            .await
+            .map_err(service_error)
+    }
+
+    /// Deletes a content entry. Returns its id.
+    async fn delete_content(&self, ctx: &Context<'_>, id: i64) -> Result<i64, Error> {
+        let member = member_of(ctx, AppScope::Content(id)).await?;
+        let content_service = ContentService::new(db(ctx));
+        content_service
+            .delete_content(&member, &id)
+            .await
+            .map_err(service_error)?;
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,106 @@
+use async_graphql::{ComplexObject, Context, Error};
+use chrono::Utc;
+
+use crate::{
+    graphql::{load_error, loaders, not_found},
+    models::{app::App, app_member::AppScope, content::Content, page::Page},
+};
+
+#[ComplexObject]
+impl App {
+    /// The app's pages, optionally only those whose name contains `name`,
+    /// ignoring case.
+    async fn pages(&self, ctx: &Context<'_>, name: Option<String>) -> Result<Vec<Page>, Error> {
+        let pages = loaders(ctx)
+            .pages_by_app
+            .load_one(self.id)
+            .await
+            .map_err(load_error)?
+            .unwrap_or_default();
+
+        let name = name.unwrap_or_default().to_lowercase();
+        Ok(pages
+            .into_iter()
+            .filter(|page| page.name.to_lowercase().contains(&name))
+            .collect())
+    }
+
+    /// The page with exactly this name.
+    async fn page(&self, ctx: &Context<'_>, name: String) -> Result<Option<Page>, Error> {
+        let pages = loaders(ctx)
+            .pages_by_app
+            .load_one(self.id)
+            .await
+            .map_err(load_error)?
+            .unwrap_or_default();
+
+        Ok(pages.into_iter().find(|page| page.name == name))
+    }
+}
+
+#[ComplexObject]
+impl Page {
+    async fn app(&self, ctx: &Context<'_>) -> Result<App, Error> {
+        loaders(ctx)
+            .apps
+            .load_one(self.app_id)
+            .await
+            .map_err(load_error)?
+            .ok_or_else(|| not_found(AppScope::App(self.app_id)))
+    }
+
+    /// The page's content, optionally only the entries named in `names`.
+    /// When `published` is true, only entries delivery would serve right now
+    /// are included, with `publishedBody` set to what it serves.
+    async fn content(
+        &self,
+        ctx: &Context<'_>,
+        names: Option<Vec<String>>,
+        #[graphql(default)] published: bool,
+    ) -> Result<Vec<Content>, Error> {
+        let contents = loaders(ctx)
+            .content_by_page
+            .load_one(self.id)
+            .await
+            .map_err(load_error)?
+            .unwrap_or_default();
+
+        let now = Utc::now().naive_utc();
+        Ok(contents
+            .into_iter()
+            .filter(|content| {
+                names
+                    .as_ref()
+                    .is_none_or(|names| names.contains(&content.name))
+            })
+            .filter_map(|content| {
+                if !published {
+                    return Some(content);
+                }
+                let live = content.live_body(now)?.to_string();
+                Some(Content {
+                    published_body: Some(live),
+                    ..content
+                })
+            })
+            .collect())
+    }
+}
+
+#[ComplexObject]
+impl Content {
+    async fn page(&self, ctx: &Context<'_>) -> Result<Page, Error> {
+        loaders(ctx)
+            .pages
+            .load_one(self.page_id)
+            .await
+            .map_err(load_error)?
+            .ok_or_else(|| not_found(AppScope::Page(self.page_id)))
+    }
+
+    /// True when the draft differs from what is currently being delivered.
+    #[graphql(name = "hasUnpublishedChanges")]
+    async fn unpublished_changes(&self) -> bool {
+        self.has_unpublished_changes()
+    }
+}
@@ -0,0 +1,80 @@
+use async_graphql::{Context, Error, Object};
+
+use crate::{
+    graphql::{current_user, database_error, db, load_error, loaders, membership},
+    models::{
+        app::{App, AppSearch},
+        app_member::AppScope,
+        content::Content,
+        page::Page,
+    },
+    repositories::apps::AppRepository,
+    services::{apps::AppService, content::ContentService},
+};
+
+pub struct Query;
+
+/// Everything is limited to the apps the user is a member of, or every app
+/// for admins. Anything else reads as not existing.
+#[Object]
+impl Query {
+    /// The apps you can see, optionally only those whose name contains `name`.
+    async fn apps(&self, ctx: &Context<'_>, name: Option<String>) -> Result<Vec<App>, Error> {
+        let app_service = AppService::new(AppRepository::new(db(ctx)));
+        let params = AppSearch {
+            name: name.unwrap_or_default(),
+        };
+
+        app_service
+            .search(current_user(ctx), &params)
+            .await
+            .map_err(database_error)
+    }
+
+    /// An app, by its id or its name.
+    async fn app(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<i64>,
+        name: Option<String>,
+    ) -> Result<Option<App>, Error> {
+        let id = match (id, name) {
+            (Some(id), None) => id,
+            (None, Some(name)) => {
+                let app_service = AppService::new(AppRepository::new(db(ctx)));
+                match app_service.find_by_name(&name).await {
+                    Ok(app) => app.id,
+                    Err(sqlx::Error::RowNotFound) => return Ok(None),
+                    Err(err) => return Err(database_error(err)),
+                }
+            }
+            _ => return Err(Error::new("Give either the app's id or its name.")),
+        };
+        if membership(ctx, AppScope::App(id)).await?.is_none() {
+            return Ok(None);
+        }
+
+        loaders(ctx).apps.load_one(id).await.map_err(load_error)
+    }
+
+    async fn page(&self, ctx: &Context<'_>, id: i64) -> Result<Option<Page>, Error> {
+        if membership(ctx, AppScope::Page(id)).await?.is_none() {
+            return Ok(None);
+        }
+
+        loaders(ctx).pages.load_one(id).await.map_err(load_error)
+    }
+
+    async fn content(&self, ctx: &Context<'_>, id: i64) -> Result<Option<Content>, Error> {
+        if membership(ctx, AppScope::Content(id)).await?.is_none() {
+            return Ok(None);
+        }
+
+        let content_service = ContentService::new(db(ctx));
+        match content_service.find_by_id(&id).await {
+            Ok(content) => Ok(Some(content)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(err) => Err(database_error(err)),
+        }
+    }
+}
@@ -3,10 +3,16 @@ use std::sync::Arc;
 use sqlx::SqlitePool;
 use tera::Tera;
 
+use crate::{mailer::Mailer, user::oidc::ProviderMetadataCache};
+
 pub mod extractors;
+pub mod graphql;
+pub mod mailer;
+pub mod middleware;
 pub mod models;
 pub mod repositories;
 pub mod routes;
+pub mod scheduler;
 pub mod services;
 pub mod user;
 
@@ -14,4 +20,6 @@ pub mod user;
 pub struct AppState {
     pub db: SqlitePool,
     pub tera: Arc<Tera>,
+    pub mailer: Arc<dyn Mailer>,
+    pub oidc_metadata: ProviderMetadataCache,
 }
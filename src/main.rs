@@ -1,23 +1,33 @@
 use axum::{
     Router,
     http::{HeaderValue, header::CACHE_CONTROL},
+    middleware,
 };
 use sqlx::SqlitePool;
-use std::{env, sync::Arc};
+use std::{env, net::SocketAddr, sync::Arc, time::Duration};
 use tera::Tera;
 use tower_http::services::ServeDir;
 use tower_http::set_header::SetResponseHeaderLayer;
+use tracing_subscriber::EnvFilter;
 use wordford::{
-    AppState,
+    AppState, mailer,
+    middleware::{auth, csrf},
     routes::{self, homepage},
-    user,
+    scheduler, user,
 };
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok(); // load environment variables
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
 
-    let tera = Arc::new(Tera::new("templates/**/*").unwrap());
+    let mut tera = Tera::new("templates/**/*").unwrap();
+    tera.register_function("csrf_token", csrf::csrf_token);
+    let tera = Arc::new(tera);
     let serve_static = Router::new()
         .nest_service("/assets", ServeDir::new("public"))
         .layer(SetResponseHeaderLayer::if_not_present(
@@ -28,19 +38,73 @@ async fn main() {
     let db = SqlitePool::connect(env::var("DATABASE_URL").unwrap().as_str())
         .await
         .unwrap();
-    let state = Arc::new(AppState { db, tera });
+
+    // Apply scheduled publishing in the background
+    let scheduler_interval = env::var("SCHEDULER_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(60);
+    tokio::spawn(scheduler::run(
+        db.clone(),
+        Duration::from_secs(scheduler_interval),
+    ));
+
+    let state = Arc::new(AppState {
+        db,
+        tera,
+        mailer: mailer::from_env(),
+        oidc_metadata: Default::default(),
+    });
+
+    // Everything used to manage apps, pages and content requires a session
+    let admin = Router::new()
+        .merge(routes::content::routes())
+        .merge(routes::pages::routes())
+        .merge(routes::apps::routes())
+        .merge(routes::graphql::routes())
+        .merge(user::routes::signup::routes())
+        .merge(user::routes::security::routes())
+        .merge(user::routes::admin::routes())
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_two_factor,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_user,
+        ));
+
+    // Account settings only need a session, so they stay reachable for people
+    // who still have to set up two-factor authentication. They can't be used
+    // with a personal access token
+    let account = Router::new()
+        .merge(user::routes::two_factor::routes())
+        .merge(user::routes::passkeys::routes())
+        .merge(user::routes::profile::routes())
+        .merge(user::routes::tokens::routes())
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_session,
+        ));
 
     // Initialize the application state and routes
     let app = Router::new()
         .merge(serve_static)
         .merge(user::routes::routes())
         .merge(homepage::routes())
-        .merge(routes::content::routes())
-        .merge(routes::pages::routes())
-        .merge(routes::apps::routes())
+        .merge(routes::pages::public_routes())
+        .merge(routes::api::routes())
+        .merge(admin)
+        .merge(account)
+        .layer(middleware::from_fn(csrf::protect))
         .with_state(state);
 
     // Run the server
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
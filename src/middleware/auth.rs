@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{FromRequestParts, Request, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{ACCEPT, AUTHORIZATION, LOCATION},
+        request::Parts,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    AppState,
+    extractors::current_user::{CurrentSession, CurrentUser},
+    user::two_factor::TwoFactorService,
+};
+
+/// Lets the request through only when it carries a valid session. The
+/// signed in user is stashed in the request extensions so that handlers
+/// extracting `CurrentUser` or `MaybeUser` don't decode the token again.
+pub async fn require_user(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    match CurrentUser::from_request_parts(&mut parts, &state).await {
+        Ok(CurrentUser(user)) => {
+            parts.extensions.insert(user);
+            next.run(Request::from_parts(parts, body)).await
+        }
+        Err(_) => unauthenticated(&parts),
+    }
+}
+
+/// Like `require_user`, but only for people signed in with a session. Account
+/// settings sit behind this, so that a personal access token can't be used
+/// to change a password or mint itself more scopes.
+pub async fn require_session(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    let Ok(CurrentSession(Some(_))) = CurrentSession::from_request_parts(&mut parts, &state).await
+    else {
+        return unauthenticated(&parts);
+    };
+    match CurrentUser::from_request_parts(&mut parts, &state).await {
+        Ok(CurrentUser(user)) => {
+            parts.extensions.insert(user);
+            next.run(Request::from_parts(parts, body)).await
+        }
+        Err(_) => unauthenticated(&parts),
+    }
+}
+
+/// Sends users whose role requires two-factor authentication to set it up
+/// before they can do anything else. Runs after `require_user`.
+pub async fn require_two_factor(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let Ok(CurrentUser(user)) = CurrentUser::from_request_parts(&mut parts, &state).await else {
+        return unauthenticated(&parts);
+    };
+
+    let two_factor_service = TwoFactorService::new(&state.db);
+    match two_factor_service.must_enrol(&user).await {
+        Ok(false) => next.run(Request::from_parts(parts, body)).await,
+        Ok(true) => two_factor_setup_required(&parts),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+fn two_factor_setup_required(parts: &Parts) -> Response {
+    let setup_url = "/me/two-factor";
+
+    if parts.headers.contains_key("HX-Request") {
+        return (
+            StatusCode::FORBIDDEN,
+            [("HX-Redirect", setup_url)],
+            "Set up two-factor authentication to continue.",
+        )
+            .into_response();
+    }
+
+    if wants_json(&parts.headers) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({
+                "error": {
+                    "code": "two_factor_required",
+                    "message": "Set up two-factor authentication to continue.",
+                }
+            })),
+        )
+            .into_response();
+    }
+
+    (StatusCode::SEE_OTHER, [(LOCATION, setup_url)]).into_response()
+}
+
+/// Browsers are sent to the sign in page and brought back afterwards, htmx
+/// and JSON callers, and scripts using a token, get a 401 they can act on.
+fn unauthenticated(parts: &Parts) -> Response {
+    let signin_url = format!(
+        "/signin?return_to={}",
+        encode_query_value(&return_to(parts))
+    );
+
+    if parts.headers.contains_key("HX-Request") {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [("HX-Redirect", signin_url)],
+            "You need to sign in to do that.",
+        )
+            .into_response();
+    }
+
+    if wants_json(&parts.headers) || parts.headers.contains_key(AUTHORIZATION) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": {
+                    "code": "unauthenticated",
+                    "message": "You need to sign in to do that.",
+                }
+            })),
+        )
+            .into_response();
+    }
+
+    (StatusCode::SEE_OTHER, [(LOCATION, signin_url)]).into_response()
+}
+
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|hv| hv.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// htmx requests are usually fired from a page other than the one they hit,
+/// so prefer the page the user was looking at when htmx tells us about it.
+fn return_to(parts: &Parts) -> String {
+    parts
+        .headers
+        .get("HX-Current-URL")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|url| url.split_once("://"))
+        .and_then(|(_, rest)| rest.find('/').map(|i| rest[i..].to_string()))
+        .unwrap_or_else(|| {
+            parts
+                .uri
+                .path_and_query()
+                .map(|pq| pq.to_string())
+                .unwrap_or_else(|| "/".to_string())
+        })
+}
+
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Only same-site paths are honoured as a place to return to after signing
+/// in, anything else falls back to the homepage.
+pub fn safe_return_to(return_to: Option<&str>) -> &str {
+    match return_to {
+        Some(path)
+            if path.starts_with('/')
+                && !path.starts_with("//")
+                && path.bytes().all(|b| b.is_ascii_graphic() && b != b'\\') =>
+        {
+            path
+        }
+        _ => "/",
+    }
+}
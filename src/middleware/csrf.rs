@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{
+        HeaderMap, HeaderValue, Method, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE, COOKIE, SET_COOKIE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use sha2::{Digest, Sha256};
+
+pub const COOKIE_NAME: &str = "csrf_token";
+
+/// htmx sends the token in this header, see `public/csrf.js`.
+pub const HEADER_NAME: &str = "x-csrf-token";
+
+/// Plain HTML forms send it in a hidden field with this name instead.
+pub const FIELD_NAME: &str = "csrf_token";
+
+/// Forms are small, so there's no need to read more of one than this while
+/// looking for the token.
+const FORM_LIMIT: usize = 1024 * 1024;
+
+tokio::task_local! {
+    static TOKEN: String;
+}
+
+/// Guards against cross-site request forgery with a double-submit token.
+/// Every browser gets a random token in a cookie, and pages repeat it
+/// through the `csrf_token()` template function. Requests that change
+/// anything must send it back in the `X-CSRF-Token` header or a
+/// `csrf_token` form field, which another site can't do because it can't
+/// read the cookie.
+///
+/// The delivery API under `/api/` authenticates with keys rather than
+/// cookies, so it is left alone, as are scripts sending a personal access
+/// token. Browsers never add an `Authorization` header on their own, so
+/// another site can't forge one of those.
+pub async fn protect(request: Request, next: Next) -> Response {
+    let cookie_token = token_from_cookie(request.headers());
+    let token = cookie_token
+        .clone()
+        .unwrap_or_else(|| hex::encode(rand::random::<[u8; 32]>()));
+
+    let request = if is_safe(request.method())
+        || request.uri().path().starts_with("/api/")
+        || has_bearer_token(request.headers())
+    {
+        request
+    } else {
+        let Some(expected) = &cookie_token else {
+            return rejected();
+        };
+        match submitted_token(request).await {
+            (Some(submitted), request) if tokens_match(&submitted, expected) => request,
+            _ => return rejected(),
+        }
+    };
+
+    let mut response = TOKEN.scope(token.clone(), next.run(request)).await;
+
+    // only pages can hand the token on, and setting cookies on cacheable
+    // assets could share one token between everyone behind a cache
+    if cookie_token.is_none() && is_html(response.headers()) {
+        let cookie = Cookie::build((COOKIE_NAME, token))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(true)
+            .build();
+        response.headers_mut().append(
+            SET_COOKIE,
+            HeaderValue::from_str(&cookie.to_string()).expect("failed to convert cookie to string"),
+        );
+    }
+
+    response
+}
+
+/// The `csrf_token()` template function. Outside a request, such as when
+/// rendering emails, there is no token and it gives an empty string.
+pub fn csrf_token(_args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let token = TOKEN.try_with(|token| token.clone()).unwrap_or_default();
+
+    Ok(tera::Value::String(token))
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn has_bearer_token(headers: &HeaderMap) -> bool {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|hv| hv.to_str().ok())
+        .is_some_and(|hv| hv.starts_with("Bearer "))
+}
+
+fn is_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|hv| hv.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"))
+}
+
+fn token_from_cookie(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .map(str::trim)
+        .filter_map(|cookie| cookie.split_once('='))
+        .find(|(name, token)| *name == COOKIE_NAME && !token.is_empty())
+        .map(|(_, token)| token)
+        .map(str::to_string)
+}
+
+/// Looks for the token in the header, then in the body of a form post. The
+/// body has to be read for that, so the request is handed back rebuilt.
+async fn submitted_token(request: Request) -> (Option<String>, Request) {
+    if let Some(token) = request
+        .headers()
+        .get(HEADER_NAME)
+        .and_then(|hv| hv.to_str().ok())
+    {
+        return (Some(token.to_string()), request);
+    }
+
+    let is_form = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|hv| hv.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return (None, request);
+    }
+
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = to_bytes(body, FORM_LIMIT).await else {
+        return (None, Request::from_parts(parts, Body::empty()));
+    };
+    // the token is hex, so it never needs decoding
+    let token = bytes
+        .split(|b| *b == b'&')
+        .find_map(|pair| pair.strip_prefix(format!("{}=", FIELD_NAME).as_bytes()))
+        .and_then(|token| std::str::from_utf8(token).ok())
+        .map(str::to_string);
+
+    (token, Request::from_parts(parts, Body::from(bytes)))
+}
+
+// comparing hashes keeps the time taken from hinting at how much of the
+// token was right
+fn tokens_match(submitted: &str, expected: &str) -> bool {
+    Sha256::digest(submitted.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+fn rejected() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        "This page has expired. Reload it and try again.",
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        http::request::Builder,
+        middleware,
+        response::Html,
+        routing::{get, post},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const TOKEN_VALUE: &str = "0123456789abcdef";
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|| async { Html("<p>Hello</p>") }).post(|body: String| async { body }),
+            )
+            .route("/plain", get(|| async { "Hello" }))
+            .route(
+                "/token",
+                get(|| async { csrf_token(&HashMap::new()).unwrap().to_string() }),
+            )
+            .route("/api/v1/pages", post(|| async { "Created" }))
+            .layer(middleware::from_fn(protect))
+    }
+
+    async fn send(request: Builder, body: &'static str) -> Response {
+        app()
+            .oneshot(request.body(Body::from(body)).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn change(uri: &str) -> Builder {
+        Request::builder().method(Method::POST).uri(uri)
+    }
+
+    fn with_cookie(request: Builder) -> Builder {
+        request.header(
+            COOKIE,
+            format!("theme=dark; {}={}", COOKIE_NAME, TOKEN_VALUE),
+        )
+    }
+
+    async fn text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pages_hand_out_a_token_once() {
+        let response = send(Request::builder().uri("/"), "").await;
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with(&format!("{}=", COOKIE_NAME)));
+        assert!(cookie.contains("HttpOnly"));
+
+        let response = send(with_cookie(Request::builder().uri("/")), "").await;
+        assert!(!response.headers().contains_key(SET_COOKIE));
+        let response = send(Request::builder().uri("/plain"), "").await;
+        assert!(!response.headers().contains_key(SET_COOKIE));
+    }
+
+    #[tokio::test]
+    async fn templates_see_the_cookie_token() {
+        let response = send(with_cookie(Request::builder().uri("/token")), "").await;
+        assert_eq!(text(response).await, format!("\"{}\"", TOKEN_VALUE));
+
+        let outside = csrf_token(&HashMap::new()).unwrap();
+        assert_eq!(outside, tera::Value::String(String::new()));
+    }
+
+    #[tokio::test]
+    async fn rejects_changes_without_a_matching_token() {
+        let response = send(change("/").header(HEADER_NAME, TOKEN_VALUE), "").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = send(with_cookie(change("/")), "").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = send(
+            with_cookie(change("/")).header(HEADER_NAME, "fedcba9876543210"),
+            "",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = send(
+            with_cookie(change("/")).header(CONTENT_TYPE, "application/x-www-form-urlencoded"),
+            "name=home&csrf_token=fedcba9876543210",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn accepts_the_token_in_a_header() {
+        let response = send(
+            with_cookie(change("/")).header(HEADER_NAME, TOKEN_VALUE),
+            "",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn accepts_the_token_in_a_form_and_keeps_the_body() {
+        let form = "name=home&csrf_token=0123456789abcdef";
+        let response = send(
+            with_cookie(change("/")).header(CONTENT_TYPE, "application/x-www-form-urlencoded"),
+            form,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text(response).await, form);
+    }
+
+    #[tokio::test]
+    async fn leaves_the_api_alone() {
+        let response = send(change("/api/v1/pages"), "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn leaves_personal_access_tokens_alone() {
+        let response = send(
+            change("/").header(AUTHORIZATION, "Bearer wfp_0123456789"),
+            "",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(change("/").header(AUTHORIZATION, "Basic d2Y6d2Y="), "").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
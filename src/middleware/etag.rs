@@ -0,0 +1,142 @@
+use axum::{
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{
+        HeaderValue, Method, StatusCode,
+        header::{ETAG, IF_NONE_MATCH},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+/// Tags successful GET responses with an `ETag` derived from their body, and
+/// answers `304 Not Modified` when the client already has that version. The
+/// response is still built, but nothing is sent when it hasn't changed.
+pub async fn etag(request: Request, next: Next) -> Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+
+    let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let digest = Sha256::digest(&bytes);
+    let tag = format!("\"{}\"", hex::encode(&digest[..16]));
+    let tag = HeaderValue::from_str(&tag).expect("a hex digest is a valid header value");
+
+    if if_none_match.is_some_and(|value| matches(&value, &tag)) {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, tag)]).into_response();
+    }
+
+    parts.headers.insert(ETAG, tag);
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Whether `If-None-Match` names `tag`, comparing weakly as RFC 9110 asks.
+fn matches(if_none_match: &HeaderValue, tag: &HeaderValue) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+    let tag = tag.to_str().unwrap_or_default();
+
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, middleware, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const TAG: &str = "\"0123456789abcdef\"";
+
+    fn header(value: &str) -> HeaderValue {
+        HeaderValue::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn matches_the_same_tag() {
+        assert!(matches(&header(TAG), &header(TAG)));
+        assert!(!matches(&header("\"fedcba9876543210\""), &header(TAG)));
+        assert!(!matches(&header("0123456789abcdef"), &header(TAG)));
+    }
+
+    #[test]
+    fn matches_weakly() {
+        assert!(matches(&header(&format!("W/{TAG}")), &header(TAG)));
+    }
+
+    #[test]
+    fn matches_any_tag_in_a_list() {
+        let list = format!("\"fedcba9876543210\", W/{TAG} ,\"00\"");
+
+        assert!(matches(&header(&list), &header(TAG)));
+        assert!(!matches(&header("\"00\", \"11\""), &header(TAG)));
+    }
+
+    #[test]
+    fn matches_a_wildcard() {
+        assert!(matches(&header("*"), &header(TAG)));
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(|| async { "Hello" }).post(|| async { "Hello" }))
+            .route("/missing", get(|| async { StatusCode::NOT_FOUND }))
+            .layer(middleware::from_fn(etag))
+    }
+
+    async fn send(method: Method, uri: &str, if_none_match: Option<&HeaderValue>) -> Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(value) = if_none_match {
+            request = request.header(IF_NONE_MATCH, value);
+        }
+
+        app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_not_modified_for_the_current_tag() {
+        let response = send(Method::GET, "/", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let tag = response.headers()[ETAG].clone();
+
+        let response = send(Method::GET, "/", Some(&tag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[ETAG], tag);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+
+        let response = send(Method::GET, "/", Some(&header(TAG))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "Hello");
+    }
+
+    #[tokio::test]
+    async fn leaves_other_requests_and_responses_alone() {
+        let response = send(Method::POST, "/", Some(&header("*"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(ETAG));
+
+        let response = send(Method::GET, "/missing", Some(&header("*"))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(!response.headers().contains_key(ETAG));
+    }
+}
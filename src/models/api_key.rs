@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+/// Delivery keys can only read published content, management keys can also
+/// change an app's pages and content.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    Read,
+    Write,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::Write => "write",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "read" => Some(ApiKeyScope::Read),
+            "write" => Some(ApiKeyScope::Write),
+            _ => None,
+        }
+    }
+
+    /// Write keys are a superset of read keys.
+    pub fn allows(&self, required: ApiKeyScope) -> bool {
+        *self == ApiKeyScope::Write || required == ApiKeyScope::Read
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: i64,
+    pub app_id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scope: ApiKeyScope,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NewApiKeyRequest {
+    pub name: String,
+    pub scope: ApiKeyScope,
+}
+
+/// Returned only when a key is created; `token` is never stored or shown again.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatedApiKey {
+    pub key: ApiKey,
+    pub token: String,
+}
@@ -1,8 +1,11 @@
+use async_graphql::SimpleObject;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use crate::models::page::Page;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct App {
     pub id: i64,
     pub name: String,
@@ -12,7 +15,7 @@ pub struct App {
     pub updated_at: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct AppWithPages {
     pub app: App,
     pub pages: Vec<Page>,
@@ -29,3 +32,10 @@ pub struct CreateAppForm {
     pub description: String,
     pub url: String,
 }
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateAppRequest {
+    pub name: String,
+    pub description: String,
+    pub url: String,
+}
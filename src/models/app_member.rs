@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+use crate::user::role::Role;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AppMember {
+    pub app_id: i64,
+    pub user_id: i64,
+    pub email: String,
+    pub given_name: String,
+    pub family_name: String,
+    pub avatar_url: String,
+    pub role: Role,
+    pub created_at: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct NewMemberRequest {
+    pub email: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateMemberRequest {
+    pub role: Role,
+}
+
+/// What a membership check is about. Pages and content resolve to the app
+/// they belong to.
+#[derive(Debug, Clone, Copy)]
+pub enum AppScope {
+    App(i64),
+    Page(i64),
+    Content(i64),
+}
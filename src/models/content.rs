@@ -1,21 +1,59 @@
 use crate::models::page::FullPage;
+use async_graphql::SimpleObject;
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FindContentByPageIdParams {
     pub page_id: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct Content {
     pub id: i64,
     pub page_id: i64,
     pub name: String,
     pub body: String,
+    pub published_body: Option<String>,
+    pub published_at: Option<String>,
+    /// What goes live at `publish_at`: the body as it was when the publish
+    /// was scheduled, so later edits need publishing again.
+    pub scheduled_body: Option<String>,
+    pub publish_at: Option<String>,
+    pub unpublish_at: Option<String>,
     pub created_at: String,
     pub updated_at: String,
 }
 
+impl Content {
+    /// True when the draft differs from what is currently being delivered.
+    pub fn has_unpublished_changes(&self) -> bool {
+        self.published_body.as_deref() != Some(self.body.as_str())
+    }
+
+    /// What delivery serves for this entry at `now`, in UTC, if anything.
+    /// Publishing windows are honoured here as well as by the scheduler, so
+    /// content flips on time even between scheduler runs. A publish that has
+    /// come due serves the body as it was scheduled, never the live draft.
+    pub fn live_body(&self, now: NaiveDateTime) -> Option<&str> {
+        let reached = |at: &Option<String>| {
+            at.as_deref()
+                .and_then(|at| NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M:%S%.f").ok())
+                .is_some_and(|at| at <= now)
+        };
+
+        if reached(&self.unpublish_at) {
+            return None;
+        }
+        match &self.scheduled_body {
+            Some(body) if reached(&self.publish_at) => Some(body),
+            _ => self.published_body.as_deref(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FullContent {
     pub content: Content,
@@ -27,6 +65,12 @@ pub struct NewContentRequest {
     pub page_id: i64,
     pub name: String,
     pub body: String,
+    #[serde(default)]
+    pub publish: bool,
+    #[serde(default)]
+    pub publish_at: Option<String>,
+    #[serde(default)]
+    pub unpublish_at: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,4 +78,143 @@ pub struct UpdateContentRequest {
     pub content_id: i64,
     pub name: String,
     pub body: String,
+    #[serde(default)]
+    pub publish: bool,
+    #[serde(default)]
+    pub publish_at: Option<String>,
+    #[serde(default)]
+    pub unpublish_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Publish,
+    Unpublish,
+}
+
+impl VersionAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VersionAction::Create => "create",
+            VersionAction::Update => "update",
+            VersionAction::Delete => "delete",
+            VersionAction::Restore => "restore",
+            VersionAction::Publish => "publish",
+            VersionAction::Unpublish => "unpublish",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContentVersion {
+    pub id: i64,
+    pub content_id: i64,
+    pub page_id: i64,
+    pub name: String,
+    pub body: String,
+    pub action: String,
+    pub user_id: Option<i64>,
+    pub author: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VersionDiffParams {
+    pub from: i64,
+    pub to: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DiffLine {
+    pub kind: String,
+    pub left_number: Option<usize>,
+    pub left: Option<String>,
+    pub right_number: Option<usize>,
+    pub right: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VersionDiff {
+    pub from: ContentVersion,
+    pub to: ContentVersion,
+    pub lines: Vec<DiffLine>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(published: Option<&str>, scheduled: Option<&str>) -> Content {
+        Content {
+            id: 1,
+            page_id: 1,
+            name: "title".to_string(),
+            body: "Draft".to_string(),
+            published_body: published.map(str::to_string),
+            published_at: None,
+            scheduled_body: scheduled.map(str::to_string),
+            publish_at: None,
+            unpublish_at: None,
+            created_at: "2025-01-01 00:00:00".to_string(),
+            updated_at: "2025-01-01 00:00:00".to_string(),
+        }
+    }
+
+    fn at(timestamp: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn serves_the_published_body() {
+        let content = content(Some("Live"), None);
+
+        assert_eq!(content.live_body(at("2025-06-01 12:00:00")), Some("Live"));
+    }
+
+    #[test]
+    fn never_serves_the_draft() {
+        let content = content(None, None);
+
+        assert_eq!(content.live_body(at("2025-06-01 12:00:00")), None);
+    }
+
+    #[test]
+    fn serves_the_scheduled_body_once_due() {
+        let content = Content {
+            publish_at: Some("2025-06-01 12:00:00".to_string()),
+            ..content(Some("Old"), Some("Scheduled"))
+        };
+
+        assert_eq!(content.live_body(at("2025-06-01 11:59:59")), Some("Old"));
+        assert_eq!(
+            content.live_body(at("2025-06-01 12:00:00")),
+            Some("Scheduled")
+        );
+
+        let first_publish = Content {
+            published_body: None,
+            ..content
+        };
+        assert_eq!(first_publish.live_body(at("2025-06-01 11:59:59")), None);
+    }
+
+    #[test]
+    fn serves_nothing_once_unpublished() {
+        let content = Content {
+            publish_at: Some("2025-06-01 12:00:00".to_string()),
+            unpublish_at: Some("2025-07-01 12:00:00".to_string()),
+            ..content(Some("Old"), Some("Scheduled"))
+        };
+
+        assert_eq!(
+            content.live_body(at("2025-07-01 11:59:59")),
+            Some("Scheduled")
+        );
+        assert_eq!(content.live_body(at("2025-07-01 12:00:00")), None);
+    }
 }
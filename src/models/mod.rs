@@ -1,3 +1,5 @@
+pub mod api_key;
 pub mod app;
+pub mod app_member;
 pub mod content;
 pub mod page;
@@ -1,8 +1,12 @@
 use crate::models::{app::App, content::Content};
+use async_graphql::SimpleObject;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Deserialize, Serialize, Debug)]
+pub use wordford_models::{DeliveredPage, DeliveredPages, PageContent};
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct Page {
     pub id: i64,
     pub app_id: i64,
@@ -11,7 +15,7 @@ pub struct Page {
     pub updated_at: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct FullPage {
     pub app: App,
     pub page: Page,
@@ -24,4 +28,26 @@ pub struct NewPageRequest {
     pub name: String,
 }
 
-pub type PageContent = HashMap<String, String>;
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UpdatePageRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PageContentParams {
+    pub preview: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PreviewClaims {
+    pub page_id: i64,
+    pub exp: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageBatchParams {
+    /// Comma separated page names, at most 50.
+    #[serde(default)]
+    pub names: String,
+}
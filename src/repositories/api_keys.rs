@@ -0,0 +1,131 @@
+use sqlx::SqlitePool;
+
+use crate::models::api_key::{ApiKey, ApiKeyScope};
+
+pub struct ApiKeyRepository {
+    db: SqlitePool,
+}
+
+impl ApiKeyRepository {
+    pub fn new(db: &SqlitePool) -> Self {
+        ApiKeyRepository { db: db.clone() }
+    }
+
+    pub async fn find_all_by_app_id(&self, app_id: &i64) -> Result<Vec<ApiKey>, sqlx::Error> {
+        let keys = sqlx::query!(
+            r#"
+            SELECT id, app_id, name, prefix, scope, last_used_at, revoked_at, created_at
+            FROM api_keys WHERE app_id = ?
+            ORDER BY revoked_at IS NOT NULL, id DESC
+            "#,
+            app_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(keys
+            .into_iter()
+            .map(|k| ApiKey {
+                id: k.id.expect("id should not be null"),
+                app_id: k.app_id,
+                name: k.name,
+                prefix: k.prefix,
+                scope: ApiKeyScope::parse(&k.scope).unwrap_or(ApiKeyScope::Read),
+                last_used_at: k.last_used_at.map(|t| t.to_string()),
+                revoked_at: k.revoked_at.map(|t| t.to_string()),
+                created_at: k.created_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// Looks up a key that has not been revoked by the hash of its token.
+    pub async fn find_active_by_hash(&self, key_hash: &str) -> Result<ApiKey, sqlx::Error> {
+        let key = sqlx::query!(
+            r#"
+            SELECT id, app_id, name, prefix, scope, last_used_at, revoked_at, created_at
+            FROM api_keys WHERE key_hash = ? AND revoked_at IS NULL
+            "#,
+            key_hash
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(ApiKey {
+            id: key.id.expect("id should not be null"),
+            app_id: key.app_id,
+            name: key.name,
+            prefix: key.prefix,
+            scope: ApiKeyScope::parse(&key.scope).unwrap_or(ApiKeyScope::Read),
+            last_used_at: key.last_used_at.map(|t| t.to_string()),
+            revoked_at: key.revoked_at.map(|t| t.to_string()),
+            created_at: key.created_at.to_string(),
+        })
+    }
+
+    pub async fn create_api_key(
+        &self,
+        app_id: &i64,
+        name: &str,
+        prefix: &str,
+        key_hash: &str,
+        scope: ApiKeyScope,
+    ) -> Result<ApiKey, sqlx::Error> {
+        let scope = scope.as_str();
+        let key = sqlx::query!(
+            r#"
+            INSERT INTO api_keys (app_id, name, prefix, key_hash, scope) VALUES (?, ?, ?, ?, ?)
+            RETURNING id, app_id, name, prefix, scope, last_used_at, revoked_at, created_at
+            "#,
+            app_id,
+            name,
+            prefix,
+            key_hash,
+            scope
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(ApiKey {
+            id: key.id.expect("id should not be null"),
+            app_id: key.app_id,
+            name: key.name,
+            prefix: key.prefix,
+            scope: ApiKeyScope::parse(&key.scope).unwrap_or(ApiKeyScope::Read),
+            last_used_at: key.last_used_at.map(|t| t.to_string()),
+            revoked_at: key.revoked_at.map(|t| t.to_string()),
+            created_at: key.created_at.to_string(),
+        })
+    }
+
+    pub async fn touch(&self, id: &i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE api_keys SET last_used_at = CURRENT_TIMESTAMP WHERE id = ?
+            "#,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke(&self, app_id: &i64, id: &i64) -> Result<(), sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE api_keys SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = ? AND app_id = ? AND revoked_at IS NULL
+            "#,
+            id,
+            app_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use sqlx::SqlitePool;
+
+use crate::{
+    models::app_member::{AppMember, AppScope},
+    user::role::Role,
+};
+
+pub struct AppMemberRepository {
+    db: SqlitePool,
+}
+
+impl AppMemberRepository {
+    pub fn new(db: &SqlitePool) -> Self {
+        AppMemberRepository { db: db.clone() }
+    }
+
+    pub async fn find_all_by_app_id(&self, app_id: &i64) -> Result<Vec<AppMember>, sqlx::Error> {
+        let members = sqlx::query!(
+            r#"
+            SELECT m.app_id, m.user_id, m.role, m.created_at,
+                   u.email, u.given_name, u.family_name, u.avatar_url
+            FROM app_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.app_id = ?
+            ORDER BY m.role DESC, u.email
+            "#,
+            app_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(members
+            .into_iter()
+            .map(|m| AppMember {
+                app_id: m.app_id,
+                user_id: m.user_id,
+                email: m.email,
+                given_name: m.given_name,
+                family_name: m.family_name,
+                avatar_url: m.avatar_url,
+                role: Role::from(m.role),
+                created_at: m.created_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// The role a user holds in the app that `scope` belongs to. Fails with
+    /// `RowNotFound` when they aren't a member of it. Content is also looked
+    /// up through its history so deleted content can still be restored.
+    pub async fn find_role(&self, scope: AppScope, user_id: &i64) -> Result<Role, sqlx::Error> {
+        let role = match scope {
+            AppScope::App(app_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT role FROM app_members WHERE app_id = ? AND user_id = ?
+                    "#,
+                    app_id,
+                    user_id
+                )
+                .fetch_one(&self.db)
+                .await?
+            }
+            AppScope::Page(page_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT m.role FROM app_members m
+                    JOIN pages p ON p.app_id = m.app_id
+                    WHERE p.id = ? AND m.user_id = ?
+                    "#,
+                    page_id,
+                    user_id
+                )
+                .fetch_one(&self.db)
+                .await?
+            }
+            AppScope::Content(content_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT m.role FROM app_members m
+                    JOIN pages p ON p.app_id = m.app_id
+                    WHERE m.user_id = ?1 AND p.id IN (
+                        SELECT page_id FROM content WHERE id = ?2
+                        UNION SELECT page_id FROM content_versions WHERE content_id = ?2
+                    )
+                    "#,
+                    user_id,
+                    content_id
+                )
+                .fetch_one(&self.db)
+                .await?
+            }
+        };
+
+        Ok(Role::from(role))
+    }
+
+    /// The app that `scope` belongs to. Fails with `RowNotFound` when there's
+    /// no such app, page or content, looking through content history the same
+    /// way as `find_role`.
+    pub async fn find_app_id(&self, scope: AppScope) -> Result<i64, sqlx::Error> {
+        match scope {
+            AppScope::App(app_id) => {
+                sqlx::query_scalar!(r#"SELECT id AS "id!" FROM apps WHERE id = ?"#, app_id)
+                    .fetch_one(&self.db)
+                    .await
+            }
+            AppScope::Page(page_id) => {
+                sqlx::query_scalar!("SELECT app_id FROM pages WHERE id = ?", page_id)
+                    .fetch_one(&self.db)
+                    .await
+            }
+            AppScope::Content(content_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT app_id FROM pages WHERE id IN (
+                        SELECT page_id FROM content WHERE id = ?1
+                        UNION SELECT page_id FROM content_versions WHERE content_id = ?1
+                    )
+                    "#,
+                    content_id
+                )
+                .fetch_one(&self.db)
+                .await
+            }
+        }
+    }
+
+    /// Adds the user with the given email to an app. Fails with
+    /// `RowNotFound` when nobody has signed up with that email.
+    pub async fn add_member_by_email(
+        &self,
+        app_id: &i64,
+        email: &str,
+        role: Role,
+    ) -> Result<(), sqlx::Error> {
+        let role = role.as_i64();
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO app_members (app_id, user_id, role)
+            SELECT ?, id, ? FROM users WHERE email = ?
+            "#,
+            app_id,
+            role,
+            email
+        )
+        .execute(&self.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_role(
+        &self,
+        app_id: &i64,
+        user_id: &i64,
+        role: Role,
+    ) -> Result<(), sqlx::Error> {
+        let role = role.as_i64();
+        let result = sqlx::query!(
+            r#"
+            UPDATE app_members SET role = ? WHERE app_id = ? AND user_id = ?
+            "#,
+            role,
+            app_id,
+            user_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove_member(&self, app_id: &i64, user_id: &i64) -> Result<(), sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM app_members WHERE app_id = ? AND user_id = ?
+            "#,
+            app_id,
+            user_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+}
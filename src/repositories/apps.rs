@@ -1,6 +1,9 @@
-use crate::models::{
-    app::{App, AppWithPages, CreateAppForm},
-    page::Page,
+use crate::{
+    models::{
+        app::{App, AppWithPages, CreateAppForm, UpdateAppRequest},
+        page::Page,
+    },
+    user::role::Role,
 };
 use sqlx::SqlitePool;
 
@@ -13,12 +16,21 @@ impl AppRepository {
         AppRepository { db: db.clone() }
     }
 
-    pub async fn find_by_id(&self, id: &i64) -> Result<AppWithPages, sqlx::Error> {
+    /// Finds an app by id. When `member_id` is given, only apps that user is
+    /// a member of are found.
+    pub async fn find_by_id(
+        &self,
+        id: &i64,
+        member_id: Option<i64>,
+    ) -> Result<AppWithPages, sqlx::Error> {
         let app = sqlx::query!(
             r#"
-            SELECT * FROM apps WHERE id = ?
+            SELECT * FROM apps
+            WHERE id = ?
+              AND (?2 IS NULL OR id IN (SELECT app_id FROM app_members WHERE user_id = ?2))
             "#,
-            id
+            id,
+            member_id
         )
         .fetch_one(&self.db)
         .await?;
@@ -54,11 +66,68 @@ impl AppRepository {
         })
     }
 
-    pub async fn search(&self, search_str: &str) -> Result<Vec<App>, sqlx::Error> {
+    pub async fn find_by_name(&self, name: &str) -> Result<App, sqlx::Error> {
+        let app = sqlx::query!(
+            r#"
+            SELECT * FROM apps WHERE name = ?
+            "#,
+            name
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(App {
+            id: app.id.expect("id should not be null"),
+            name: app.name,
+            description: app.description.unwrap_or("".to_string()),
+            url: app.url.unwrap_or("".to_string()),
+            created_at: app.created_at.to_string(),
+            updated_at: app.updated_at.to_string(),
+        })
+    }
+
+    /// Finds several apps by id in one query, for batch loading.
+    pub async fn find_all_by_ids(&self, ids: &[i64]) -> Result<Vec<App>, sqlx::Error> {
+        let ids = serde_json::to_string(ids).expect("ids should serialize");
+        let apps = sqlx::query!(
+            r#"
+            SELECT id AS "id!", name, description, url, created_at, updated_at FROM apps
+            WHERE id IN (SELECT value FROM json_each(?))
+            "#,
+            ids
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(apps
+            .into_iter()
+            .map(|app| App {
+                id: app.id,
+                name: app.name,
+                description: app.description.unwrap_or("".to_string()),
+                url: app.url.unwrap_or("".to_string()),
+                created_at: app.created_at.to_string(),
+                updated_at: app.updated_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// Searches apps by name. When `member_id` is given, only apps that user
+    /// is a member of are returned.
+    pub async fn search(
+        &self,
+        search_str: &str,
+        member_id: Option<i64>,
+    ) -> Result<Vec<App>, sqlx::Error> {
         let pattern = format!("%{}%", search_str);
         let apps = sqlx::query!(
-            r#"SELECT * FROM apps WHERE LOWER(name) LIKE LOWER(?)"#,
-            pattern
+            r#"
+            SELECT * FROM apps
+            WHERE LOWER(name) LIKE LOWER(?)
+              AND (?2 IS NULL OR id IN (SELECT app_id FROM app_members WHERE user_id = ?2))
+            "#,
+            pattern,
+            member_id
         )
         .fetch_all(&self.db)
         .await?;
@@ -98,7 +167,15 @@ impl AppRepository {
             .collect())
     }
 
-    pub async fn create_app(&self, request: CreateAppForm) -> Result<App, sqlx::Error> {
+    /// Creates an app with `owner_id` as its first member, holding `owner_role`.
+    pub async fn create_app(
+        &self,
+        request: CreateAppForm,
+        owner_id: &i64,
+        owner_role: Role,
+    ) -> Result<App, sqlx::Error> {
+        let mut tx = self.db.begin().await?;
+
         let app = sqlx::query!(
             r#"
             INSERT INTO apps (name, description, url) VALUES (?, ?, ?)
@@ -108,9 +185,23 @@ impl AppRepository {
             request.description,
             request.url
         )
-        .fetch_one(&self.db)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let owner_role = owner_role.as_i64();
+        sqlx::query!(
+            r#"
+            INSERT INTO app_members (app_id, user_id, role) VALUES (?, ?, ?)
+            "#,
+            app.id,
+            owner_id,
+            owner_role
+        )
+        .execute(&mut *tx)
         .await?;
 
+        tx.commit().await?;
+
         Ok(App {
             id: app.id,
             name: request.name,
@@ -121,7 +212,36 @@ impl AppRepository {
         })
     }
 
-    pub async fn delete_app(&self, id: &str) -> Result<(), sqlx::Error> {
+    pub async fn update_app(
+        &self,
+        id: &i64,
+        request: UpdateAppRequest,
+    ) -> Result<App, sqlx::Error> {
+        let app = sqlx::query!(
+            r#"
+            UPDATE apps SET name = ?, description = ?, url = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            RETURNING id AS "id!", name, description, url, created_at, updated_at
+            "#,
+            request.name,
+            request.description,
+            request.url,
+            id
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(App {
+            id: app.id,
+            name: app.name,
+            description: app.description.unwrap_or("".to_string()),
+            url: app.url.unwrap_or("".to_string()),
+            created_at: app.created_at.to_string(),
+            updated_at: app.updated_at.to_string(),
+        })
+    }
+
+    pub async fn delete_app(&self, id: &i64) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
             DELETE FROM apps WHERE id = ?
@@ -1,6 +1,6 @@
-use sqlx::SqlitePool;
+use sqlx::{SqliteConnection, SqlitePool};
 
-use crate::models::content::{Content, NewContentRequest, UpdateContentRequest};
+use crate::models::content::{Content, ContentVersion, NewContentRequest, UpdateContentRequest};
 
 pub struct ContentRepository {
     db: SqlitePool,
@@ -26,6 +26,11 @@ impl ContentRepository {
             page_id: content.page_id,
             name: content.name,
             body: content.body,
+            published_body: content.published_body,
+            published_at: content.published_at.map(|p| p.to_string()),
+            scheduled_body: content.scheduled_body,
+            publish_at: content.publish_at.map(|p| p.to_string()),
+            unpublish_at: content.unpublish_at.map(|p| p.to_string()),
             created_at: content.created_at.to_string(),
             updated_at: content.updated_at.to_string(),
         })
@@ -48,26 +53,83 @@ impl ContentRepository {
                 page_id: c.page_id,
                 name: c.name,
                 body: c.body,
+                published_body: c.published_body,
+                published_at: c.published_at.map(|p| p.to_string()),
+                scheduled_body: c.scheduled_body,
+                publish_at: c.publish_at.map(|p| p.to_string()),
+                unpublish_at: c.unpublish_at.map(|p| p.to_string()),
                 created_at: c.created_at.to_string(),
                 updated_at: c.updated_at.to_string(),
             })
             .collect())
     }
 
+    /// Finds the content of several pages in one query, for batch loading.
+    pub async fn find_all_by_page_ids(
+        &self,
+        page_ids: &[i64],
+    ) -> Result<Vec<Content>, sqlx::Error> {
+        let page_ids = serde_json::to_string(page_ids).expect("ids should serialize");
+        let contents = sqlx::query!(
+            r#"
+            SELECT id AS "id!", page_id, name, body, published_body, published_at, scheduled_body,
+                publish_at, unpublish_at, created_at, updated_at
+            FROM content WHERE page_id IN (SELECT value FROM json_each(?))
+            ORDER BY id
+            "#,
+            page_ids
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(contents
+            .into_iter()
+            .map(|c| Content {
+                id: c.id,
+                page_id: c.page_id,
+                name: c.name,
+                body: c.body,
+                published_body: c.published_body,
+                published_at: c.published_at.map(|p| p.to_string()),
+                scheduled_body: c.scheduled_body,
+                publish_at: c.publish_at.map(|p| p.to_string()),
+                unpublish_at: c.unpublish_at.map(|p| p.to_string()),
+                created_at: c.created_at.to_string(),
+                updated_at: c.updated_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// Saves the draft and its schedule. With `snapshot`, the new body is
+    /// what a scheduled publish will ship; without it, an edit leaves the
+    /// snapshot taken when the publish was scheduled alone.
     pub async fn update_content(
         &self,
+        conn: &mut SqliteConnection,
         request: UpdateContentRequest,
+        snapshot: bool,
     ) -> Result<Content, sqlx::Error> {
         let result = sqlx::query!(
             r#"
-            UPDATE content SET name = ?, body = ? WHERE id = ?
-            RETURNING id, page_id, name, body, created_at, updated_at
+            UPDATE content
+            SET name = ?1, body = ?2, publish_at = datetime(?3), unpublish_at = datetime(?4),
+                scheduled_body = CASE
+                    WHEN NOT ?5 THEN scheduled_body
+                    WHEN datetime(?3) IS NULL THEN NULL
+                    ELSE ?2
+                END
+            WHERE id = ?6
+            RETURNING id, page_id, name, body, published_body, published_at, scheduled_body, publish_at,
+                unpublish_at, created_at, updated_at
             "#,
             request.name,
             request.body,
+            request.publish_at,
+            request.unpublish_at,
+            snapshot,
             request.content_id
         )
-        .fetch_one(&self.db)
+        .fetch_one(conn)
         .await?;
 
         Ok(Content {
@@ -75,6 +137,11 @@ impl ContentRepository {
             page_id: result.page_id,
             name: result.name,
             body: result.body,
+            published_body: result.published_body,
+            published_at: result.published_at.map(|p| p.to_string()),
+            scheduled_body: result.scheduled_body,
+            publish_at: result.publish_at.map(|p| p.to_string()),
+            unpublish_at: result.unpublish_at.map(|p| p.to_string()),
             created_at: result.created_at.to_string(),
             updated_at: result.updated_at.to_string(),
         })
@@ -82,18 +149,26 @@ impl ContentRepository {
 
     pub async fn create_content(
         &self,
+        conn: &mut SqliteConnection,
         request: &NewContentRequest,
     ) -> Result<Content, sqlx::Error> {
         let result = sqlx::query!(
             r#"
-            INSERT INTO content (page_id, name, body) VALUES (?, ?, ?)
-            RETURNING id, page_id, name, body, created_at, updated_at
+            INSERT INTO content (page_id, name, body, scheduled_body, publish_at, unpublish_at)
+            VALUES (
+                ?1, ?2, ?3, CASE WHEN datetime(?4) IS NULL THEN NULL ELSE ?3 END,
+                datetime(?4), datetime(?5)
+            )
+            RETURNING id, page_id, name, body, published_body, published_at, scheduled_body, publish_at,
+                unpublish_at, created_at, updated_at
             "#,
             request.page_id,
             request.name,
-            request.body
+            request.body,
+            request.publish_at,
+            request.unpublish_at
         )
-        .fetch_one(&self.db)
+        .fetch_one(conn)
         .await?;
 
         Ok(Content {
@@ -101,19 +176,209 @@ impl ContentRepository {
             page_id: result.page_id,
             name: result.name,
             body: result.body,
+            published_body: result.published_body,
+            published_at: result.published_at.map(|p| p.to_string()),
+            scheduled_body: result.scheduled_body,
+            publish_at: result.publish_at.map(|p| p.to_string()),
+            unpublish_at: result.unpublish_at.map(|p| p.to_string()),
+            created_at: result.created_at.to_string(),
+            updated_at: result.updated_at.to_string(),
+        })
+    }
+
+    /// Writes a version's name and body back onto its content row, recreating
+    /// the row under its original id if it has since been deleted.
+    pub async fn restore_content(
+        &self,
+        conn: &mut SqliteConnection,
+        version: &ContentVersion,
+    ) -> Result<Content, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO content (id, page_id, name, body) VALUES (?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET name = excluded.name, body = excluded.body
+            RETURNING id, page_id, name, body, published_body, published_at, scheduled_body, publish_at,
+                unpublish_at, created_at, updated_at
+            "#,
+            version.content_id,
+            version.page_id,
+            version.name,
+            version.body
+        )
+        .fetch_one(conn)
+        .await?;
+
+        Ok(Content {
+            id: result.id,
+            page_id: result.page_id,
+            name: result.name,
+            body: result.body,
+            published_body: result.published_body,
+            published_at: result.published_at.map(|p| p.to_string()),
+            scheduled_body: result.scheduled_body,
+            publish_at: result.publish_at.map(|p| p.to_string()),
+            unpublish_at: result.unpublish_at.map(|p| p.to_string()),
+            created_at: result.created_at.to_string(),
+            updated_at: result.updated_at.to_string(),
+        })
+    }
+
+    pub async fn publish_content(
+        &self,
+        conn: &mut SqliteConnection,
+        id: &i64,
+    ) -> Result<Content, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE content
+            SET published_body = body, published_at = CURRENT_TIMESTAMP, publish_at = NULL,
+                scheduled_body = NULL
+            WHERE id = ?
+            RETURNING id, page_id, name, body, published_body, published_at, scheduled_body, publish_at,
+                unpublish_at, created_at, updated_at
+            "#,
+            id
+        )
+        .fetch_one(conn)
+        .await?;
+
+        Ok(Content {
+            id: result.id,
+            page_id: result.page_id,
+            name: result.name,
+            body: result.body,
+            published_body: Some(result.published_body),
+            published_at: result.published_at.map(|p| p.to_string()),
+            scheduled_body: result.scheduled_body,
+            publish_at: result.publish_at.map(|p| p.to_string()),
+            unpublish_at: result.unpublish_at.map(|p| p.to_string()),
             created_at: result.created_at.to_string(),
             updated_at: result.updated_at.to_string(),
         })
     }
 
-    pub async fn delete_content(&self, id: &i64) -> Result<(), sqlx::Error> {
+    /// Promotes every draft on a page that differs from its published value.
+    pub async fn publish_all_by_page_id(
+        &self,
+        conn: &mut SqliteConnection,
+        page_id: &i64,
+    ) -> Result<Vec<Content>, sqlx::Error> {
+        let contents = sqlx::query!(
+            r#"
+            UPDATE content
+            SET published_body = body, published_at = CURRENT_TIMESTAMP, publish_at = NULL,
+                scheduled_body = NULL
+            WHERE page_id = ? AND (published_body IS NULL OR published_body != body)
+            RETURNING id, page_id, name, body, published_body, published_at, scheduled_body, publish_at,
+                unpublish_at, created_at, updated_at
+            "#,
+            page_id
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(contents
+            .into_iter()
+            .map(|c| Content {
+                id: c.id.expect("id should not be null"),
+                page_id: c.page_id,
+                name: c.name,
+                body: c.body,
+                published_body: c.published_body,
+                published_at: c.published_at.map(|p| p.to_string()),
+                scheduled_body: c.scheduled_body,
+                publish_at: c.publish_at.map(|p| p.to_string()),
+                unpublish_at: c.unpublish_at.map(|p| p.to_string()),
+                created_at: c.created_at.to_string(),
+                updated_at: c.updated_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// Promotes the scheduled snapshot of entries whose `publish_at` has
+    /// passed and clears the schedule.
+    pub async fn publish_due(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<Content>, sqlx::Error> {
+        let contents = sqlx::query!(
+            r#"
+            UPDATE content
+            SET published_body = COALESCE(scheduled_body, published_body),
+                published_at = publish_at, publish_at = NULL, scheduled_body = NULL
+            WHERE publish_at IS NOT NULL AND datetime(publish_at) <= datetime('now')
+            RETURNING id, page_id, name, body, published_body, published_at, scheduled_body, publish_at,
+                unpublish_at, created_at, updated_at
+            "#
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(contents
+            .into_iter()
+            .map(|c| Content {
+                id: c.id,
+                page_id: c.page_id,
+                name: c.name,
+                body: c.body,
+                published_body: c.published_body,
+                published_at: c.published_at.map(|p| p.to_string()),
+                scheduled_body: c.scheduled_body,
+                publish_at: c.publish_at.map(|p| p.to_string()),
+                unpublish_at: c.unpublish_at.map(|p| p.to_string()),
+                created_at: c.created_at.to_string(),
+                updated_at: c.updated_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// Withdraws entries whose `unpublish_at` has passed and clears the schedule.
+    pub async fn unpublish_due(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<Content>, sqlx::Error> {
+        let contents = sqlx::query!(
+            r#"
+            UPDATE content
+            SET published_body = NULL, published_at = NULL, unpublish_at = NULL
+            WHERE unpublish_at IS NOT NULL AND datetime(unpublish_at) <= datetime('now')
+            RETURNING id, page_id, name, body, published_body AS "published_body?: String",
+                published_at, scheduled_body, publish_at, unpublish_at, created_at, updated_at
+            "#
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(contents
+            .into_iter()
+            .map(|c| Content {
+                id: c.id,
+                page_id: c.page_id,
+                name: c.name,
+                body: c.body,
+                published_body: c.published_body,
+                published_at: c.published_at.map(|p| p.to_string()),
+                scheduled_body: c.scheduled_body,
+                publish_at: c.publish_at.map(|p| p.to_string()),
+                unpublish_at: c.unpublish_at.map(|p| p.to_string()),
+                created_at: c.created_at.to_string(),
+                updated_at: c.updated_at.to_string(),
+            })
+            .collect())
+    }
+
+    pub async fn delete_content(
+        &self,
+        conn: &mut SqliteConnection,
+        id: &i64,
+    ) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
             DELETE FROM content WHERE id = ?
             "#,
             id
         )
-        .execute(&self.db)
+        .execute(conn)
         .await?;
 
         Ok(())
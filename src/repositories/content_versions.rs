@@ -0,0 +1,102 @@
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::models::content::{Content, ContentVersion, VersionAction};
+
+pub struct ContentVersionRepository {
+    db: SqlitePool,
+}
+
+impl ContentVersionRepository {
+    pub fn new(db: &SqlitePool) -> Self {
+        ContentVersionRepository { db: db.clone() }
+    }
+
+    pub async fn create_version(
+        &self,
+        conn: &mut SqliteConnection,
+        content: &Content,
+        action: VersionAction,
+        user_id: Option<i64>,
+    ) -> Result<i64, sqlx::Error> {
+        let action = action.as_str();
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO content_versions (content_id, page_id, name, body, action, user_id)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+            content.id,
+            content.page_id,
+            content.name,
+            content.body,
+            action,
+            user_id
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn find_by_id(&self, id: &i64) -> Result<ContentVersion, sqlx::Error> {
+        let version = sqlx::query!(
+            r#"
+            SELECT v.id, v.content_id, v.page_id, v.name, v.body, v.action, v.user_id,
+                u.given_name || ' ' || u.family_name AS "author?: String",
+                v.created_at
+            FROM content_versions v
+            LEFT JOIN users u ON u.id = v.user_id
+            WHERE v.id = ?
+            "#,
+            id
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(ContentVersion {
+            id: version.id,
+            content_id: version.content_id,
+            page_id: version.page_id,
+            name: version.name,
+            body: version.body,
+            action: version.action,
+            user_id: version.user_id,
+            author: version.author,
+            created_at: version.created_at.to_string(),
+        })
+    }
+
+    pub async fn find_all_by_content_id(
+        &self,
+        content_id: &i64,
+    ) -> Result<Vec<ContentVersion>, sqlx::Error> {
+        let versions = sqlx::query!(
+            r#"
+            SELECT v.id, v.content_id, v.page_id, v.name, v.body, v.action, v.user_id,
+                u.given_name || ' ' || u.family_name AS "author?: String",
+                v.created_at
+            FROM content_versions v
+            LEFT JOIN users u ON u.id = v.user_id
+            WHERE v.content_id = ?
+            ORDER BY v.id DESC
+            "#,
+            content_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(versions
+            .into_iter()
+            .map(|v| ContentVersion {
+                id: v.id.expect("id should not be null"),
+                content_id: v.content_id,
+                page_id: v.page_id,
+                name: v.name,
+                body: v.body,
+                action: v.action,
+                user_id: v.user_id,
+                author: v.author,
+                created_at: v.created_at.to_string(),
+            })
+            .collect())
+    }
+}
@@ -1,3 +1,6 @@
+pub mod api_keys;
+pub mod app_members;
 pub mod apps;
 pub mod content;
+pub mod content_versions;
 pub mod pages;
@@ -1,11 +1,15 @@
+use chrono::Utc;
 use slug::slugify;
 use sqlx::Error::RowNotFound;
 use sqlx::SqlitePool;
 
-use crate::models::{
-    app::App,
-    content::Content,
-    page::{FullPage, NewPageRequest, Page, PageContent},
+use crate::{
+    models::{
+        app::App,
+        content::Content,
+        page::{FullPage, NewPageRequest, Page, PageContent, UpdatePageRequest},
+    },
+    repositories::content::ContentRepository,
 };
 
 pub struct PageRepository {
@@ -68,6 +72,11 @@ impl PageRepository {
                     page_id: c.page_id,
                     name: c.name,
                     body: c.body,
+                    published_body: c.published_body,
+                    published_at: c.published_at.map(|p| p.to_string()),
+                    scheduled_body: c.scheduled_body,
+                    publish_at: c.publish_at.map(|p| p.to_string()),
+                    unpublish_at: c.unpublish_at.map(|p| p.to_string()),
                     created_at: c.created_at.to_string(),
                     updated_at: c.updated_at.to_string(),
                 })
@@ -75,11 +84,33 @@ impl PageRepository {
         })
     }
 
+    /// Only live values are returned, as decided by `Content::live_body`;
+    /// drafts stay out of delivery.
     pub async fn get_content_for_page(&self, page_id: &i64) -> Result<PageContent, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+        let contents = ContentRepository::new(&self.db)
+            .find_all_by_page_ids(&[*page_id])
+            .await?;
+
+        let content: PageContent = contents
+            .iter()
+            .filter_map(|content| Some((content.name.clone(), content.live_body(now)?.to_string())))
+            .collect();
+        if content.is_empty() {
+            return Err(RowNotFound);
+        }
+
+        Ok(content)
+    }
+
+    pub async fn get_draft_content_for_page(
+        &self,
+        page_id: &i64,
+    ) -> Result<PageContent, sqlx::Error> {
         let rows = sqlx::query!(
             r#"
-            SELECT * FROM content
-            WHERE page_id = ? 
+            SELECT name, body FROM content
+            WHERE page_id = ?
             "#,
             page_id
         )
@@ -118,6 +149,58 @@ impl PageRepository {
             .await
     }
 
+    /// Finds several pages by id in one query, for batch loading.
+    pub async fn find_all_by_ids(&self, ids: &[i64]) -> Result<Vec<Page>, sqlx::Error> {
+        let ids = serde_json::to_string(ids).expect("ids should serialize");
+        let pages = sqlx::query!(
+            r#"
+            SELECT id AS "id!", app_id, name, created_at, updated_at FROM pages
+            WHERE id IN (SELECT value FROM json_each(?))
+            ORDER BY id
+            "#,
+            ids
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(pages
+            .into_iter()
+            .map(|p| Page {
+                id: p.id,
+                app_id: p.app_id,
+                name: p.name,
+                created_at: p.created_at.to_string(),
+                updated_at: p.updated_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// Finds the pages of several apps in one query, for batch loading.
+    pub async fn find_all_by_app_ids(&self, app_ids: &[i64]) -> Result<Vec<Page>, sqlx::Error> {
+        let app_ids = serde_json::to_string(app_ids).expect("ids should serialize");
+        let pages = sqlx::query!(
+            r#"
+            SELECT id AS "id!", app_id, name, created_at, updated_at FROM pages
+            WHERE app_id IN (SELECT value FROM json_each(?))
+            ORDER BY id
+            "#,
+            app_ids
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(pages
+            .into_iter()
+            .map(|p| Page {
+                id: p.id,
+                app_id: p.app_id,
+                name: p.name,
+                created_at: p.created_at.to_string(),
+                updated_at: p.updated_at.to_string(),
+            })
+            .collect())
+    }
+
     pub async fn create_page(&self, mut page: NewPageRequest) -> Result<Page, sqlx::Error> {
         page.name = slugify(&page.name).replace("-", "_");
         let record = sqlx::query!(
@@ -140,6 +223,33 @@ impl PageRepository {
         })
     }
 
+    pub async fn update_page(
+        &self,
+        id: &i64,
+        mut request: UpdatePageRequest,
+    ) -> Result<Page, sqlx::Error> {
+        request.name = slugify(&request.name).replace("-", "_");
+        let record = sqlx::query!(
+            r#"
+            UPDATE pages SET name = LOWER(?), updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            RETURNING id AS "id!", app_id, name, created_at, updated_at
+            "#,
+            request.name,
+            id
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(Page {
+            id: record.id,
+            app_id: record.app_id,
+            name: record.name,
+            created_at: record.created_at.to_string(),
+            updated_at: record.updated_at.to_string(),
+        })
+    }
+
     pub async fn delete_page(&self, id: &i64) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
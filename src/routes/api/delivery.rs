@@ -0,0 +1,165 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    AppState,
+    extractors::api_key::AppApiKey,
+    models::{
+        api_key::ApiKeyScope,
+        app::App,
+        page::{DeliveredPage, DeliveredPages, PageBatchParams},
+    },
+    repositories::{apps::AppRepository, pages::PageRepository},
+    routes::api::error::{ApiError, ErrorBody},
+    services::{apps::AppService, pages::PageService},
+};
+
+/// Upper bound on the number of pages a single batch request may ask for.
+const MAX_BATCH_SIZE: usize = 50;
+
+pub fn routes() -> OpenApiRouter<Arc<AppState>> {
+    OpenApiRouter::new()
+        .routes(routes!(get_pages))
+        .routes(routes!(get_page))
+}
+
+async fn find_app(state: &AppState, app_name: &str) -> Result<App, ApiError> {
+    let app_service = AppService::new(AppRepository::new(&state.db));
+
+    app_service
+        .find_by_name(app_name)
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::RowNotFound => ApiError::not_found(
+                "app_not_found",
+                format!("No app named '{}' exists.", app_name),
+            ),
+            _ => ApiError::internal(),
+        })
+}
+
+/// Fetches the published content of one page.
+#[utoipa::path(
+    get,
+    path = "/apps/{app_name}/pages/{page_name}",
+    tag = "delivery",
+    params(
+        ("app_name" = String, Path, description = "Name of the app"),
+        ("page_name" = String, Path, description = "Name of the page"),
+    ),
+    responses(
+        (status = 200, body = DeliveredPage),
+        (status = 401, description = "No API key, or an unknown one", body = ErrorBody),
+        (status = 403, description = "The key belongs to another app", body = ErrorBody),
+        (status = 404, description = "No such app, or nothing published on the page", body = ErrorBody),
+    ),
+    security(("api_key" = [])),
+)]
+pub async fn get_page(
+    api_key: AppApiKey,
+    State(state): State<Arc<AppState>>,
+    Path((app_name, page_name)): Path<(String, String)>,
+) -> Result<Json<DeliveredPage>, ApiError> {
+    let app = find_app(&state, &app_name).await?;
+    api_key.require(&app.id, ApiKeyScope::Read)?;
+    let page_service = PageService::new(PageRepository::new(&state.db));
+
+    let content = page_service
+        .get_content_for_page_name(&page_name, app.id)
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::RowNotFound => ApiError::not_found(
+                "page_not_found",
+                format!(
+                    "No published page named '{}' exists in '{}'.",
+                    page_name, app.name
+                ),
+            ),
+            _ => ApiError::internal(),
+        })?;
+
+    Ok(Json(DeliveredPage {
+        app: app.name,
+        page: page_name,
+        content,
+    }))
+}
+
+/// Fetches several pages of one app in a single round trip. Pages that do
+/// not exist (or have nothing published) are reported in `missing` rather
+/// than failing the whole batch.
+#[utoipa::path(
+    get,
+    path = "/apps/{app_name}/pages",
+    tag = "delivery",
+    params(
+        ("app_name" = String, Path, description = "Name of the app"),
+        PageBatchParams,
+    ),
+    responses(
+        (status = 200, body = DeliveredPages),
+        (status = 400, description = "No page names, or too many", body = ErrorBody),
+        (status = 401, description = "No API key, or an unknown one", body = ErrorBody),
+        (status = 403, description = "The key belongs to another app", body = ErrorBody),
+        (status = 404, description = "No such app", body = ErrorBody),
+    ),
+    security(("api_key" = [])),
+)]
+pub async fn get_pages(
+    api_key: AppApiKey,
+    State(state): State<Arc<AppState>>,
+    Path(app_name): Path<String>,
+    Query(params): Query<PageBatchParams>,
+) -> Result<Json<DeliveredPages>, ApiError> {
+    let mut seen = HashSet::new();
+    let names: Vec<String> = params
+        .names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && seen.insert(*name))
+        .map(str::to_string)
+        .collect();
+
+    if names.is_empty() {
+        return Err(ApiError::bad_request(
+            "missing_page_names",
+            "Provide one or more comma separated page names in the 'names' query parameter.",
+        ));
+    }
+    if names.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::bad_request(
+            "too_many_pages",
+            format!("At most {} pages can be requested at once.", MAX_BATCH_SIZE),
+        ));
+    }
+
+    let app = find_app(&state, &app_name).await?;
+    api_key.require(&app.id, ApiKeyScope::Read)?;
+    let page_service = PageService::new(PageRepository::new(&state.db));
+
+    let mut pages = BTreeMap::new();
+    let mut missing = Vec::new();
+    for name in names {
+        match page_service.get_content_for_page_name(&name, app.id).await {
+            Ok(content) => {
+                pages.insert(name, content);
+            }
+            Err(sqlx::Error::RowNotFound) => missing.push(name),
+            Err(_) => return Err(ApiError::internal()),
+        }
+    }
+
+    Ok(Json(DeliveredPages {
+        app: app.name,
+        pages,
+        missing,
+    }))
+}
@@ -0,0 +1,147 @@
+use axum::{
+    Json,
+    extract::rejection::JsonRejection,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::services::error::{FieldError, ServiceError};
+
+/// Every API failure is reported with the same body so that clients can
+/// branch on `error.code` instead of parsing messages.
+///
+/// ```json
+/// { "error": { "code": "page_not_found", "message": "..." } }
+/// ```
+///
+/// Requests that fail validation also list what's wrong with each field:
+///
+/// ```json
+/// { "error": { "code": "validation_failed", "message": "...",
+///   "fields": [{ "field": "name", "message": "..." }] } }
+/// ```
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    pub fields: Vec<FieldError>,
+}
+
+/// The body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorDetail<'a> {
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    fields: &'a [FieldError],
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// A 422 listing the problems with each field of the request.
+    pub fn validation(fields: Vec<FieldError>) -> Self {
+        ApiError {
+            fields,
+            ..ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_failed",
+                "Some fields are not valid.",
+            )
+        }
+    }
+
+    /// A 409 for a name that's already taken, reported against the `name`
+    /// field so clients can show it like any other validation error.
+    pub fn name_taken(message: impl Into<String>) -> Self {
+        let message = message.into();
+        ApiError {
+            fields: vec![FieldError::new("name", message.clone())],
+            ..ApiError::new(StatusCode::CONFLICT, "name_taken", message)
+        }
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub fn internal() -> Self {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "Something went wrong on our end.",
+        )
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => {
+                ApiError::not_found("not_found", "The requested resource does not exist.")
+            }
+            _ => ApiError::internal(),
+        }
+    }
+}
+
+impl From<ServiceError> for ApiError {
+    fn from(err: ServiceError) -> Self {
+        match err {
+            ServiceError::Forbidden(_) => ApiError::new(
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "Your role or token doesn't allow this.",
+            ),
+            ServiceError::Invalid(message) => {
+                ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_request", message)
+            }
+            ServiceError::Validation(fields) => ApiError::validation(fields),
+            ServiceError::Database(err) => err.into(),
+            ServiceError::Hashing(_) => ApiError::internal(),
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::new(rejection.status(), "invalid_body", rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: &self.message,
+                fields: &self.fields,
+            },
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
@@ -0,0 +1,668 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State, rejection::JsonRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    AppState,
+    extractors::api_key::ApiCaller,
+    models::{
+        app::{App, AppSearch, AppWithPages, CreateAppForm, UpdateAppRequest},
+        app_member::AppScope,
+        content::{Content, NewContentRequest, UpdateContentRequest},
+        page::{FullPage, NewPageRequest, Page, UpdatePageRequest},
+    },
+    repositories::{app_members::AppMemberRepository, apps::AppRepository, pages::PageRepository},
+    routes::api::error::{ApiError, ErrorBody},
+    services::{
+        app_members::AppMemberService, apps::AppService, content::ContentService,
+        error::ServiceError, pages::PageService,
+    },
+    user::User,
+};
+
+/// Create, read, update and delete apps, pages and content with JSON, as the
+/// owner of a personal access token. Everything goes through the same
+/// services as the web UI, so roles, token scopes and validation apply the
+/// same way.
+pub fn routes() -> OpenApiRouter<Arc<AppState>> {
+    OpenApiRouter::new().nest(
+        "/manage",
+        OpenApiRouter::new()
+            .routes(routes!(list_apps, create_app))
+            .routes(routes!(get_app, update_app, delete_app))
+            .routes(routes!(list_pages, create_page))
+            .routes(routes!(get_page, update_page, delete_page))
+            .routes(routes!(list_content, create_content))
+            .routes(routes!(get_content, update_content, delete_content)),
+    )
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AppListParams {
+    /// Only list apps whose name contains this.
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewAppBody {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+/// Fields left out of an update keep their current value.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct AppChanges {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewPageBody {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewContentBody {
+    pub name: String,
+    #[serde(default)]
+    pub body: String,
+    /// Publish straight away instead of saving a draft.
+    #[serde(default)]
+    pub publish: bool,
+    /// When to publish, in UTC, like `2025-07-01T09:30`.
+    pub publish_at: Option<String>,
+    /// When to stop delivering the content, in UTC.
+    pub unpublish_at: Option<String>,
+}
+
+/// Fields left out of an update keep their current value. An empty
+/// `publish_at` or `unpublish_at` clears it.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ContentChanges {
+    pub name: Option<String>,
+    pub body: Option<String>,
+    #[serde(default)]
+    pub publish: bool,
+    pub publish_at: Option<String>,
+    pub unpublish_at: Option<String>,
+}
+
+fn not_found(scope: AppScope) -> ApiError {
+    match scope {
+        AppScope::App(id) => ApiError::not_found("app_not_found", format!("No app {} exists.", id)),
+        AppScope::Page(id) => {
+            ApiError::not_found("page_not_found", format!("No page {} exists.", id))
+        }
+        AppScope::Content(id) => {
+            ApiError::not_found("content_not_found", format!("No content {} exists.", id))
+        }
+    }
+}
+
+/// Maps a failed lookup of what `scope` points at to the right error.
+fn lookup(scope: AppScope) -> impl FnOnce(sqlx::Error) -> ApiError {
+    move |err| match err {
+        sqlx::Error::RowNotFound => not_found(scope),
+        err => err.into(),
+    }
+}
+
+/// Resolves who to act as in the app `scope` belongs to: a person with
+/// their role in that app, or a write key if it's the key's own app. Apps,
+/// pages and content outside of that are reported as missing.
+async fn member_of(
+    state: &AppState,
+    caller: &ApiCaller,
+    scope: AppScope,
+) -> Result<User, ApiError> {
+    let app_member_repository = AppMemberRepository::new(&state.db);
+
+    match caller {
+        ApiCaller::User(user) => AppMemberService::new(app_member_repository)
+            .member(user, scope)
+            .await
+            .map_err(lookup(scope)),
+        ApiCaller::Key(key) => {
+            let app_id = app_member_repository
+                .find_app_id(scope)
+                .await
+                .map_err(lookup(scope))?;
+            if app_id != key.app_id {
+                return Err(not_found(scope));
+            }
+
+            Ok(User::for_api_key(key))
+        }
+    }
+}
+
+// names are unique within their parent, so a unique violation always means
+// the name is taken
+fn name_taken(err: ServiceError, message: &str) -> ApiError {
+    match err {
+        ServiceError::Database(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            ApiError::name_taken(message)
+        }
+        err => err.into(),
+    }
+}
+
+fn created<T: serde::Serialize>(value: T) -> Response {
+    (StatusCode::CREATED, Json(value)).into_response()
+}
+
+/// Lists the apps you're a member of.
+#[utoipa::path(
+    get,
+    path = "/apps",
+    tag = "apps",
+    params(AppListParams),
+    responses(
+        (status = 200, body = Vec<App>),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn list_apps(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<AppListParams>,
+) -> Result<Json<Vec<App>>, ApiError> {
+    let app_service = AppService::new(AppRepository::new(&state.db));
+    let apps = match &caller {
+        ApiCaller::User(user) => {
+            app_service
+                .search(user, &AppSearch { name: params.name })
+                .await?
+        }
+        // a key only ever sees its own app
+        ApiCaller::Key(key) => {
+            let app = app_service
+                .find_by_id(&caller.user(), &key.app_id)
+                .await?
+                .app;
+            let name = params.name.to_lowercase();
+            if app.name.to_lowercase().contains(&name) {
+                vec![app]
+            } else {
+                vec![]
+            }
+        }
+    };
+
+    Ok(Json(apps))
+}
+
+/// Creates an app, with you as its first admin.
+#[utoipa::path(
+    post,
+    path = "/apps",
+    tag = "apps",
+    request_body = NewAppBody,
+    responses(
+        (status = 201, body = App),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 409, description = "The name is taken", body = ErrorBody),
+        (status = 422, description = "The body is malformed or fails validation", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn create_app(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    body: Result<Json<NewAppBody>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(body) = body?;
+    let app_service = AppService::new(AppRepository::new(&state.db));
+    let request = CreateAppForm {
+        name: body.name.trim().to_string(),
+        description: body.description,
+        url: body.url.trim().to_string(),
+    };
+
+    let app = app_service
+        .create_app(&caller.user(), request)
+        .await
+        .map_err(|err| name_taken(err, "An app with this name already exists."))?;
+
+    Ok(created(app))
+}
+
+/// Fetches an app and its pages.
+#[utoipa::path(
+    get,
+    path = "/apps/{id}",
+    tag = "apps",
+    params(("id" = i64, Path, description = "Id of the app")),
+    responses(
+        (status = 200, body = AppWithPages),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 404, description = "No such app, or you're not a member", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn get_app(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<AppWithPages>, ApiError> {
+    let member = member_of(&state, &caller, AppScope::App(id)).await?;
+    let app_service = AppService::new(AppRepository::new(&state.db));
+
+    Ok(Json(
+        app_service
+            .find_by_id(&member, &id)
+            .await
+            .map_err(lookup(AppScope::App(id)))?,
+    ))
+}
+
+/// Changes an app's name, description or URL.
+#[utoipa::path(
+    patch,
+    path = "/apps/{id}",
+    tag = "apps",
+    params(("id" = i64, Path, description = "Id of the app")),
+    request_body = AppChanges,
+    responses(
+        (status = 200, body = App),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 404, description = "No such app, or you're not a member", body = ErrorBody),
+        (status = 409, description = "The name is taken", body = ErrorBody),
+        (status = 422, description = "The body is malformed or fails validation", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn update_app(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    body: Result<Json<AppChanges>, JsonRejection>,
+) -> Result<Json<App>, ApiError> {
+    let Json(changes) = body?;
+    let member = member_of(&state, &caller, AppScope::App(id)).await?;
+    let app_service = AppService::new(AppRepository::new(&state.db));
+
+    let existing = app_service
+        .find_by_id(&member, &id)
+        .await
+        .map_err(lookup(AppScope::App(id)))?
+        .app;
+    let request = UpdateAppRequest {
+        name: changes.name.unwrap_or(existing.name).trim().to_string(),
+        description: changes.description.unwrap_or(existing.description),
+        url: changes.url.unwrap_or(existing.url).trim().to_string(),
+    };
+    let app = app_service
+        .update_app(&member, &id, request)
+        .await
+        .map_err(|err| name_taken(err, "An app with this name already exists."))?;
+
+    Ok(Json(app))
+}
+
+/// Deletes an app with its pages and content.
+#[utoipa::path(
+    delete,
+    path = "/apps/{id}",
+    tag = "apps",
+    params(("id" = i64, Path, description = "Id of the app")),
+    responses(
+        (status = 204, description = "The app was deleted"),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 404, description = "No such app, or you're not a member", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn delete_app(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let member = member_of(&state, &caller, AppScope::App(id)).await?;
+    let app_service = AppService::new(AppRepository::new(&state.db));
+    app_service.delete_app(&member, &id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists an app's pages.
+#[utoipa::path(
+    get,
+    path = "/apps/{id}/pages",
+    tag = "pages",
+    params(("id" = i64, Path, description = "Id of the app")),
+    responses(
+        (status = 200, body = Vec<Page>),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 404, description = "No such app, or you're not a member", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn list_pages(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<Page>>, ApiError> {
+    member_of(&state, &caller, AppScope::App(id)).await?;
+    let app_service = AppService::new(AppRepository::new(&state.db));
+
+    Ok(Json(
+        app_service.find_pages_by_app_id(&id.to_string()).await?,
+    ))
+}
+
+/// Adds a page to an app.
+#[utoipa::path(
+    post,
+    path = "/apps/{id}/pages",
+    tag = "pages",
+    params(("id" = i64, Path, description = "Id of the app")),
+    request_body = NewPageBody,
+    responses(
+        (status = 201, body = Page),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 404, description = "No such app, or you're not a member", body = ErrorBody),
+        (status = 409, description = "The name is taken", body = ErrorBody),
+        (status = 422, description = "The body is malformed or fails validation", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn create_page(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    body: Result<Json<NewPageBody>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(body) = body?;
+    let member = member_of(&state, &caller, AppScope::App(id)).await?;
+    let page_service = PageService::new(PageRepository::new(&state.db));
+    let request = NewPageRequest {
+        app_id: id,
+        name: body.name,
+    };
+
+    let page = page_service
+        .create_page(&member, request)
+        .await
+        .map_err(|err| name_taken(err, "A page with this name already exists in this app."))?;
+
+    Ok(created(page))
+}
+
+/// Fetches a page with its app and content.
+#[utoipa::path(
+    get,
+    path = "/pages/{id}",
+    tag = "pages",
+    params(("id" = i64, Path, description = "Id of the page")),
+    responses(
+        (status = 200, body = FullPage),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 404, description = "No such page, or you're not a member of its app", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn get_page(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<FullPage>, ApiError> {
+    member_of(&state, &caller, AppScope::Page(id)).await?;
+    let page_service = PageService::new(PageRepository::new(&state.db));
+
+    Ok(Json(
+        page_service
+            .find_by_id(&id)
+            .await
+            .map_err(lookup(AppScope::Page(id)))?,
+    ))
+}
+
+/// Renames a page.
+#[utoipa::path(
+    patch,
+    path = "/pages/{id}",
+    tag = "pages",
+    params(("id" = i64, Path, description = "Id of the page")),
+    request_body = UpdatePageRequest,
+    responses(
+        (status = 200, body = Page),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 404, description = "No such page, or you're not a member of its app", body = ErrorBody),
+        (status = 409, description = "The name is taken", body = ErrorBody),
+        (status = 422, description = "The body is malformed or fails validation", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn update_page(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    body: Result<Json<UpdatePageRequest>, JsonRejection>,
+) -> Result<Json<Page>, ApiError> {
+    let Json(request) = body?;
+    let member = member_of(&state, &caller, AppScope::Page(id)).await?;
+    let page_service = PageService::new(PageRepository::new(&state.db));
+
+    let page = page_service
+        .update_page(&member, &id, request)
+        .await
+        .map_err(|err| name_taken(err, "A page with this name already exists in this app."))?;
+
+    Ok(Json(page))
+}
+
+/// Deletes a page with its content.
+#[utoipa::path(
+    delete,
+    path = "/pages/{id}",
+    tag = "pages",
+    params(("id" = i64, Path, description = "Id of the page")),
+    responses(
+        (status = 204, description = "The page was deleted"),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 404, description = "No such page, or you're not a member of its app", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn delete_page(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let member = member_of(&state, &caller, AppScope::Page(id)).await?;
+    let page_service = PageService::new(PageRepository::new(&state.db));
+    page_service.delete_page(&member, &id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists a page's content, drafts included.
+#[utoipa::path(
+    get,
+    path = "/pages/{id}/content",
+    tag = "content",
+    params(("id" = i64, Path, description = "Id of the page")),
+    responses(
+        (status = 200, body = Vec<Content>),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 404, description = "No such page, or you're not a member of its app", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn list_content(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<Content>>, ApiError> {
+    member_of(&state, &caller, AppScope::Page(id)).await?;
+    let content_service = ContentService::new(&state.db);
+
+    Ok(Json(content_service.find_all_by_page_id(id).await?))
+}
+
+/// Adds content to a page, publishing it if asked to.
+#[utoipa::path(
+    post,
+    path = "/pages/{id}/content",
+    tag = "content",
+    params(("id" = i64, Path, description = "Id of the page")),
+    request_body = NewContentBody,
+    responses(
+        (status = 201, body = Content),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 404, description = "No such page, or you're not a member of its app", body = ErrorBody),
+        (status = 409, description = "The name is taken", body = ErrorBody),
+        (status = 422, description = "The body is malformed or fails validation", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn create_content(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    body: Result<Json<NewContentBody>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(body) = body?;
+    let member = member_of(&state, &caller, AppScope::Page(id)).await?;
+    let content_service = ContentService::new(&state.db);
+    let request = NewContentRequest {
+        page_id: id,
+        name: body.name,
+        body: body.body,
+        publish: body.publish,
+        publish_at: body.publish_at,
+        unpublish_at: body.unpublish_at,
+    };
+
+    let content = content_service
+        .create_content(&member, request)
+        .await
+        .map_err(|err| name_taken(err, "Content with this name already exists on this page."))?;
+
+    Ok(created(content))
+}
+
+/// Fetches a content entry.
+#[utoipa::path(
+    get,
+    path = "/content/{id}",
+    tag = "content",
+    params(("id" = i64, Path, description = "Id of the content")),
+    responses(
+        (status = 200, body = Content),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 404, description = "No such content, or you're not a member of its app", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn get_content(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Content>, ApiError> {
+    member_of(&state, &caller, AppScope::Content(id)).await?;
+    let content_service = ContentService::new(&state.db);
+
+    Ok(Json(
+        content_service
+            .find_by_id(&id)
+            .await
+            .map_err(lookup(AppScope::Content(id)))?,
+    ))
+}
+
+/// Changes a content entry, publishing it if asked to.
+#[utoipa::path(
+    patch,
+    path = "/content/{id}",
+    tag = "content",
+    params(("id" = i64, Path, description = "Id of the content")),
+    request_body = ContentChanges,
+    responses(
+        (status = 200, body = Content),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 404, description = "No such content, or you're not a member of its app", body = ErrorBody),
+        (status = 409, description = "The name is taken", body = ErrorBody),
+        (status = 422, description = "The body is malformed or fails validation", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn update_content(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    body: Result<Json<ContentChanges>, JsonRejection>,
+) -> Result<Json<Content>, ApiError> {
+    let Json(changes) = body?;
+    let member = member_of(&state, &caller, AppScope::Content(id)).await?;
+    let content_service = ContentService::new(&state.db);
+
+    let existing = content_service
+        .find_by_id(&id)
+        .await
+        .map_err(lookup(AppScope::Content(id)))?;
+    let request = UpdateContentRequest {
+        content_id: id,
+        name: changes.name.unwrap_or(existing.name),
+        body: changes.body.unwrap_or(existing.body),
+        publish: changes.publish,
+        publish_at: changes.publish_at.or(existing.publish_at),
+        unpublish_at: changes.unpublish_at.or(existing.unpublish_at),
+    };
+    let content = content_service
+        .update_content(&member, request)
+        .await
+        .map_err(|err| name_taken(err, "Content with this name already exists on this page."))?;
+
+    Ok(Json(content))
+}
+
+/// Deletes a content entry.
+#[utoipa::path(
+    delete,
+    path = "/content/{id}",
+    tag = "content",
+    params(("id" = i64, Path, description = "Id of the content")),
+    responses(
+        (status = 204, description = "The content was deleted"),
+        (status = 401, description = "No personal access token or write key, or an unusable one", body = ErrorBody),
+        (status = 403, description = "Your role, the token's scopes or the key don't allow this", body = ErrorBody),
+        (status = 404, description = "No such content, or you're not a member of its app", body = ErrorBody),
+    ),
+    security(("access_token" = []), ("api_key" = [])),
+)]
+pub async fn delete_content(
+    caller: ApiCaller,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let member = member_of(&state, &caller, AppScope::Content(id)).await?;
+    let content_service = ContentService::new(&state.db);
+    content_service.delete_content(&member, &id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
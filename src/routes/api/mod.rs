@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    http::{
+        Method,
+        header::{AUTHORIZATION, ETAG, IF_NONE_MATCH},
+    },
+    middleware,
+};
+use tower_http::cors::{Any, CorsLayer};
+use utoipa::OpenApi;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{AppState, middleware::etag::etag};
+
+pub mod delivery;
+pub mod error;
+pub mod management;
+pub mod openapi;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    // the delivery API is read-only and meant to be called from any client
+    // app, including browsers on other origins
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods([Method::GET])
+        .allow_headers([AUTHORIZATION, IF_NONE_MATCH])
+        .expose_headers([ETAG]);
+
+    // the management API is for scripts acting as a user, not for other
+    // sites, so it gets no CORS headers
+    let (router, openapi) = OpenApiRouter::with_openapi(openapi::ApiDoc::openapi())
+        .nest(
+            "/api/v1",
+            OpenApiRouter::new()
+                // delivered content changes rarely and is fetched often, so
+                // clients can revalidate what they have instead of
+                // downloading it again
+                .merge(
+                    delivery::routes()
+                        .layer(middleware::from_fn(etag))
+                        .layer(cors),
+                )
+                .merge(management::routes()),
+        )
+        .split_for_parts();
+
+    // the document is built from the same routes that are served, so it
+    // can't drift from them
+    router.merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", openapi))
+}
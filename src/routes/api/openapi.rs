@@ -0,0 +1,48 @@
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+/// The parts of the OpenAPI document that don't come from the routes. Paths
+/// and the schemas they use are collected as `api::routes` builds the router.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Wordford API",
+        description = "Deliver published content, and manage apps, pages and content."
+    ),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "delivery", description = "Published content, read with an app's API key"),
+        (name = "apps", description = "Manage apps with a personal access token"),
+        (name = "pages", description = "Manage pages with a personal access token or an app's write key"),
+        (name = "content", description = "Manage content with a personal access token or an app's write key"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("An app's API key, starting with `wf_`."))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "access_token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("A personal access token, starting with `wfp_`."))
+                    .build(),
+            ),
+        );
+    }
+}
@@ -2,17 +2,29 @@ use axum::{
     Form, Router,
     extract::{Path, Query, State},
     http::StatusCode,
-    response::{Html, IntoResponse},
-    routing::{get, put},
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, put},
 };
 use std::sync::Arc;
 use tera::Context;
 
 use crate::{
     AppState,
-    models::app::{App, AppSearch, CreateAppForm},
-    repositories::apps::AppRepository,
-    services::apps::AppService,
+    extractors::current_user::CurrentUser,
+    models::{
+        api_key::NewApiKeyRequest,
+        app::{App, AppSearch, CreateAppForm},
+        app_member::{AppScope, NewMemberRequest, UpdateMemberRequest},
+    },
+    repositories::{
+        api_keys::ApiKeyRepository, app_members::AppMemberRepository, apps::AppRepository,
+    },
+    routes::{forbidden, insert_member, insert_user, member_of},
+    services::{
+        api_keys::ApiKeyService, app_members::AppMemberService, apps::AppService,
+        error::ServiceError,
+    },
+    user::{User, role::Role},
 };
 
 pub fn routes() -> Router<Arc<AppState>> {
@@ -21,60 +33,112 @@ pub fn routes() -> Router<Arc<AppState>> {
         Router::new()
             .route("/", put(create_app))
             .route("/new", get(create_app_html))
-            .route("/{id}", get(index))
+            .route("/{id}", get(index).delete(delete_app))
             .route("/{id}/pages/new", get(create_new_page))
+            .route("/{id}/api-keys", put(create_api_key))
+            .route("/{id}/api-keys/{key_id}", delete(revoke_api_key))
+            .route("/{id}/members", put(add_member))
+            .route(
+                "/{id}/members/{user_id}",
+                delete(remove_member).patch(update_member),
+            )
             .route("/search", get(search_results)),
     )
 }
 
-pub async fn index(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Html<String> {
+pub async fn index(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let app_repository = AppRepository::new(&state.db);
     let app_service = AppService::new(app_repository);
 
-    match app_service.find_by_id(&id).await {
+    let api_key_service = ApiKeyService::new(ApiKeyRepository::new(&state.db));
+    let app_member_service = AppMemberService::new(AppMemberRepository::new(&state.db));
+
+    match app_service.find_by_id(&user, &id).await {
         Ok(app) => {
-            let context = tera::Context::from_serialize(app).unwrap();
-            Html(state.tera.render("apps/index.html", &context).unwrap())
+            let mut context = tera::Context::from_serialize(app).unwrap();
+            insert_member(&mut context, &user, &member);
+            context.insert(
+                "api_keys",
+                &api_key_service
+                    .find_all_by_app_id(&id)
+                    .await
+                    .unwrap_or_default(),
+            );
+            context.insert(
+                "members",
+                &app_member_service
+                    .find_all_by_app_id(&id)
+                    .await
+                    .unwrap_or_default(),
+            );
+            context.insert("roles", &Role::ALL);
+            Html(state.tera.render("apps/index.html", &context).unwrap()).into_response()
         }
-        Err(_) => Html("".to_string()),
+        Err(_) => Html("".to_string()).into_response(),
     }
 }
 
 pub async fn create_app(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Form(request): Form<CreateAppForm>,
 ) -> impl IntoResponse {
     let app_repository = AppRepository::new(&state.db);
     let app_service = AppService::new(app_repository);
 
-    match app_service.create_app(request).await {
+    match app_service.create_app(&user, request).await {
         Ok(app) => [("HX-Redirect", format!("/apps/{}", app.id))].into_response(),
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(err @ ServiceError::Validation(_)) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response()
+        }
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
-pub async fn create_app_html(State(state): State<Arc<AppState>>) -> Html<String> {
-    let context = Context::new();
+pub async fn create_app_html(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Html<String> {
+    let mut context = Context::new();
+    insert_user(&mut context, &user);
     Html(state.tera.render("apps/new.html", &context).unwrap())
 }
 
 pub async fn create_new_page(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> Html<String> {
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let app_repository = AppRepository::new(&state.db);
     let app_service = AppService::new(app_repository);
 
-    match app_service.find_by_id(&id).await {
+    match app_service.find_by_id(&user, &id).await {
         Ok(app) => {
-            let context = tera::Context::from_serialize(app).unwrap();
-            Html(state.tera.render("apps/new_page.html", &context).unwrap())
+            let mut context = tera::Context::from_serialize(app).unwrap();
+            insert_member(&mut context, &user, &member);
+            Html(state.tera.render("apps/new_page.html", &context).unwrap()).into_response()
         }
-        Err(_) => Html("".to_string()),
+        Err(_) => Html("".to_string()).into_response(),
     }
 }
 
 pub async fn search_results(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Query(params): Query<AppSearch>,
 ) -> Html<String> {
@@ -88,7 +152,7 @@ pub async fn search_results(
         return Html("".to_string());
     }
 
-    let discovered_apps = app_service.search(&params).await.unwrap_or_default();
+    let discovered_apps = app_service.search(&user, &params).await.unwrap_or_default();
     context.insert("apps", &discovered_apps);
 
     Html(
@@ -100,14 +164,209 @@ pub async fn search_results(
 }
 
 pub async fn delete_app(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-) -> impl IntoResponse {
+    Path(id): Path<i64>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let app_repository = AppRepository::new(&state.db);
     let app_service = AppService::new(app_repository);
 
-    match app_service.delete_app(&id).await {
-        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+    match app_service.delete_app(&member, &id).await {
+        Ok(_) => [("HX-Redirect", "/")].into_response(),
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn render_api_keys(
+    state: &AppState,
+    member: &User,
+    app_id: &i64,
+    mut context: Context,
+) -> Response {
+    let api_key_service = ApiKeyService::new(ApiKeyRepository::new(&state.db));
+
+    match api_key_service.find_all_by_app_id(app_id).await {
+        Ok(api_keys) => {
+            context.insert("can", &member.permissions());
+            context.insert("app", &serde_json::json!({ "id": app_id }));
+            context.insert("api_keys", &api_keys);
+            Html(state.tera.render("apps/api_keys.html", &context).unwrap()).into_response()
+        }
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
+
+pub async fn create_api_key(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Form(request): Form<NewApiKeyRequest>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let api_key_service = ApiKeyService::new(ApiKeyRepository::new(&state.db));
+    let mut context = Context::new();
+
+    if request.name.trim().is_empty() {
+        context.insert(
+            "error",
+            "Give the key a name so you can recognise it later.",
+        );
+        return render_api_keys(&state, &member, &id, context).await;
+    }
+
+    match api_key_service.create_api_key(&member, &id, request).await {
+        Ok(created) => context.insert("created", &created),
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(ServiceError::Database(sqlx::Error::Database(err)))
+            if err.is_foreign_key_violation() =>
+        {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Err(_) => context.insert("error", "Something went wrong creating the key."),
+    }
+
+    render_api_keys(&state, &member, &id, context).await
+}
+
+pub async fn revoke_api_key(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path((id, key_id)): Path<(i64, i64)>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let api_key_service = ApiKeyService::new(ApiKeyRepository::new(&state.db));
+
+    match api_key_service.revoke_api_key(&member, &id, &key_id).await {
+        Ok(_) => render_api_keys(&state, &member, &id, Context::new()).await,
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn render_members(
+    state: &AppState,
+    user: &User,
+    member: &User,
+    app_id: &i64,
+    mut context: Context,
+) -> Response {
+    let app_member_service = AppMemberService::new(AppMemberRepository::new(&state.db));
+
+    match app_member_service.find_all_by_app_id(app_id).await {
+        Ok(members) => {
+            insert_member(&mut context, user, member);
+            context.insert("app", &serde_json::json!({ "id": app_id }));
+            context.insert("members", &members);
+            context.insert("roles", &Role::ALL);
+            Html(state.tera.render("apps/members.html", &context).unwrap()).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn add_member(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Form(request): Form<NewMemberRequest>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let app_member_service = AppMemberService::new(AppMemberRepository::new(&state.db));
+    let mut context = Context::new();
+    let email = request.email.trim().to_string();
+
+    match app_member_service.add_member(&member, &id, request).await {
+        Ok(_) => context.insert("success", &format!("Added {} to this app.", email)),
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => context.insert(
+            "error",
+            &format!("Nobody has signed up with {} yet.", email),
+        ),
+        Err(ServiceError::Database(sqlx::Error::Database(err))) if err.is_unique_violation() => {
+            context.insert("error", &format!("{} is already a member.", email))
+        }
+        Err(_) => context.insert("error", "Something went wrong adding the member."),
+    }
+
+    render_members(&state, &user, &member, &id, context).await
+}
+
+pub async fn update_member(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path((id, user_id)): Path<(i64, i64)>,
+    Form(request): Form<UpdateMemberRequest>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let app_member_service = AppMemberService::new(AppMemberRepository::new(&state.db));
+    let mut context = Context::new();
+
+    match app_member_service
+        .update_member(&member, &id, &user_id, request)
+        .await
+    {
+        Ok(_) => {}
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Err(_) => context.insert("error", "Something went wrong changing the role."),
+    }
+
+    render_members(&state, &user, &member, &id, context).await
+}
+
+pub async fn remove_member(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path((id, user_id)): Path<(i64, i64)>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let app_member_service = AppMemberService::new(AppMemberRepository::new(&state.db));
+    let mut context = Context::new();
+
+    match app_member_service
+        .remove_member(&member, &id, &user_id)
+        .await
+    {
+        Ok(_) => {}
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Err(_) => context.insert("error", "Something went wrong removing the member."),
+    }
+
+    render_members(&state, &user, &member, &id, context).await
+}
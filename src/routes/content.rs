@@ -1,12 +1,17 @@
-use crate::models::content::{NewContentRequest, UpdateContentRequest};
-use crate::services::content::ContentService;
+use crate::extractors::current_user::CurrentUser;
+use crate::models::app_member::AppScope;
+use crate::models::content::{NewContentRequest, UpdateContentRequest, VersionDiffParams};
+use crate::repositories::pages::PageRepository;
+use crate::routes::{forbidden, insert_member, member_of};
+use crate::services::{content::ContentService, error::ServiceError, pages::PageService};
+use crate::user::User;
 use crate::{AppState, models::content::FindContentByPageIdParams};
 use axum::{
     Form, Json, Router,
     extract::{Path, Query, State},
     http::StatusCode,
-    response::{Html, IntoResponse},
-    routing::get,
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
 };
 use std::sync::Arc;
 
@@ -19,24 +24,41 @@ pub fn routes() -> Router<Arc<AppState>> {
                 "/{id}",
                 get(find_by_id).patch(update_content).delete(delete_content),
             )
-            .route("/{id}/edit", get(edit_content_page)),
+            .route("/{id}/edit", get(edit_content_page))
+            .route("/{id}/publish", post(publish_content))
+            .route("/{id}/history", get(history_page))
+            .route("/{id}/history/diff", get(diff_versions))
+            .route("/{id}/history/{version_id}/restore", post(restore_version)),
     )
 }
 
 pub async fn find_all_by_page_id(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Query(params): Query<FindContentByPageIdParams>,
-) -> impl IntoResponse {
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Page(params.page_id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    render_list_view(&state, &member, params.page_id).await
+}
+
+/// Renders the content list of a page, which several actions swap back in
+/// after they change it. `member` is the user as resolved for the page's app.
+pub async fn render_list_view(state: &AppState, member: &User, page_id: i64) -> Response {
     let content_service = ContentService::new(&state.db);
 
-    let contents = match content_service.find_all_by_page_id(params.page_id).await {
+    let contents = match content_service.find_all_by_page_id(page_id).await {
         Ok(contents) => contents,
         Err(sqlx::Error::RowNotFound) => Vec::new(),
         Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     };
 
-    let context =
+    let mut context =
         tera::Context::from_serialize(serde_json::json!({ "content": contents })).unwrap();
+    context.insert("can", &member.permissions());
     Html(
         state
             .tera
@@ -47,9 +69,14 @@ pub async fn find_all_by_page_id(
 }
 
 pub async fn find_by_id(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> impl IntoResponse {
+) -> Response {
+    if let Err(response) = member_of(&state, &user, AppScope::Content(id)).await {
+        return response;
+    }
+
     let content_service = ContentService::new(&state.db);
 
     match content_service.find_by_id(&id).await {
@@ -60,9 +87,15 @@ pub async fn find_by_id(
 }
 
 pub async fn create_content(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Form(request): Form<NewContentRequest>,
-) -> impl IntoResponse {
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Page(request.page_id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let content_service = ContentService::new(&state.db);
 
     let page_id = request.page_id;
@@ -70,6 +103,7 @@ pub async fn create_content(
     let body = request.body.clone();
     let error_message = |msg: &str| {
         let mut context = tera::Context::new();
+        insert_member(&mut context, &user, &member);
         context.insert("error", &msg);
         context.insert("page_id", &page_id);
         context.insert("name", &name);
@@ -77,38 +111,55 @@ pub async fn create_content(
         Html(state.tera.render("content/form.html", &context).unwrap()).into_response()
     };
 
-    match content_service.create_content(request).await {
+    match content_service.create_content(&member, request).await {
         Ok(content) => {
             let mut context = tera::Context::new();
+            insert_member(&mut context, &user, &member);
             context.insert("page_id", &content.page_id);
-            context.insert(
-                "success",
-                &"Created the content, you can add more content below, or go back to the page."
-                    .to_string(),
-            );
+            let message = if content.published_body.is_some() {
+                "Created and published the content, you can add more content below, or go back to the page."
+            } else {
+                "Created the content as a draft, you can add more content below, or go back to the page."
+            };
+            context.insert("success", &message.to_string());
             Html(state.tera.render("content/form.html", &context).unwrap()).into_response()
         }
-        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
-            error_message(&"Content with this name already exists.").into_response()
+        Err(ServiceError::Forbidden(_)) => {
+            error_message("You don't have permission to do that.").into_response()
         }
-        Err(sqlx::Error::Database(db_err)) if db_err.is_foreign_key_violation() => {
-            error_message(&"The page you're adding to does not appear to exist.")
+        Err(err @ ServiceError::Validation(_)) => error_message(&err.to_string()).into_response(),
+        Err(ServiceError::Database(sqlx::Error::Database(db_err)))
+            if db_err.is_unique_violation() =>
+        {
+            error_message("Content with this name already exists.").into_response()
+        }
+        Err(ServiceError::Database(sqlx::Error::Database(db_err)))
+            if db_err.is_foreign_key_violation() =>
+        {
+            error_message("The page you're adding to does not appear to exist.")
                 .into_response()
                 .into_response()
         }
-        Err(_) => error_message(&"Something happened when saving your content").into_response(),
+        Err(_) => error_message("Something happened when saving your content").into_response(),
     }
 }
 
 pub async fn edit_content_page(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> impl IntoResponse {
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Content(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let content_service = ContentService::new(&state.db);
 
     match content_service.full_content_by_id(&id).await {
         Ok(content) => {
             let mut context = tera::Context::new();
+            insert_member(&mut context, &user, &member);
             context.insert("is_editing", &true);
             context.insert("form_action", "Save Content");
             context.insert("content", &content.content);
@@ -126,50 +177,211 @@ pub async fn edit_content_page(
 }
 
 pub async fn update_content(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-    Form(request): Form<UpdateContentRequest>,
-) -> impl IntoResponse {
+    Form(mut request): Form<UpdateContentRequest>,
+) -> Response {
+    // the content checked is the content saved, whatever the form says
+    request.content_id = id;
+    let member = match member_of(&state, &user, AppScope::Content(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let content_service = ContentService::new(&state.db);
 
     let error_message = |msg: &str| {
         let mut context = tera::Context::new();
+        insert_member(&mut context, &user, &member);
         context.insert("error", &msg);
         context.insert("id", &id);
         Html(state.tera.render("content/form.html", &context).unwrap()).into_response()
     };
 
-    match content_service.update_content(request).await {
+    match content_service.update_content(&member, request).await {
         Ok(content) => {
             let mut context = tera::Context::new();
-            context.insert("success", &"Content updated successfully.");
+            insert_member(&mut context, &user, &member);
+            let message = if content.has_unpublished_changes() {
+                "Draft saved. Publish it when you're ready for it to go live."
+            } else {
+                "Content saved and published."
+            };
+            context.insert("success", &message);
             context.insert("content", &content);
+            context.insert("content_id", &content.id);
             context.insert("body", &content.body);
             context.insert("name", &content.name);
             context.insert("is_editing", &true);
             Html(state.tera.render("content/form.html", &context).unwrap()).into_response()
         }
-        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
-            error_message(&"Content with this name already exists.").into_response()
+        Err(ServiceError::Forbidden(_)) => {
+            error_message("You don't have permission to do that.").into_response()
         }
-        Err(sqlx::Error::Database(db_err)) if db_err.is_foreign_key_violation() => {
-            error_message(&"The page you're adding to does not appear to exist.")
+        Err(err @ ServiceError::Validation(_)) => error_message(&err.to_string()).into_response(),
+        Err(ServiceError::Database(sqlx::Error::Database(db_err)))
+            if db_err.is_unique_violation() =>
+        {
+            error_message("Content with this name already exists.").into_response()
+        }
+        Err(ServiceError::Database(sqlx::Error::Database(db_err)))
+            if db_err.is_foreign_key_violation() =>
+        {
+            error_message("The page you're adding to does not appear to exist.")
                 .into_response()
                 .into_response()
         }
-        Err(_) => error_message(&"Something happened when saving your content").into_response(),
+        Err(_) => error_message("Something happened when saving your content").into_response(),
     }
 }
 
 pub async fn delete_content(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> impl IntoResponse {
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Content(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let content_service = ContentService::new(&state.db);
 
-    match content_service.delete_content(&id).await {
+    match content_service.delete_content(&member, &id).await {
         Ok(_) => Html("").into_response(),
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn publish_content(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Content(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let content_service = ContentService::new(&state.db);
+
+    match content_service.publish_content(&member, &id).await {
+        Ok(content) => render_list_view(&state, &member, content.page_id).await,
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn history_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Content(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let content_service = ContentService::new(&state.db);
+    let page_service = PageService::new(PageRepository::new(&state.db));
+
+    let versions = match content_service.history(&id).await {
+        Ok(versions) => versions,
+        Err(sqlx::Error::RowNotFound) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    // the newest revision knows which page the content lives on, even when
+    // the content row itself has been deleted
+    let page = match page_service.find_by_id(&versions[0].page_id).await {
+        Ok(page) => page,
+        Err(sqlx::Error::RowNotFound) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let mut context = tera::Context::new();
+    insert_member(&mut context, &user, &member);
+    context.insert("content_id", &id);
+    context.insert("current", &versions[0]);
+    context.insert("versions", &versions);
+    context.insert("page", &page.page);
+    context.insert("app", &page.app);
+
+    Html(state.tera.render("content/history.html", &context).unwrap()).into_response()
+}
+
+pub async fn diff_versions(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<VersionDiffParams>,
+) -> Response {
+    if let Err(response) = member_of(&state, &user, AppScope::Content(id)).await {
+        return response;
+    }
+
+    let content_service = ContentService::new(&state.db);
+
+    match content_service
+        .diff_versions(&id, &params.from, &params.to)
+        .await
+    {
+        Ok(diff) => {
+            let context = tera::Context::from_serialize(diff).unwrap();
+            Html(state.tera.render("content/diff.html", &context).unwrap()).into_response()
+        }
         Err(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND.into_response(),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
+
+pub async fn restore_version(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path((id, version_id)): Path<(i64, i64)>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Content(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let content_service = ContentService::new(&state.db);
+
+    match content_service
+        .restore_version(&member, &id, &version_id)
+        .await
+    {
+        Ok(content) => {
+            [("HX-Redirect", format!("/content/{}/history", content.id))].into_response()
+        }
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(ServiceError::Database(sqlx::Error::Database(db_err)))
+            if db_err.is_unique_violation() =>
+        {
+            let mut context = tera::Context::new();
+            context.insert(
+                "error",
+                "Another content entry on this page already uses this revision's name.",
+            );
+            Html(
+                state
+                    .tera
+                    .render("content/history_banner.html", &context)
+                    .unwrap(),
+            )
+            .into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use async_graphql::{BatchRequest, BatchResponse};
+use axum::{Extension, Json, Router, extract::State, routing::post};
+
+use crate::{
+    AppState,
+    extractors::current_user::CurrentUser,
+    graphql::{self, WordfordSchema},
+};
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/graphql", post(execute))
+        .layer(Extension(graphql::schema()))
+}
+
+/// Runs a query, or a batch of them, as the signed in user. Browsers send
+/// the CSRF token with it like any other change, scripts authenticate with a
+/// personal access token instead.
+pub async fn execute(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Extension(schema): Extension<WordfordSchema>,
+    Json(request): Json<BatchRequest>,
+) -> Json<BatchResponse> {
+    let request = graphql::prepare(request, &state.db, user);
+
+    Json(schema.execute_batch(request).await)
+}
@@ -9,7 +9,7 @@ use axum::{
 
 use crate::{
     AppState, extractors::current_user::MaybeUser, repositories::pages::PageRepository,
-    services::pages::PageService,
+    routes::insert_user, services::pages::PageService,
 };
 
 pub fn routes() -> Router<Arc<AppState>> {
@@ -26,7 +26,9 @@ async fn homepage_index(
         Ok(content) => {
             let context = match tera::Context::from_serialize(content) {
                 Ok(mut ctx) => {
-                    ctx.insert("user", &user);
+                    if let Some(user) = &user {
+                        insert_user(&mut ctx, user);
+                    }
                     ctx
                 }
                 Err(_) => return Html("<h1>Error preparing context</h1>".to_string()),
@@ -36,8 +38,6 @@ async fn homepage_index(
                 Err(_) => Html("Error rendering the homepage, did you you need to have <code>homepage_content</code> present in your wordford page".to_string()),
             }
         }
-        Err(_) => {
-            return Html("<h1>Error loading content</h1>".to_string());
-        }
+        Err(_) => Html("<h1>Error loading content</h1>".to_string()),
     }
 }
@@ -1,4 +1,50 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    AppState, models::app_member::AppScope, repositories::app_members::AppMemberRepository,
+    services::app_members::AppMemberService, user::User,
+};
+
+pub mod api;
 pub mod apps;
 pub mod content;
+pub mod graphql;
 pub mod homepage;
 pub mod pages;
+
+/// Makes the signed in user, and what their role allows them to do,
+/// available to templates as `user` and `can`.
+pub fn insert_user(context: &mut tera::Context, user: &User) {
+    context.insert("user", user);
+    context.insert("can", &user.permissions());
+}
+
+/// Like `insert_user`, but `can` describes what the user may do inside one
+/// app, as resolved by `member_of`.
+pub fn insert_member(context: &mut tera::Context, user: &User, member: &User) {
+    context.insert("user", user);
+    context.insert("can", &member.permissions());
+}
+
+/// Resolves the signed in user's role in the app `scope` belongs to. Anyone
+/// who isn't a member gets a 404 response to return instead.
+pub async fn member_of(state: &AppState, user: &User, scope: AppScope) -> Result<User, Response> {
+    let app_member_service = AppMemberService::new(AppMemberRepository::new(&state.db));
+
+    match app_member_service.member(user, scope).await {
+        Ok(member) => Ok(member),
+        Err(sqlx::Error::RowNotFound) => Err(StatusCode::NOT_FOUND.into_response()),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+pub fn forbidden() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        "You don't have permission to do that.",
+    )
+        .into_response()
+}
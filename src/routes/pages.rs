@@ -1,13 +1,25 @@
 use crate::{
-    AppState, models::page::NewPageRequest, repositories::pages::PageRepository,
-    services::pages::PageService,
+    AppState,
+    extractors::{api_key::AppApiKey, current_user::CurrentUser},
+    middleware::etag::etag,
+    models::{
+        api_key::ApiKeyScope,
+        app_member::AppScope,
+        page::{NewPageRequest, PageContentParams},
+    },
+    repositories::pages::PageRepository,
+    routes::{
+        api::error::ApiError, content::render_list_view, forbidden, insert_member, member_of,
+    },
+    services::{content::ContentService, error::ServiceError, pages::PageService},
 };
 use axum::{
     Form, Json, Router,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
-    response::{Html, IntoResponse},
-    routing::{get, put},
+    middleware,
+    response::{Html, IntoResponse, Response},
+    routing::{get, post, put},
 };
 use std::sync::Arc;
 
@@ -17,68 +29,127 @@ pub fn routes() -> Router<Arc<AppState>> {
         Router::new()
             .route("/", put(create_page))
             .route("/{id}", get(index).delete(delete))
-            .route("/{id}/content", get(get_content_for_page))
+            .route("/{id}/publish", post(publish_page))
             .route("/{id}/content/create", get(create_content_page)),
     )
 }
 
+/// Routes that stay reachable without signing in. They still need an API
+/// key, or a preview token, so content can't be read by guessing page ids.
+pub fn public_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/pages/{id}/content", get(get_content_for_page))
+        .layer(middleware::from_fn(etag))
+}
+
 pub async fn create_content_page(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> Html<String> {
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Page(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let page_repository = PageRepository::new(&state.db);
     let page_service = PageService::new(page_repository);
     let context = tera::Context::new();
 
     match page_service.find_by_id(&id).await {
         Ok(page) => {
-            let context = tera::Context::from_serialize(page).unwrap();
+            let mut context = tera::Context::from_serialize(page).unwrap();
+            insert_member(&mut context, &user, &member);
             Html(
                 state
                     .tera
                     .render("pages/create_content.html", &context)
                     .unwrap(),
             )
+            .into_response()
         }
         Err(sqlx::Error::RowNotFound) => {
-            Html(state.tera.render("shared/404.html", &context).unwrap())
+            Html(state.tera.render("shared/404.html", &context).unwrap()).into_response()
         }
-        Err(_) => Html(state.tera.render("shared/500.html", &context).unwrap()),
+        Err(_) => Html(state.tera.render("shared/500.html", &context).unwrap()).into_response(),
     }
 }
 
-pub async fn index(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Html<String> {
+pub async fn index(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Page(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let page_repository = PageRepository::new(&state.db);
     let page_service = PageService::new(page_repository);
 
     match page_service.find_by_id(&id).await {
         Ok(page) => {
-            let context = tera::Context::from_serialize(page).unwrap();
-            Html(state.tera.render("pages/index.html", &context).unwrap())
+            let mut context = tera::Context::from_serialize(&page).unwrap();
+            insert_member(&mut context, &user, &member);
+            context.insert(
+                "preview_token",
+                &page_service.create_preview_token(&page.page.id),
+            );
+            Html(state.tera.render("pages/index.html", &context).unwrap()).into_response()
         }
         Err(sqlx::Error::RowNotFound) => Html(
             state
                 .tera
                 .render("shared/404.html", &tera::Context::new())
                 .unwrap(),
-        ),
+        )
+        .into_response(),
         Err(_) => Html(
             state
                 .tera
                 .render("shared/500.html", &tera::Context::new())
                 .unwrap(),
-        ),
+        )
+        .into_response(),
     }
 }
 
+/// Published content of a page by id, for an API key of the page's app. A
+/// preview token stands in for the key, and shows the drafts instead.
 pub async fn get_content_for_page(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> impl IntoResponse {
+    Query(params): Query<PageContentParams>,
+    api_key: Result<AppApiKey, ApiError>,
+) -> Response {
     let page_repository = PageRepository::new(&state.db);
     let page_service = PageService::new(page_repository);
 
-    match page_service.get_content_for_page(&id).await {
+    let content = match params.preview {
+        Some(token) if page_service.verify_preview_token(&token, &id) => {
+            page_service.get_draft_content_for_page(&id).await
+        }
+        Some(_) => return StatusCode::UNAUTHORIZED.into_response(),
+        None => {
+            let api_key = match api_key {
+                Ok(api_key) => api_key,
+                Err(err) => return err.into_response(),
+            };
+            let page = match page_service.find_by_id(&id).await {
+                Ok(page) => page,
+                Err(sqlx::Error::RowNotFound) => return StatusCode::NOT_FOUND.into_response(),
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            };
+            if let Err(err) = api_key.require(&page.app.id, ApiKeyScope::Read) {
+                return err.into_response();
+            }
+
+            page_service.get_content_for_page(&id).await
+        }
+    };
+
+    match content {
         Ok(content) => Json(content).into_response(),
         Err(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND.into_response(),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
@@ -86,9 +157,15 @@ pub async fn get_content_for_page(
 }
 
 pub async fn create_page(
+    CurrentUser(user): CurrentUser,
     State(state): State<Arc<AppState>>,
     Form(request): Form<NewPageRequest>,
-) -> impl IntoResponse {
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::App(request.app_id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let page_repository = PageRepository::new(&state.db);
     let page_service = PageService::new(page_repository);
 
@@ -96,7 +173,7 @@ pub async fn create_page(
     context.insert("app", &serde_json::json!({ "id": request.app_id }));
     context.insert("page", &request);
 
-    let result = page_service.create_page(request).await;
+    let result = page_service.create_page(&member, request).await;
     let mut error_message = |msg| {
         context.insert("error", &msg);
         Html(
@@ -113,23 +190,63 @@ pub async fn create_page(
             StatusCode::OK,
         )
             .into_response(),
-        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+        Err(ServiceError::Forbidden(_)) => {
+            error_message("You don't have permission to create pages.").into_response()
+        }
+        Err(err @ ServiceError::Validation(_)) => error_message(&err.to_string()).into_response(),
+        Err(ServiceError::Database(sqlx::Error::Database(err))) if err.is_unique_violation() => {
             error_message("Page name already exists for this app.").into_response()
         }
-        Err(sqlx::Error::Database(err)) if err.is_foreign_key_violation() => {
+        Err(ServiceError::Database(sqlx::Error::Database(err)))
+            if err.is_foreign_key_violation() =>
+        {
             error_message("The provided App ID is not valid.").into_response()
         }
         Err(_) => error_message("Something went wrong creating the page.").into_response(),
     }
 }
 
-pub async fn delete(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> impl IntoResponse {
+pub async fn delete(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Page(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
     let page_repository = PageRepository::new(&state.db);
     let page_service = PageService::new(page_repository);
 
-    match page_service.delete_page(&id).await {
+    match page_service.delete_page(&member, &id).await {
         Ok(_) => Html("").into_response(),
-        Err(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND.into_response(),
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn publish_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let member = match member_of(&state, &user, AppScope::Page(id)).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    let content_service = ContentService::new(&state.db);
+
+    match content_service.publish_page(&member, &id).await {
+        Ok(_) => render_list_view(&state, &member, id).await,
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
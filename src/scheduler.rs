@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use sqlx::SqlitePool;
+
+use crate::{
+    services::content::ContentService,
+    user::{
+        lockout::SignInAttemptRepository, passkey::PasskeyRepository, session::SessionRepository,
+    },
+};
+
+/// Periodically applies scheduled publish and unpublish transitions.
+/// Delivery already honours the windows on read, so this only has to keep
+/// the stored state (and the revision history) in step with the clock.
+/// Sessions and passkey challenges that can no longer be used, and old sign
+/// in attempts, are cleared out on the same tick.
+pub async fn run(db: SqlitePool, every: Duration) {
+    let content_service = ContentService::new(&db);
+    let session_repository = SessionRepository::new(&db);
+    let passkey_repository = PasskeyRepository::new(&db);
+    let sign_in_attempt_repository = SignInAttemptRepository::new(&db);
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        interval.tick().await;
+
+        match content_service.apply_schedule().await {
+            Ok((0, 0)) => {}
+            Ok((published, unpublished)) => tracing::info!(
+                "published {} and unpublished {} content entries",
+                published,
+                unpublished
+            ),
+            Err(err) => tracing::error!("failed to apply schedule: {}", err),
+        }
+
+        if let Err(err) = session_repository.delete_expired().await {
+            tracing::error!("failed to clear old sessions: {}", err);
+        }
+
+        if let Err(err) = passkey_repository.delete_expired_challenges().await {
+            tracing::error!("failed to clear old passkey challenges: {}", err);
+        }
+
+        if let Err(err) = sign_in_attempt_repository.delete_old().await {
+            tracing::error!("failed to clear old sign in attempts: {}", err);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{
+    models::app_member::{AppMember, AppScope, NewMemberRequest, UpdateMemberRequest},
+    repositories::app_members::AppMemberRepository,
+    services::error::{ServiceError, authorize},
+    user::{
+        User,
+        role::{Permission, Role},
+    },
+};
+
+pub struct AppMemberService {
+    app_member_repository: AppMemberRepository,
+}
+
+impl AppMemberService {
+    pub fn new(app_member_repository: AppMemberRepository) -> Self {
+        AppMemberService {
+            app_member_repository,
+        }
+    }
+
+    /// Returns the user as they act inside one app, with their role swapped
+    /// for the one their membership grants. Admins keep access to every app.
+    /// Non-members get `RowNotFound`, so an app they can't see looks the same
+    /// as one that doesn't exist.
+    pub async fn member(&self, user: &User, scope: AppScope) -> Result<User, sqlx::Error> {
+        if user.role == Role::Admin {
+            return Ok(user.clone());
+        }
+
+        let role = self
+            .app_member_repository
+            .find_role(scope, &user.id)
+            .await?;
+
+        Ok(User {
+            role,
+            ..user.clone()
+        })
+    }
+
+    pub async fn find_all_by_app_id(&self, app_id: &i64) -> Result<Vec<AppMember>, sqlx::Error> {
+        self.app_member_repository.find_all_by_app_id(app_id).await
+    }
+
+    pub async fn add_member(
+        &self,
+        member: &User,
+        app_id: &i64,
+        request: NewMemberRequest,
+    ) -> Result<(), ServiceError> {
+        authorize(member, Permission::ManageMembers)?;
+        Ok(self
+            .app_member_repository
+            .add_member_by_email(app_id, request.email.trim(), request.role)
+            .await?)
+    }
+
+    pub async fn update_member(
+        &self,
+        member: &User,
+        app_id: &i64,
+        user_id: &i64,
+        request: UpdateMemberRequest,
+    ) -> Result<(), ServiceError> {
+        authorize(member, Permission::ManageMembers)?;
+        if *user_id == member.id {
+            return Err(ServiceError::Invalid(
+                "You can't change your own role.".to_string(),
+            ));
+        }
+
+        Ok(self
+            .app_member_repository
+            .update_role(app_id, user_id, request.role)
+            .await?)
+    }
+
+    pub async fn remove_member(
+        &self,
+        member: &User,
+        app_id: &i64,
+        user_id: &i64,
+    ) -> Result<(), ServiceError> {
+        authorize(member, Permission::ManageMembers)?;
+        if *user_id == member.id {
+            return Err(ServiceError::Invalid(
+                "You can't remove yourself from an app.".to_string(),
+            ));
+        }
+
+        Ok(self
+            .app_member_repository
+            .remove_member(app_id, user_id)
+            .await?)
+    }
+}
@@ -1,9 +1,14 @@
 use crate::{
     models::{
-        app::{App, AppSearch, AppWithPages, CreateAppForm},
+        app::{App, AppSearch, AppWithPages, CreateAppForm, UpdateAppRequest},
         page::Page,
     },
     repositories::apps::AppRepository,
+    services::error::{FieldError, ServiceError, authorize, validated},
+    user::{
+        User,
+        role::{Permission, Role},
+    },
 };
 
 pub struct AppService {
@@ -15,23 +20,82 @@ impl AppService {
         AppService { app_repository }
     }
 
-    pub async fn find_by_id(&self, app_id: &i64) -> Result<AppWithPages, sqlx::Error> {
-        self.app_repository.find_by_id(app_id).await
+    pub async fn find_by_id(&self, user: &User, app_id: &i64) -> Result<AppWithPages, sqlx::Error> {
+        self.app_repository
+            .find_by_id(app_id, membership_filter(user))
+            .await
+    }
+
+    pub async fn find_by_name(&self, name: &str) -> Result<App, sqlx::Error> {
+        self.app_repository.find_by_name(name).await
     }
 
-    pub async fn search(&self, params: &AppSearch) -> Result<Vec<App>, sqlx::Error> {
-        self.app_repository.search(&params.name).await
+    pub async fn search(&self, user: &User, params: &AppSearch) -> Result<Vec<App>, sqlx::Error> {
+        self.app_repository
+            .search(&params.name, membership_filter(user))
+            .await
     }
 
     pub async fn find_pages_by_app_id(&self, app_id: &str) -> Result<Vec<Page>, sqlx::Error> {
         self.app_repository.find_pages_by_app_id(app_id).await
     }
 
-    pub async fn create_app(&self, request: CreateAppForm) -> Result<App, sqlx::Error> {
-        self.app_repository.create_app(request).await
+    pub async fn create_app(
+        &self,
+        user: &User,
+        request: CreateAppForm,
+    ) -> Result<App, ServiceError> {
+        authorize(user, Permission::ManageApps)?;
+        validated(validate_app(&request.name, &request.url))?;
+        Ok(self
+            .app_repository
+            .create_app(request, &user.id, Role::Admin)
+            .await?)
     }
 
-    pub async fn delete_app(&self, app_id: &str) -> Result<(), sqlx::Error> {
-        self.app_repository.delete_app(app_id).await
+    pub async fn update_app(
+        &self,
+        user: &User,
+        app_id: &i64,
+        request: UpdateAppRequest,
+    ) -> Result<App, ServiceError> {
+        authorize(user, Permission::ManageApps)?;
+        validated(validate_app(&request.name, &request.url))?;
+        Ok(self.app_repository.update_app(app_id, request).await?)
     }
+
+    pub async fn delete_app(&self, user: &User, app_id: &i64) -> Result<(), ServiceError> {
+        authorize(user, Permission::ManageApps)?;
+        Ok(self.app_repository.delete_app(app_id).await?)
+    }
+}
+
+/// Longest name an app can have.
+const MAX_APP_NAME_LENGTH: usize = 50;
+
+fn validate_app(name: &str, url: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let name = name.trim();
+    if name.is_empty() {
+        errors.push(FieldError::new("name", "Give the app a name."));
+    } else if name.chars().count() > MAX_APP_NAME_LENGTH {
+        errors.push(FieldError::new(
+            "name",
+            format!("Keep the name to {} characters.", MAX_APP_NAME_LENGTH),
+        ));
+    }
+    if !url.is_empty() && !url.starts_with("https://") && !url.starts_with("http://") {
+        errors.push(FieldError::new(
+            "url",
+            "The URL should start with http:// or https://.",
+        ));
+    }
+
+    errors
+}
+
+// admins can see every app, everyone else only the apps they belong to. API
+// keys have already been held to their own app by the management API
+fn membership_filter(user: &User) -> Option<i64> {
+    (user.role != Role::Admin && user.api_key_id.is_none()).then_some(user.id)
 }
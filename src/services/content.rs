@@ -1,20 +1,34 @@
+use chrono::NaiveDateTime;
+use similar::{ChangeTag, TextDiff};
 use sqlx::SqlitePool;
 
 use crate::{
-    models::content::{Content, FullContent, NewContentRequest, UpdateContentRequest},
-    repositories::{content::ContentRepository, pages::PageRepository},
+    models::content::{
+        Content, ContentVersion, DiffLine, FullContent, NewContentRequest, UpdateContentRequest,
+        VersionAction, VersionDiff,
+    },
+    repositories::{
+        content::ContentRepository, content_versions::ContentVersionRepository,
+        pages::PageRepository,
+    },
+    services::error::{FieldError, ServiceError, authorize, validated},
+    user::{User, role::Permission},
 };
 
 pub struct ContentService {
+    db: SqlitePool,
     content_repository: ContentRepository,
+    content_version_repository: ContentVersionRepository,
     page_repository: PageRepository,
 }
 
 impl ContentService {
     pub fn new(db: &SqlitePool) -> Self {
         ContentService {
-            content_repository: ContentRepository::new(&db),
-            page_repository: PageRepository::new(&db),
+            db: db.clone(),
+            content_repository: ContentRepository::new(db),
+            content_version_repository: ContentVersionRepository::new(db),
+            page_repository: PageRepository::new(db),
         }
     }
 
@@ -35,20 +49,347 @@ impl ContentService {
 
     pub async fn create_content(
         &self,
+        user: &User,
         mut request: NewContentRequest,
-    ) -> Result<Content, sqlx::Error> {
+    ) -> Result<Content, ServiceError> {
+        authorize(user, Permission::EditContent)?;
+        if request.publish {
+            authorize(user, Permission::PublishContent)?;
+        }
+        // scheduling is a form of publishing, editors can only write drafts
+        if !user.can(Permission::PublishContent) {
+            request.publish_at = None;
+            request.unpublish_at = None;
+        }
+        validated(validate_content(
+            &request.name,
+            request.publish_at.as_deref(),
+            request.unpublish_at.as_deref(),
+        ))?;
+
         request.name = slug::slugify(&request.name).replace("-", "_");
-        self.content_repository.create_content(&request).await
+        let mut tx = self.db.begin().await?;
+        let content = self
+            .content_repository
+            .create_content(&mut tx, &request)
+            .await?;
+        self.content_version_repository
+            .create_version(&mut tx, &content, VersionAction::Create, user.author_id())
+            .await?;
+        tx.commit().await?;
+
+        if request.publish {
+            return self.publish_content(user, &content.id).await;
+        }
+
+        Ok(content)
     }
 
     pub async fn update_content(
         &self,
-        request: UpdateContentRequest,
-    ) -> Result<Content, sqlx::Error> {
-        self.content_repository.update_content(request).await
+        user: &User,
+        mut request: UpdateContentRequest,
+    ) -> Result<Content, ServiceError> {
+        authorize(user, Permission::EditContent)?;
+        if request.publish {
+            authorize(user, Permission::PublishContent)?;
+        }
+        // editors never see the schedule fields, so carry the existing
+        // schedule over rather than clearing it
+        if !user.can(Permission::PublishContent) {
+            let existing = self
+                .content_repository
+                .find_by_id(&request.content_id)
+                .await?;
+            request.publish_at = existing.publish_at;
+            request.unpublish_at = existing.unpublish_at;
+        }
+        validated(validate_content(
+            &request.name,
+            request.publish_at.as_deref(),
+            request.unpublish_at.as_deref(),
+        ))?;
+
+        let publish = request.publish;
+        // only someone who may publish can decide what a scheduled publish
+        // ships, an editor's changes wait for the next explicit publish
+        let snapshot = user.can(Permission::PublishContent);
+        let mut tx = self.db.begin().await?;
+        let content = self
+            .content_repository
+            .update_content(&mut tx, request, snapshot)
+            .await?;
+        self.content_version_repository
+            .create_version(&mut tx, &content, VersionAction::Update, user.author_id())
+            .await?;
+        tx.commit().await?;
+
+        if publish {
+            return self.publish_content(user, &content.id).await;
+        }
+
+        Ok(content)
+    }
+
+    pub async fn publish_content(&self, user: &User, id: &i64) -> Result<Content, ServiceError> {
+        authorize(user, Permission::PublishContent)?;
+
+        let mut tx = self.db.begin().await?;
+        let content = self.content_repository.publish_content(&mut tx, id).await?;
+        self.content_version_repository
+            .create_version(&mut tx, &content, VersionAction::Publish, user.author_id())
+            .await?;
+        tx.commit().await?;
+
+        Ok(content)
+    }
+
+    /// Publishes every entry on the page that has unpublished changes and
+    /// returns the entries that were promoted.
+    pub async fn publish_page(
+        &self,
+        user: &User,
+        page_id: &i64,
+    ) -> Result<Vec<Content>, ServiceError> {
+        authorize(user, Permission::PublishContent)?;
+
+        let mut tx = self.db.begin().await?;
+        let published = self
+            .content_repository
+            .publish_all_by_page_id(&mut tx, page_id)
+            .await?;
+
+        for content in &published {
+            self.content_version_repository
+                .create_version(&mut tx, content, VersionAction::Publish, user.author_id())
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(published)
+    }
+
+    pub async fn delete_content(&self, user: &User, id: &i64) -> Result<(), ServiceError> {
+        authorize(user, Permission::EditContent)?;
+
+        let content = self.content_repository.find_by_id(id).await?;
+        let mut tx = self.db.begin().await?;
+        self.content_repository.delete_content(&mut tx, id).await?;
+        self.content_version_repository
+            .create_version(&mut tx, &content, VersionAction::Delete, user.author_id())
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
     }
 
-    pub async fn delete_content(&self, id: &i64) -> Result<(), sqlx::Error> {
-        self.content_repository.delete_content(id).await
+    /// Applies every publishing transition that has come due. Returns the
+    /// number of entries that were published and unpublished.
+    pub async fn apply_schedule(&self) -> Result<(usize, usize), sqlx::Error> {
+        let mut tx = self.db.begin().await?;
+        let published = self.content_repository.publish_due(&mut tx).await?;
+        for content in &published {
+            // record what went live, which may be older than the draft
+            let published = Content {
+                body: content.published_body.clone().unwrap_or_default(),
+                ..content.clone()
+            };
+            self.content_version_repository
+                .create_version(&mut tx, &published, VersionAction::Publish, None)
+                .await?;
+        }
+
+        let unpublished = self.content_repository.unpublish_due(&mut tx).await?;
+        for content in &unpublished {
+            self.content_version_repository
+                .create_version(&mut tx, content, VersionAction::Unpublish, None)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok((published.len(), unpublished.len()))
     }
+
+    pub async fn history(&self, content_id: &i64) -> Result<Vec<ContentVersion>, sqlx::Error> {
+        let versions = self
+            .content_version_repository
+            .find_all_by_content_id(content_id)
+            .await?;
+
+        if versions.is_empty() {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Ok(versions)
+    }
+
+    pub async fn find_version(
+        &self,
+        content_id: &i64,
+        version_id: &i64,
+    ) -> Result<ContentVersion, sqlx::Error> {
+        let version = self
+            .content_version_repository
+            .find_by_id(version_id)
+            .await?;
+
+        // versions are addressed through their content, so never hand back one
+        // that belongs to a different entry
+        if version.content_id != *content_id {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Ok(version)
+    }
+
+    pub async fn diff_versions(
+        &self,
+        content_id: &i64,
+        from: &i64,
+        to: &i64,
+    ) -> Result<VersionDiff, sqlx::Error> {
+        let from = self.find_version(content_id, from).await?;
+        let to = self.find_version(content_id, to).await?;
+        let lines = side_by_side(&from.body, &to.body);
+
+        Ok(VersionDiff { from, to, lines })
+    }
+
+    /// Restoring never rewrites history; the old revision is copied forward
+    /// into the content row and recorded as a brand new revision.
+    pub async fn restore_version(
+        &self,
+        user: &User,
+        content_id: &i64,
+        version_id: &i64,
+    ) -> Result<Content, ServiceError> {
+        authorize(user, Permission::EditContent)?;
+
+        let version = self.find_version(content_id, version_id).await?;
+        let mut tx = self.db.begin().await?;
+        let content = self
+            .content_repository
+            .restore_content(&mut tx, &version)
+            .await?;
+        self.content_version_repository
+            .create_version(&mut tx, &content, VersionAction::Restore, user.author_id())
+            .await?;
+        tx.commit().await?;
+
+        Ok(content)
+    }
+}
+
+/// Lines up a line based diff of two bodies into rows for a two column view.
+/// Runs of deletions are paired with the insertions that follow them so that
+/// a changed line shows up as a single row.
+fn side_by_side(old: &str, new: &str) -> Vec<DiffLine> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut lines = Vec::new();
+    let mut deleted: Vec<(usize, String)> = Vec::new();
+    let mut inserted: Vec<(usize, String)> = Vec::new();
+
+    let flush = |lines: &mut Vec<DiffLine>,
+                 deleted: &mut Vec<(usize, String)>,
+                 inserted: &mut Vec<(usize, String)>| {
+        let rows = deleted.len().max(inserted.len());
+        let mut deleted = deleted.drain(..);
+        let mut inserted = inserted.drain(..);
+        for _ in 0..rows {
+            let left = deleted.next();
+            let right = inserted.next();
+            let kind = match (&left, &right) {
+                (Some(_), Some(_)) => "replace",
+                (Some(_), None) => "delete",
+                _ => "insert",
+            };
+            lines.push(DiffLine {
+                kind: kind.to_string(),
+                left_number: left.as_ref().map(|(n, _)| n + 1),
+                left: left.map(|(_, l)| l),
+                right_number: right.as_ref().map(|(n, _)| n + 1),
+                right: right.map(|(_, l)| l),
+            });
+        }
+    };
+
+    for change in diff.iter_all_changes() {
+        let value = change.value().trim_end_matches(['\r', '\n']).to_string();
+        match change.tag() {
+            ChangeTag::Delete => {
+                deleted.push((change.old_index().unwrap_or_default(), value));
+            }
+            ChangeTag::Insert => {
+                inserted.push((change.new_index().unwrap_or_default(), value));
+            }
+            ChangeTag::Equal => {
+                flush(&mut lines, &mut deleted, &mut inserted);
+                lines.push(DiffLine {
+                    kind: "equal".to_string(),
+                    left_number: change.old_index().map(|n| n + 1),
+                    left: Some(value.clone()),
+                    right_number: change.new_index().map(|n| n + 1),
+                    right: Some(value),
+                });
+            }
+        }
+    }
+    flush(&mut lines, &mut deleted, &mut inserted);
+
+    lines
+}
+
+fn validate_content(
+    name: &str,
+    publish_at: Option<&str>,
+    unpublish_at: Option<&str>,
+) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if slug::slugify(name).is_empty() {
+        errors.push(FieldError::new(
+            "name",
+            "Give the content a name with at least one letter or number.",
+        ));
+    }
+
+    let publish_at = parse_schedule("publish_at", publish_at, &mut errors);
+    let unpublish_at = parse_schedule("unpublish_at", unpublish_at, &mut errors);
+    if let (Some(publish_at), Some(unpublish_at)) = (publish_at, unpublish_at)
+        && unpublish_at <= publish_at
+    {
+        errors.push(FieldError::new(
+            "unpublish_at",
+            "Unpublish the content after it's published.",
+        ));
+    }
+
+    errors
+}
+
+// the form sends `datetime-local` values and the database hands back its own
+// format, both are accepted, with or without seconds. Blank means unscheduled.
+fn parse_schedule(
+    field: &'static str,
+    value: Option<&str>,
+    errors: &mut Vec<FieldError>,
+) -> Option<NaiveDateTime> {
+    let value = value.map(str::trim).filter(|v| !v.is_empty())?;
+    let value = value.trim_end_matches('Z');
+    let parsed = [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok());
+
+    if parsed.is_none() {
+        errors.push(FieldError::new(
+            field,
+            "Use a UTC date and time like 2025-07-01T09:30.",
+        ));
+    }
+
+    parsed
 }
@@ -0,0 +1,83 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::user::{User, role::Permission};
+
+/// A problem with one field of a request, for forms and API clients to show
+/// next to that field.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        FieldError {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+/// Errors from service calls that act on behalf of a user.
+#[derive(Debug)]
+pub enum ServiceError {
+    Forbidden(Permission),
+    /// The request breaks a rule the service enforces. The message is meant
+    /// to be shown to the user.
+    Invalid(String),
+    /// One or more fields of the request failed validation.
+    Validation(Vec<FieldError>),
+    Database(sqlx::Error),
+    Hashing(argon2::password_hash::Error),
+}
+
+impl From<sqlx::Error> for ServiceError {
+    fn from(err: sqlx::Error) -> Self {
+        ServiceError::Database(err)
+    }
+}
+
+impl From<argon2::password_hash::Error> for ServiceError {
+    fn from(err: argon2::password_hash::Error) -> Self {
+        ServiceError::Hashing(err)
+    }
+}
+
+impl std::fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceError::Forbidden(permission) => {
+                write!(f, "missing permission: {:?}", permission)
+            }
+            ServiceError::Invalid(message) => f.write_str(message),
+            ServiceError::Validation(errors) => {
+                let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+                f.write_str(&messages.join(" "))
+            }
+            ServiceError::Database(err) => err.fmt(f),
+            ServiceError::Hashing(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+/// Fails with `ServiceError::Validation` when any errors were collected.
+pub fn validated(errors: Vec<FieldError>) -> Result<(), ServiceError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ServiceError::Validation(errors))
+    }
+}
+
+/// Fails with `ServiceError::Forbidden` unless the user's role grants `permission`.
+pub fn authorize(user: &User, permission: Permission) -> Result<(), ServiceError> {
+    if user.can(permission) {
+        Ok(())
+    } else {
+        Err(ServiceError::Forbidden(permission))
+    }
+}
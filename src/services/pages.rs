@@ -1,6 +1,11 @@
+use chrono::{Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+
 use crate::{
-    models::page::{FullPage, NewPageRequest, Page, PageContent},
+    models::page::{FullPage, NewPageRequest, Page, PageContent, PreviewClaims, UpdatePageRequest},
     repositories::pages::PageRepository,
+    services::error::{FieldError, ServiceError, authorize, validated},
+    user::{User, auth::derived_secret, role::Permission},
 };
 
 pub struct PageService {
@@ -20,6 +25,15 @@ impl PageService {
         self.page_repository.get_content_for_page(page_id).await
     }
 
+    pub async fn get_draft_content_for_page(
+        &self,
+        page_id: &i64,
+    ) -> Result<PageContent, sqlx::Error> {
+        self.page_repository
+            .get_draft_content_for_page(page_id)
+            .await
+    }
+
     pub async fn get_content_for_page_name(
         &self,
         page_name: &str,
@@ -30,11 +44,67 @@ impl PageService {
             .await
     }
 
-    pub async fn create_page(&self, request: NewPageRequest) -> Result<Page, sqlx::Error> {
-        self.page_repository.create_page(request).await
+    pub async fn create_page(
+        &self,
+        user: &User,
+        request: NewPageRequest,
+    ) -> Result<Page, ServiceError> {
+        authorize(user, Permission::CreatePages)?;
+        validated(validate_page_name(&request.name))?;
+        Ok(self.page_repository.create_page(request).await?)
+    }
+
+    /// Renames a page. Anyone who can create pages can rename them.
+    pub async fn update_page(
+        &self,
+        user: &User,
+        page_id: &i64,
+        request: UpdatePageRequest,
+    ) -> Result<Page, ServiceError> {
+        authorize(user, Permission::CreatePages)?;
+        validated(validate_page_name(&request.name))?;
+        Ok(self.page_repository.update_page(page_id, request).await?)
+    }
+
+    pub async fn delete_page(&self, user: &User, page_id: &i64) -> Result<(), ServiceError> {
+        authorize(user, Permission::DeletePages)?;
+        Ok(self.page_repository.delete_page(page_id).await?)
+    }
+
+    /// Issues a signed token that lets its holder read a page's drafts
+    /// through the delivery endpoint for the next week.
+    pub fn create_preview_token(&self, page_id: &i64) -> String {
+        let claims = PreviewClaims {
+            page_id: *page_id,
+            exp: Utc::now()
+                .checked_add_signed(Duration::days(7))
+                .expect("valid timestamp")
+                .timestamp() as usize,
+        };
+        let key = EncodingKey::from_secret(derived_secret("preview").as_bytes());
+
+        encode(&Header::new(Algorithm::HS256), &claims, &key).expect("failed to sign preview token")
     }
 
-    pub async fn delete_page(&self, page_id: &i64) -> Result<(), sqlx::Error> {
-        self.page_repository.delete_page(page_id).await
+    pub fn verify_preview_token(&self, token: &str, page_id: &i64) -> bool {
+        let key = DecodingKey::from_secret(derived_secret("preview").as_bytes());
+
+        match decode::<PreviewClaims>(token, &key, &Validation::default()) {
+            Ok(data) => data.claims.page_id == *page_id,
+            Err(_) => false,
+        }
+    }
+}
+
+// page names end up as slugs, so they're checked after slugifying, the same
+// way they'll be stored
+fn validate_page_name(name: &str) -> Vec<FieldError> {
+    if slug::slugify(name).chars().count() < 3 {
+        vec![FieldError::new(
+            "name",
+            "Page names need at least 3 letters or numbers.",
+        )]
+    } else {
+        vec![]
     }
 }
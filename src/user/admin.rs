@@ -0,0 +1,167 @@
+use crate::{
+    services::error::{ServiceError, authorize},
+    user::{
+        User, UserPage,
+        password::hash_password,
+        password_reset::PasswordResetService,
+        repository::UserRepository,
+        role::{Permission, Role},
+        session::SessionRepository,
+        tokens::TokenRepository,
+    },
+};
+
+/// How many users a page of the admin list shows.
+pub const USERS_PER_PAGE: i64 = 25;
+
+/// What admins can do to other people's accounts. Admins can't disable or
+/// demote themselves, and the last admin can't be disabled or demoted by
+/// anyone, so there's always someone left to manage Wordford.
+pub struct UserAdminService {
+    db: sqlx::SqlitePool,
+    user_repository: UserRepository,
+    session_repository: SessionRepository,
+    token_repository: TokenRepository,
+}
+
+impl UserAdminService {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        UserAdminService {
+            db: db.clone(),
+            user_repository: UserRepository::new(db),
+            session_repository: SessionRepository::new(db),
+            token_repository: TokenRepository::new(db),
+        }
+    }
+
+    /// Users whose name or email contains `query`, a page at a time. Pages
+    /// are numbered from 1, and ones past the end give the last page.
+    pub async fn list(
+        &self,
+        admin: &User,
+        query: &str,
+        page: i64,
+    ) -> Result<UserPage, ServiceError> {
+        authorize(admin, Permission::ManageUsers)?;
+
+        let total = self.user_repository.count_matching(query).await?;
+        let pages = ((total + USERS_PER_PAGE - 1) / USERS_PER_PAGE).max(1);
+        let page = page.clamp(1, pages);
+        let users = self
+            .user_repository
+            .search(query, USERS_PER_PAGE, (page - 1) * USERS_PER_PAGE)
+            .await?;
+
+        Ok(UserPage {
+            users,
+            query: query.trim().to_string(),
+            page,
+            pages,
+            total,
+        })
+    }
+
+    pub async fn change_role(
+        &self,
+        admin: &User,
+        user_id: i64,
+        role: Role,
+    ) -> Result<User, ServiceError> {
+        authorize(admin, Permission::ManageUsers)?;
+        let user = self
+            .other_user(admin, user_id, "change your own role")
+            .await?;
+
+        if user.role == Role::Admin && role != Role::Admin {
+            self.keep_an_admin(&user).await?;
+        }
+        self.user_repository.update_role(user.id, role).await?;
+
+        Ok(User { role, ..user })
+    }
+
+    /// Stops the user signing in and ends the sessions they have.
+    pub async fn disable(&self, admin: &User, user_id: i64) -> Result<User, ServiceError> {
+        authorize(admin, Permission::ManageUsers)?;
+        let user = self.other_user(admin, user_id, "disable yourself").await?;
+
+        if user.role == Role::Admin {
+            self.keep_an_admin(&user).await?;
+        }
+        self.user_repository.set_disabled(user.id, true).await?;
+        self.session_repository.revoke_all_for_user(user.id).await?;
+
+        Ok(self.user_repository.find_by_id(user.id).await?)
+    }
+
+    pub async fn enable(&self, admin: &User, user_id: i64) -> Result<User, ServiceError> {
+        authorize(admin, Permission::ManageUsers)?;
+
+        self.user_repository.set_disabled(user_id, false).await?;
+
+        Ok(self.user_repository.find_by_id(user_id).await?)
+    }
+
+    /// Replaces the user's password with a random one nobody knows, signs
+    /// them out and revokes their access tokens, for when it may have
+    /// leaked. Returns the user and a
+    /// reset token to send them, so they can choose a new one.
+    pub async fn force_password_reset(
+        &self,
+        admin: &User,
+        user_id: i64,
+    ) -> Result<(User, String), ServiceError> {
+        authorize(admin, Permission::ManageUsers)?;
+        let user = self.user_repository.find_by_id(user_id).await?;
+
+        let password = hex::encode(rand::random::<[u8; 32]>());
+        let password_hash = hash_password(&password).await?;
+        self.user_repository
+            .update_password(user.id, &password_hash)
+            .await?;
+        self.session_repository.revoke_all_for_user(user.id).await?;
+        self.token_repository.revoke_all_for_user(user.id).await?;
+
+        let password_reset_service = PasswordResetService::new(&self.db);
+        match password_reset_service.issue_token(&user.email).await? {
+            Some((user, token)) => Ok((user, token)),
+            None => Err(sqlx::Error::RowNotFound.into()),
+        }
+    }
+
+    /// Ends every session the user has and revokes their access tokens, so
+    /// that nothing signed in as them keeps working. Returns how many
+    /// sessions and tokens there were.
+    pub async fn sign_out(&self, admin: &User, user_id: i64) -> Result<(u64, u64), ServiceError> {
+        authorize(admin, Permission::ManageUsers)?;
+
+        let sessions = self.session_repository.revoke_all_for_user(user_id).await?;
+        let tokens = self.token_repository.revoke_all_for_user(user_id).await?;
+
+        Ok((sessions, tokens))
+    }
+
+    async fn other_user(
+        &self,
+        admin: &User,
+        user_id: i64,
+        action: &str,
+    ) -> Result<User, ServiceError> {
+        if admin.id == user_id {
+            return Err(ServiceError::Invalid(format!("You can't {}.", action)));
+        }
+
+        Ok(self.user_repository.find_by_id(user_id).await?)
+    }
+
+    async fn keep_an_admin(&self, user: &User) -> Result<(), ServiceError> {
+        if !user.is_disabled() && self.user_repository.count_admins().await? <= 1 {
+            return Err(ServiceError::Invalid(format!(
+                "{} is the only admin. Make someone else an admin first.",
+                user.email
+            )));
+        }
+
+        Ok(())
+    }
+}
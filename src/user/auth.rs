@@ -4,56 +4,289 @@ use chrono::{Duration, Utc};
 use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
 use serde::{Deserialize, Serialize};
 
-pub async fn hash_password(password: &str) -> String {
-    bcrypt::hash(password, bcrypt::DEFAULT_COST).expect("Failed to hash password")
+use crate::{
+    services::error::ServiceError,
+    user::{
+        PasskeySignInRequest, User,
+        lockout::{LockoutService, Throttle},
+        passkey::PasskeyService,
+        password::{hash_password, needs_rehash, verify_password},
+        repository::UserRepository,
+        session::SessionRepository,
+        two_factor::TwoFactorService,
+    },
+};
+
+/// How long a sign in lasts, both for the token and the cookie carrying it.
+pub const SESSION_LIFETIME: Duration = Duration::days(1);
+
+/// The secret session tokens are signed with.
+pub fn jwt_secret() -> String {
+    env::var("JWT_SECRET").expect("JWT_SECRET must be present")
+}
+
+/// The secret for tokens made for one `purpose`, like `"preview"`. Every
+/// purpose signs with its own, so a preview link, an emailed link or a
+/// sign in challenge can never pass for a session token, or for each other.
+pub fn derived_secret(purpose: &str) -> String {
+    format!("{}:{}", purpose, jwt_secret())
+}
+
+/// What happened when someone tried to sign in.
+pub enum LoginOutcome {
+    /// The session token for the now signed in user.
+    Success(String),
+    InvalidCredentials,
+    /// The password was right, but the email address hasn't been verified.
+    Unverified,
+    /// The password was right, and now a code is needed. Holds the challenge
+    /// to pass along to `complete_two_factor`.
+    TwoFactorRequired(String),
+    /// Too many recent failures, so the password wasn't checked.
+    Throttled(Throttle),
+    /// The password was right, but an admin has disabled the account.
+    Disabled,
+}
+
+/// What happened when someone an identity provider vouched for signed in.
+pub enum IdentityLogin {
+    /// The session token for the now signed in user.
+    Session(String),
+    /// A code is needed first. Holds the challenge to pass along to
+    /// `complete_two_factor`.
+    TwoFactor(String),
+}
+
+/// The error for signing in to an account an admin has disabled.
+pub fn disabled() -> ServiceError {
+    ServiceError::Invalid(
+        "This account has been disabled. Ask an admin if you think that's a mistake.".to_string(),
+    )
 }
 
 pub struct AuthService {
     db: sqlx::SqlitePool,
+    session_repository: SessionRepository,
+    two_factor_service: TwoFactorService,
+    lockout_service: LockoutService,
 }
 
 impl AuthService {
     pub fn new(db: sqlx::SqlitePool) -> Self {
-        AuthService { db }
+        let session_repository = SessionRepository::new(&db);
+        let two_factor_service = TwoFactorService::new(&db);
+        let lockout_service = LockoutService::new(&db);
+        AuthService {
+            db,
+            session_repository,
+            two_factor_service,
+            lockout_service,
+        }
     }
 
-    pub async fn login(&self, email: &str, password: &str) -> Result<Option<String>, sqlx::Error> {
+    /// Checks a password sign in from `ip`. Failures count towards slowing
+    /// down further attempts on the account and from the address.
+    pub async fn login(
+        &self,
+        email: &str,
+        password: &str,
+        ip: &str,
+    ) -> Result<LoginOutcome, sqlx::Error> {
+        if let Some(throttle) = self.lockout_service.check(email, ip).await? {
+            return Ok(LoginOutcome::Throttled(throttle));
+        }
+
         let user = sqlx::query!(
-            "SELECT id, email, password_hash FROM users WHERE email = ?",
+            "SELECT id, email, password_hash, email_verified_at, disabled_at FROM users WHERE email = ?",
             email
         )
         .fetch_optional(&self.db)
         .await?;
 
-        if let Some(user) = user {
-            if AuthService::verify_password(password, &user.password_hash).await {
-                let expiration = Utc::now()
-                    .checked_add_signed(Duration::days(1))
-                    .expect("valid timestamp")
-                    .timestamp() as usize;
-                let claims = UserClaims {
-                    sub: user.id.unwrap().to_string(),
-                    email: user.email.to_string(),
-                    exp: expiration,
-                };
-                let header = Header::new(Algorithm::HS256);
-                let encoding_key =
-                    EncodingKey::from_secret(env::var("JWT_SECRET").unwrap().as_bytes());
-
-                let token = encode(&header, &claims, &encoding_key)
-                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
-
-                Ok(Some(token))
-            } else {
-                Ok(None)
+        let user = match user {
+            Some(user) if verify_password(password, &user.password_hash).await => user,
+            user => {
+                let user_id = user.and_then(|user| user.id);
+                let throttle = self
+                    .lockout_service
+                    .record_failure(email, user_id, ip)
+                    .await?;
+                return Ok(match throttle {
+                    Some(throttle @ Throttle::Locked(_)) => LoginOutcome::Throttled(throttle),
+                    _ => LoginOutcome::InvalidCredentials,
+                });
+            }
+        };
+
+        let user_id = user.id.expect("id should not be null");
+        if needs_rehash(&user.password_hash) {
+            self.rehash_password(user_id, password).await;
+        }
+
+        if user.disabled_at.is_some() {
+            return Ok(LoginOutcome::Disabled);
+        }
+
+        if user.email_verified_at.is_none() {
+            self.lockout_service
+                .record_success(email, user_id, ip)
+                .await?;
+            return Ok(LoginOutcome::Unverified);
+        }
+
+        // failures only clear once a session starts, so that knowing the
+        // password doesn't buy unlimited guesses at the code
+        if self.two_factor_service.is_enabled(user_id).await? {
+            let challenge = self.two_factor_service.create_challenge(user_id);
+            return Ok(LoginOutcome::TwoFactorRequired(challenge));
+        }
+
+        self.lockout_service
+            .record_success(email, user_id, ip)
+            .await?;
+        let token = self.start_session(user_id, &user.email).await?;
+
+        Ok(LoginOutcome::Success(token))
+    }
+
+    /// The second step of signing in, for users with two-factor
+    /// authentication. Only a valid code for the challenge starts a session.
+    pub async fn complete_two_factor(
+        &self,
+        challenge: &str,
+        code: &str,
+        ip: &str,
+    ) -> Result<String, ServiceError> {
+        let Some(user_id) = self.two_factor_service.decode_challenge(challenge) else {
+            return Err(ServiceError::Invalid(
+                "That took too long. Please sign in again.".to_string(),
+            ));
+        };
+
+        let user = UserRepository::new(&self.db).find_by_id(user_id).await?;
+        if user.is_disabled() {
+            return Err(disabled());
+        }
+        if let Some(throttle) = self.lockout_service.check(&user.email, ip).await? {
+            return Err(ServiceError::Invalid(throttle.message()));
+        }
+
+        if !self.two_factor_service.verify_code(user_id, code).await? {
+            let throttle = self
+                .lockout_service
+                .record_failure(&user.email, Some(user_id), ip)
+                .await?;
+            return Err(ServiceError::Invalid(match throttle {
+                Some(throttle @ Throttle::Locked(_)) => throttle.message(),
+                _ => "That code isn't right. Please try again.".to_string(),
+            }));
+        }
+
+        self.lockout_service
+            .record_success(&user.email, user_id, ip)
+            .await?;
+        Ok(self.start_session(user.id, &user.email).await?)
+    }
+
+    /// Signs in with a passkey instead of a password. A passkey already
+    /// proves both who someone is and that they have their device, so no
+    /// two-factor code is asked for. Like with a password, the email has to
+    /// be verified first.
+    pub async fn login_with_passkey(
+        &self,
+        request: &PasskeySignInRequest,
+    ) -> Result<String, ServiceError> {
+        let passkey_service = PasskeyService::new(&self.db);
+        let user_id = passkey_service.authenticate(request).await?;
+
+        let user = UserRepository::new(&self.db).find_by_id(user_id).await?;
+        if user.is_disabled() {
+            return Err(disabled());
+        }
+        if !user.is_verified() {
+            return Err(ServiceError::Invalid(
+                "Please verify your email address before signing in. Check your inbox for the link we sent you.".to_string(),
+            ));
+        }
+        Ok(self.start_session(user.id, &user.email).await?)
+    }
+
+    /// Signs in someone an identity provider vouched for. The provider only
+    /// stands in for their password, so two-factor authentication still
+    /// applies.
+    pub async fn login_with_identity(&self, user: &User) -> Result<IdentityLogin, sqlx::Error> {
+        if self.two_factor_service.is_enabled(user.id).await? {
+            let challenge = self.two_factor_service.create_challenge(user.id);
+            return Ok(IdentityLogin::TwoFactor(challenge));
+        }
+
+        let token = self.start_session(user.id, &user.email).await?;
+        Ok(IdentityLogin::Session(token))
+    }
+
+    /// Records a new session for the user and returns its signed token.
+    pub async fn start_session(&self, user_id: i64, email: &str) -> Result<String, sqlx::Error> {
+        let expiration = Utc::now()
+            .checked_add_signed(SESSION_LIFETIME)
+            .expect("valid timestamp")
+            .timestamp() as usize;
+        let jti = hex::encode(rand::random::<[u8; 16]>());
+        self.session_repository
+            .create_session(&jti, user_id, SESSION_LIFETIME)
+            .await?;
+
+        let claims = UserClaims {
+            sub: user_id.to_string(),
+            email: email.to_string(),
+            exp: expiration,
+            jti,
+        };
+        let header = Header::new(Algorithm::HS256);
+        let encoding_key = EncodingKey::from_secret(jwt_secret().as_bytes());
+
+        encode(&header, &claims, &encoding_key).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+    }
+
+    /// Whether the session a token was issued for can still be used.
+    pub async fn is_session_active(&self, claims: &UserClaims) -> Result<bool, sqlx::Error> {
+        match claims.sub.parse::<i64>() {
+            Ok(user_id) => {
+                self.session_repository
+                    .is_active(&claims.jti, user_id)
+                    .await
             }
-        } else {
-            Ok(None)
+            Err(_) => Ok(false),
         }
     }
 
-    async fn verify_password(password: &str, hashed: &str) -> bool {
-        bcrypt::verify(password, hashed).unwrap_or(false)
+    pub async fn logout(&self, session_id: &str) -> Result<(), sqlx::Error> {
+        self.session_repository.revoke(session_id).await
+    }
+
+    /// Revokes every session the user has, on any device.
+    pub async fn logout_everywhere(&self, user_id: i64) -> Result<u64, sqlx::Error> {
+        self.session_repository.revoke_all_for_user(user_id).await
+    }
+
+    /// Replaces an old bcrypt hash, or one made with outdated parameters,
+    /// now that the password is at hand. Failing to is no reason to stop
+    /// someone signing in, so errors are only logged.
+    async fn rehash_password(&self, user_id: i64, password: &str) {
+        let password_hash = match hash_password(password).await {
+            Ok(password_hash) => password_hash,
+            Err(err) => {
+                tracing::warn!("failed to rehash password: {}", err);
+                return;
+            }
+        };
+
+        let user_repository = UserRepository::new(&self.db);
+        if let Err(err) = user_repository
+            .update_password(user_id, &password_hash)
+            .await
+        {
+            tracing::warn!("failed to store rehashed password: {}", err);
+        }
     }
 }
 
@@ -62,4 +295,6 @@ pub struct UserClaims {
     pub sub: String,
     pub email: String,
     pub exp: usize,
+    /// Identifies the row in `sessions` this token belongs to.
+    pub jti: String,
 }
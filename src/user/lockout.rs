@@ -0,0 +1,502 @@
+use chrono::Duration;
+
+use crate::{
+    services::error::{ServiceError, authorize},
+    user::{LockedAccount, SignInAttempt, User, role::Permission},
+};
+
+/// Failed attempts an account gets before each further one has to wait.
+pub const ACCOUNT_FREE_ATTEMPTS: i64 = 5;
+
+/// Failed attempts after which an account is locked until an admin unlocks
+/// it or `LOCKOUT` passes.
+pub const LOCKOUT_AFTER: i64 = 10;
+
+pub const LOCKOUT: Duration = Duration::minutes(15);
+
+/// Failed attempts one address gets, across all accounts, within
+/// `IP_WINDOW` before it has to wait too.
+pub const IP_FREE_ATTEMPTS: i64 = 20;
+
+pub const IP_WINDOW: Duration = Duration::hours(1);
+
+/// Failures older than this no longer count against an account.
+const ACCOUNT_WINDOW: Duration = Duration::days(1);
+
+/// How long attempts are kept for admins to look at.
+const HISTORY: Duration = Duration::days(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttemptOutcome {
+    Failed,
+    Succeeded,
+    /// Turned away without checking the password because of earlier failures.
+    Blocked,
+    /// A failure that locked the account.
+    Locked,
+    /// An admin cleared the account's failures.
+    Unlocked,
+}
+
+impl AttemptOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttemptOutcome::Failed => "failed",
+            AttemptOutcome::Succeeded => "succeeded",
+            AttemptOutcome::Blocked => "blocked",
+            AttemptOutcome::Locked => "locked",
+            AttemptOutcome::Unlocked => "unlocked",
+        }
+    }
+}
+
+/// Why someone has to wait before trying to sign in again.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Throttle {
+    /// Recent failures for the account, with the seconds left to wait.
+    Account(i64),
+    /// The account is locked, with the seconds left until it unlocks.
+    Locked(i64),
+    /// Recent failures from the client's address.
+    Ip(i64),
+}
+
+impl Throttle {
+    /// What to tell the person signing in.
+    pub fn message(&self) -> String {
+        match self {
+            Throttle::Account(secs) | Throttle::Ip(secs) => format!(
+                "Too many failed attempts. Please wait {} before trying again.",
+                describe_wait(*secs)
+            ),
+            Throttle::Locked(secs) => format!(
+                "This account is locked after too many failed attempts. Try again in {}, or ask an admin to unlock it.",
+                describe_wait(*secs)
+            ),
+        }
+    }
+}
+
+fn describe_wait(secs: i64) -> String {
+    match secs {
+        ..=1 => "a second".to_string(),
+        2..=59 => format!("{} seconds", secs),
+        60..=119 => "a minute".to_string(),
+        _ => format!("{} minutes", (secs + 59) / 60),
+    }
+}
+
+/// How long to wait after the latest of `failures` failures, doubling with
+/// each one past the free attempts.
+fn backoff(failures: i64, free_attempts: i64) -> i64 {
+    if failures < free_attempts {
+        return 0;
+    }
+
+    let doublings = (failures - free_attempts).min(16) as u32;
+    (2_i64 << doublings).min(LOCKOUT.num_seconds())
+}
+
+/// The failures that count against an account or address, and how many
+/// seconds ago the latest of them was.
+struct Failures {
+    count: i64,
+    seconds_since_last: i64,
+}
+
+pub struct SignInAttemptRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl SignInAttemptRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        SignInAttemptRepository { db: db.clone() }
+    }
+
+    pub async fn record(
+        &self,
+        email: &str,
+        user_id: Option<i64>,
+        ip: &str,
+        outcome: AttemptOutcome,
+    ) -> Result<(), sqlx::Error> {
+        let outcome = outcome.as_str();
+        sqlx::query!(
+            "INSERT INTO sign_in_attempts (email, user_id, ip, outcome) VALUES (?, ?, ?, ?)",
+            email,
+            user_id,
+            ip,
+            outcome
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Failures for an account since it last signed in or was unlocked.
+    async fn account_failures(&self, email: &str) -> Result<Failures, sqlx::Error> {
+        let window = format!("-{} seconds", ACCOUNT_WINDOW.num_seconds());
+        let failures = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "count!: i64",
+                CAST(strftime('%s', 'now') AS INTEGER)
+                    - CAST(strftime('%s', MAX(created_at)) AS INTEGER) AS "seconds_since_last: i64"
+            FROM sign_in_attempts
+            WHERE email = ? AND outcome IN ('failed', 'locked')
+              AND created_at > datetime('now', ?)
+              AND id > COALESCE((
+                  SELECT MAX(id) FROM sign_in_attempts
+                  WHERE email = ? AND outcome IN ('succeeded', 'unlocked')
+              ), 0)
+            "#,
+            email,
+            window,
+            email
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(Failures {
+            count: failures.count,
+            seconds_since_last: failures.seconds_since_last.unwrap_or_default(),
+        })
+    }
+
+    /// Failures from an address within `IP_WINDOW`. Signing in successfully
+    /// doesn't clear these, or guessing at other accounts could be kept up
+    /// by signing in to one's own now and then.
+    async fn ip_failures(&self, ip: &str) -> Result<Failures, sqlx::Error> {
+        let window = format!("-{} seconds", IP_WINDOW.num_seconds());
+        let failures = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "count!: i64",
+                CAST(strftime('%s', 'now') AS INTEGER)
+                    - CAST(strftime('%s', MAX(created_at)) AS INTEGER) AS "seconds_since_last: i64"
+            FROM sign_in_attempts
+            WHERE ip = ? AND outcome IN ('failed', 'locked') AND created_at > datetime('now', ?)
+            "#,
+            ip,
+            window
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(Failures {
+            count: failures.count,
+            seconds_since_last: failures.seconds_since_last.unwrap_or_default(),
+        })
+    }
+
+    pub async fn recent(&self, limit: i64) -> Result<Vec<SignInAttempt>, sqlx::Error> {
+        let attempts = sqlx::query_as!(
+            SignInAttempt,
+            r#"
+            SELECT id AS "id!", email, user_id, ip, outcome, created_at AS "created_at: String"
+            FROM sign_in_attempts
+            ORDER BY id DESC
+            LIMIT ?
+            "#,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(attempts)
+    }
+
+    /// Accounts whose latest failure locked them, within the last `LOCKOUT`.
+    pub async fn locked(&self) -> Result<Vec<LockedAccount>, sqlx::Error> {
+        let lockout = format!("-{} seconds", LOCKOUT.num_seconds());
+        let accounts = sqlx::query_as!(
+            LockedAccount,
+            r#"
+            SELECT email AS "email!", MAX(created_at) AS "locked_at!: String"
+            FROM sign_in_attempts a
+            WHERE outcome = 'locked' AND created_at > datetime('now', ?)
+              AND id > COALESCE((
+                  SELECT MAX(id) FROM sign_in_attempts b
+                  WHERE b.email = a.email AND b.outcome IN ('succeeded', 'unlocked')
+              ), 0)
+            GROUP BY email
+            ORDER BY MAX(id) DESC
+            "#,
+            lockout
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(accounts)
+    }
+
+    pub async fn delete_old(&self) -> Result<u64, sqlx::Error> {
+        let history = format!("-{} seconds", HISTORY.num_seconds());
+        let result = sqlx::query!(
+            "DELETE FROM sign_in_attempts WHERE created_at < datetime('now', ?)",
+            history
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Slows down password guessing. Failures count against both the account
+/// and the client's address: past a few, each further attempt has to wait
+/// twice as long as the last, and an account with too many is locked for a
+/// while.
+pub struct LockoutService {
+    repository: SignInAttemptRepository,
+}
+
+impl LockoutService {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        LockoutService {
+            repository: SignInAttemptRepository::new(db),
+        }
+    }
+
+    /// Whether an attempt to sign in to `email` from `ip` has to wait. Turned
+    /// away attempts are recorded so admins can see them.
+    pub async fn check(&self, email: &str, ip: &str) -> Result<Option<Throttle>, sqlx::Error> {
+        let email = normalize(email);
+        let throttle = self.throttle(&email, ip).await?;
+        if throttle.is_some() {
+            self.repository
+                .record(&email, None, ip, AttemptOutcome::Blocked)
+                .await?;
+        }
+
+        Ok(throttle)
+    }
+
+    async fn throttle(&self, email: &str, ip: &str) -> Result<Option<Throttle>, sqlx::Error> {
+        let account = self.repository.account_failures(email).await?;
+        if account.count >= LOCKOUT_AFTER {
+            let left = LOCKOUT.num_seconds() - account.seconds_since_last;
+            if left > 0 {
+                return Ok(Some(Throttle::Locked(left)));
+            }
+        }
+
+        let left = backoff(account.count, ACCOUNT_FREE_ATTEMPTS) - account.seconds_since_last;
+        if left > 0 {
+            return Ok(Some(Throttle::Account(left)));
+        }
+
+        let address = self.repository.ip_failures(ip).await?;
+        let left = backoff(address.count, IP_FREE_ATTEMPTS) - address.seconds_since_last;
+        if left > 0 {
+            return Ok(Some(Throttle::Ip(left)));
+        }
+
+        Ok(None)
+    }
+
+    /// Records a wrong password or code. Returns how long the next attempt
+    /// has to wait, if it does.
+    pub async fn record_failure(
+        &self,
+        email: &str,
+        user_id: Option<i64>,
+        ip: &str,
+    ) -> Result<Option<Throttle>, sqlx::Error> {
+        let email = normalize(email);
+        let failures = self.repository.account_failures(&email).await?.count + 1;
+        let outcome = if failures >= LOCKOUT_AFTER {
+            AttemptOutcome::Locked
+        } else {
+            AttemptOutcome::Failed
+        };
+        self.repository.record(&email, user_id, ip, outcome).await?;
+
+        self.throttle(&email, ip).await
+    }
+
+    /// Records a sign in, which clears the account's failures.
+    pub async fn record_success(
+        &self,
+        email: &str,
+        user_id: i64,
+        ip: &str,
+    ) -> Result<(), sqlx::Error> {
+        self.repository
+            .record(
+                &normalize(email),
+                Some(user_id),
+                ip,
+                AttemptOutcome::Succeeded,
+            )
+            .await
+    }
+
+    /// Clears an account's failures so it can sign in straight away.
+    pub async fn unlock(&self, admin: &User, email: &str, ip: &str) -> Result<(), ServiceError> {
+        authorize(admin, Permission::ManageSecurity)?;
+
+        self.repository
+            .record(&normalize(email), None, ip, AttemptOutcome::Unlocked)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn recent_attempts(&self, admin: &User) -> Result<Vec<SignInAttempt>, ServiceError> {
+        authorize(admin, Permission::ManageSecurity)?;
+
+        Ok(self.repository.recent(100).await?)
+    }
+
+    pub async fn locked_accounts(&self, admin: &User) -> Result<Vec<LockedAccount>, ServiceError> {
+        authorize(admin, Permission::ManageSecurity)?;
+
+        Ok(self.repository.locked().await?)
+    }
+}
+
+// so that `Ada@example.com` and `ada@example.com ` count as the same account
+fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::user::{CreateUserRequest, repository::UserRepository, role::Role};
+
+    const IP: &str = "192.0.2.1";
+
+    async fn service() -> (LockoutService, User) {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+
+        let request = CreateUserRequest {
+            email: "ada@example.com".to_string(),
+            password: "not a real hash".to_string(),
+            given_name: "Ada".to_string(),
+            family_name: "Lovelace".to_string(),
+            invite: None,
+        };
+        let user = UserRepository::new(&db)
+            .create_user(&request, Role::Editor, true)
+            .await
+            .unwrap();
+
+        (LockoutService::new(&db), user)
+    }
+
+    /// Fails to sign in to `email` `times` times, returning the last wait.
+    async fn fail(service: &LockoutService, email: &str, times: i64) -> Option<Throttle> {
+        let mut throttle = None;
+        for _ in 0..times {
+            throttle = service.record_failure(email, None, IP).await.unwrap();
+        }
+        throttle
+    }
+
+    #[test]
+    fn backs_off_after_the_free_attempts() {
+        assert_eq!(backoff(0, ACCOUNT_FREE_ATTEMPTS), 0);
+        assert_eq!(backoff(4, ACCOUNT_FREE_ATTEMPTS), 0);
+        assert_eq!(backoff(5, ACCOUNT_FREE_ATTEMPTS), 2);
+        assert_eq!(backoff(6, ACCOUNT_FREE_ATTEMPTS), 4);
+        assert_eq!(backoff(9, ACCOUNT_FREE_ATTEMPTS), 32);
+
+        assert_eq!(backoff(19, IP_FREE_ATTEMPTS), 0);
+        assert_eq!(backoff(20, IP_FREE_ATTEMPTS), 2);
+    }
+
+    #[test]
+    fn never_backs_off_longer_than_a_lockout() {
+        assert_eq!(backoff(13, ACCOUNT_FREE_ATTEMPTS), 512);
+        assert_eq!(backoff(14, ACCOUNT_FREE_ATTEMPTS), LOCKOUT.num_seconds());
+        assert_eq!(backoff(1_000, ACCOUNT_FREE_ATTEMPTS), LOCKOUT.num_seconds());
+    }
+
+    #[tokio::test]
+    async fn throttles_an_account_after_five_failures() {
+        let (service, user) = service().await;
+
+        assert_eq!(fail(&service, &user.email, 4).await, None);
+        assert_eq!(service.check(&user.email, IP).await.unwrap(), None);
+
+        let throttle = fail(&service, &user.email, 1).await;
+        assert!(matches!(throttle, Some(Throttle::Account(1..=2))));
+        // the same account, however it's typed
+        let throttle = service.check(" ADA@example.com", IP).await.unwrap();
+        assert!(matches!(throttle, Some(Throttle::Account(1..=2))));
+        assert_eq!(service.check("grace@example.com", IP).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn locks_an_account_after_ten_failures() {
+        let (service, user) = service().await;
+
+        let throttle = fail(&service, &user.email, 9).await;
+        assert!(matches!(throttle, Some(Throttle::Account(31..=32))));
+
+        let lockout = LOCKOUT.num_seconds();
+        let throttle = fail(&service, &user.email, 1).await;
+        assert!(matches!(throttle, Some(Throttle::Locked(left)) if left > lockout - 2));
+        let throttle = service.check(&user.email, IP).await.unwrap();
+        assert!(matches!(throttle, Some(Throttle::Locked(left)) if left > lockout - 2));
+    }
+
+    #[tokio::test]
+    async fn throttles_an_address_after_twenty_failures() {
+        let (service, _) = service().await;
+
+        for n in 1..20 {
+            let email = format!("guess{}@example.com", n);
+            assert_eq!(fail(&service, &email, 1).await, None);
+        }
+
+        let throttle = fail(&service, "guess20@example.com", 1).await;
+        assert!(matches!(throttle, Some(Throttle::Ip(1..=2))));
+        let throttle = service.check("ada@example.com", IP).await.unwrap();
+        assert!(matches!(throttle, Some(Throttle::Ip(1..=2))));
+        assert_eq!(
+            service.check("ada@example.com", "192.0.2.2").await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn signing_in_clears_the_accounts_failures() {
+        let (service, user) = service().await;
+        fail(&service, &user.email, 5).await;
+        assert!(service.check(&user.email, IP).await.unwrap().is_some());
+
+        service
+            .record_success(&user.email, user.id, IP)
+            .await
+            .unwrap();
+
+        assert_eq!(service.check(&user.email, IP).await.unwrap(), None);
+        // counting starts again from none
+        assert_eq!(fail(&service, &user.email, 4).await, None);
+    }
+
+    #[tokio::test]
+    async fn signing_in_doesnt_clear_the_addresss_failures() {
+        let (service, user) = service().await;
+        for n in 1..=20 {
+            fail(&service, &format!("guess{}@example.com", n), 1).await;
+        }
+
+        service
+            .record_success(&user.email, user.id, IP)
+            .await
+            .unwrap();
+
+        let throttle = service.check(&user.email, IP).await.unwrap();
+        assert!(matches!(throttle, Some(Throttle::Ip(_))));
+    }
+}
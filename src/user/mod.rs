@@ -1,9 +1,29 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    models::api_key::ApiKey,
+    user::{
+        role::{Permission, Permissions, Role},
+        tokens::TokenScope,
+    },
+};
+
+pub mod admin;
 pub mod auth;
+pub mod lockout;
+pub mod oidc;
+pub mod passkey;
+pub mod password;
+pub mod password_reset;
+pub mod profile;
 pub mod repository;
+pub mod role;
 pub mod routes;
 pub mod service;
+pub mod session;
+pub mod signup;
+pub mod tokens;
+pub mod two_factor;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
@@ -12,9 +32,67 @@ pub struct User {
     pub given_name: String,
     pub family_name: String,
     pub avatar_url: String,
-    pub role: i64,
+    pub role: Role,
+    pub email_verified_at: Option<String>,
+    pub disabled_at: Option<String>,
     pub created_at: String,
     pub updated_at: String,
+    /// Set when the request came with a personal access token rather than a
+    /// session, limiting the user to what the token's scopes allow.
+    #[serde(skip)]
+    pub token_scopes: Option<Vec<TokenScope>>,
+    /// Set when an app's write key is acting rather than a person, see
+    /// `User::for_api_key`.
+    #[serde(skip)]
+    pub api_key_id: Option<i64>,
+}
+
+impl User {
+    /// Stands in for a write key on the management API. The key may change
+    /// its app's pages and content the way a publisher can, and nothing else.
+    /// There's no account behind it, so `id` is 0 and its changes aren't
+    /// attributed to anyone.
+    pub fn for_api_key(key: &ApiKey) -> Self {
+        User {
+            id: 0,
+            email: String::new(),
+            given_name: key.name.clone(),
+            family_name: String::new(),
+            avatar_url: String::new(),
+            role: Role::Publisher,
+            email_verified_at: None,
+            disabled_at: None,
+            created_at: key.created_at.clone(),
+            updated_at: key.created_at.clone(),
+            token_scopes: Some(vec![TokenScope::Content, TokenScope::Pages]),
+            api_key_id: Some(key.id),
+        }
+    }
+
+    /// Who to record as having made a change.
+    pub fn author_id(&self) -> Option<i64> {
+        self.api_key_id.is_none().then_some(self.id)
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        self.role.can(permission)
+            && self
+                .token_scopes
+                .as_ref()
+                .is_none_or(|scopes| scopes.iter().any(|scope| scope.allows(permission)))
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        Permissions::from(self)
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.email_verified_at.is_some()
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -23,10 +101,308 @@ pub struct CreateUserRequest {
     pub password: String,
     pub given_name: String,
     pub family_name: String,
+    /// The token from an invite link, when signing up through one.
+    #[serde(default)]
+    pub invite: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SignInRequest {
     pub email: String,
     pub password: String,
+    #[serde(default)]
+    pub return_to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignInParams {
+    pub return_to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResetPasswordParams {
+    #[serde(default)]
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+    pub password_confirmation: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VerifyEmailParams {
+    #[serde(default)]
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+/// Claims of the token in an email verification link. The email is part of
+/// the token so a link stops working if the address it was sent to changes.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VerificationClaims {
+    pub sub: i64,
+    pub email: String,
+    pub exp: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignUpParams {
+    pub invite: Option<String>,
+}
+
+/// An invitation to sign up, sent by an admin. Whoever accepts it gets its
+/// role and doesn't need to verify their email again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Invite {
+    pub id: i64,
+    pub email: String,
+    pub role: Role,
+    pub invited_by: Option<String>,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewInviteRequest {
+    pub email: String,
+    pub role: Role,
+}
+
+/// Claims of the token that links the two steps of signing in with
+/// two-factor authentication.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TwoFactorClaims {
+    pub sub: i64,
+    pub exp: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    /// Whether the user's role requires two-factor authentication.
+    pub required: bool,
+    pub recovery_codes_left: i64,
+}
+
+/// What someone needs to add Wordford to their authenticator app.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TwoFactorEnrolment {
+    /// The secret in base32, for apps that can't scan QR codes.
+    pub secret: String,
+    /// An SVG image of the `otpauth://` link.
+    pub qr_code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TwoFactorCodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TwoFactorSignInRequest {
+    pub challenge: String,
+    pub code: String,
+    #[serde(default)]
+    pub return_to: Option<String>,
+}
+
+/// A passkey someone registered, for listing on their account.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Passkey {
+    pub id: i64,
+    pub name: String,
+    pub credential_id: String,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+}
+
+/// What `navigator.credentials.create()` produced, with binary values
+/// base64url encoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewPasskeyRequest {
+    pub name: String,
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// What `navigator.credentials.get()` produced, with binary values base64url
+/// encoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasskeySignInRequest {
+    /// The credential id.
+    pub id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub return_to: Option<String>,
+}
+
+/// What the browser carries in a cookie while someone signs in through an
+/// OpenID Connect provider.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OidcFlowClaims {
+    pub state: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub return_to: String,
+    pub exp: usize,
+}
+
+/// What the identity provider sends back to the callback.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OidcCallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A sign in attempt, as listed for admins.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignInAttempt {
+    pub id: i64,
+    pub email: String,
+    pub user_id: Option<i64>,
+    pub ip: String,
+    pub outcome: String,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockedAccount {
+    pub email: String,
+    pub locked_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnlockAccountRequest {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateProfileRequest {
+    pub given_name: String,
+    pub family_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub password: String,
+    pub password_confirmation: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangeEmailRequest {
+    pub email: String,
+    pub current_password: String,
+}
+
+/// Claims of the token in the link that confirms a new email address. The
+/// old address is part of it so the link stops working if the email changes
+/// some other way first.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmailChangeClaims {
+    pub sub: i64,
+    pub old_email: String,
+    pub new_email: String,
+    pub exp: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfirmEmailParams {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChooseAvatarRequest {
+    pub critter: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeleteAccountRequest {
+    pub current_password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserSearchParams {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub page: Option<i64>,
+}
+
+/// One page of the admin user list.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub query: String,
+    pub page: i64,
+    pub pages: i64,
+    pub total: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangeRoleRequest {
+    pub role: Role,
+}
+
+/// A personal access token, for listing on its owner's account.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PersonalAccessToken {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<TokenScope>,
+    pub expires_at: String,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub created_at: String,
+}
+
+/// The form for minting a token. Browsers send one `scopes` field per ticked
+/// box, which `Form` can't gather into a list, so see `from_pairs`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NewTokenRequest {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub expires_in_days: i64,
+}
+
+impl NewTokenRequest {
+    /// Builds the request from the form's fields in the order they were
+    /// sent. Unknown scopes and fields are ignored.
+    pub fn from_pairs(pairs: Vec<(String, String)>) -> Self {
+        let mut request = NewTokenRequest::default();
+        for (name, value) in pairs {
+            match name.as_str() {
+                "name" => request.name = value,
+                "scopes" => request.scopes.extend(TokenScope::parse(&value)),
+                "expires_in_days" => request.expires_in_days = value.parse().unwrap_or_default(),
+                _ => {}
+            }
+        }
+
+        request
+    }
+}
+
+/// Returned only when a token is created; `token` is never stored or shown
+/// again.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatedToken {
+    pub token: PersonalAccessToken,
+    pub secret: String,
 }
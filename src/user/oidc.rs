@@ -0,0 +1,553 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use openidconnect::{
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+    core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata},
+    reqwest,
+};
+
+use crate::{
+    mailer::absolute_url,
+    services::error::ServiceError,
+    user::{
+        CreateUserRequest, OidcFlowClaims, User,
+        auth::{self, derived_secret},
+        password::hash_password,
+        repository::UserRepository,
+        role::Role,
+        signup::SignupMode,
+    },
+};
+
+/// How long someone has to finish signing in at the identity provider.
+pub const FLOW_LIFETIME: Duration = Duration::minutes(10);
+
+/// How long discovered provider metadata is used before it's fetched again,
+/// so that rotated signing keys are picked up.
+const METADATA_LIFETIME: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Settings for signing in through an OpenID Connect provider, read from the
+/// `OIDC_*` environment variables.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    /// Shown on the sign in button, e.g. "Sign in with Okta".
+    pub provider_name: String,
+    /// The ID token claim listing the user's groups.
+    pub groups_claim: String,
+    /// Groups and the role their members get. When someone is in several,
+    /// the highest role wins.
+    pub role_mapping: Vec<(String, Role)>,
+    /// The role for people in none of the mapped groups.
+    pub default_role: Role,
+    /// Whether people without an account get one the first time they sign
+    /// in. Otherwise they need an account with the same email already.
+    pub create_users: bool,
+}
+
+impl OidcConfig {
+    /// Single sign-on is off unless `OIDC_ISSUER_URL` and `OIDC_CLIENT_ID`
+    /// are set.
+    pub fn from_env() -> Option<Self> {
+        let issuer_url = env::var("OIDC_ISSUER_URL").ok()?;
+        let client_id = env::var("OIDC_CLIENT_ID").ok()?;
+
+        Some(OidcConfig {
+            issuer_url,
+            client_id,
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_url: env::var("OIDC_REDIRECT_URL")
+                .unwrap_or_else(|_| absolute_url("/signin/oidc/callback")),
+            provider_name: env::var("OIDC_PROVIDER_NAME")
+                .unwrap_or_else(|_| "single sign-on".to_string()),
+            groups_claim: env::var("OIDC_GROUPS_CLAIM").unwrap_or_else(|_| "groups".to_string()),
+            role_mapping: env::var("OIDC_ROLE_MAPPING")
+                .map(|mapping| parse_role_mapping(&mapping))
+                .unwrap_or_default(),
+            default_role: env::var("OIDC_DEFAULT_ROLE")
+                .ok()
+                .and_then(|role| role.parse().ok())
+                .unwrap_or(Role::Viewer),
+            // follows SIGNUP_MODE unless it's set, so that closing sign ups
+            // closes them here too
+            create_users: match env::var("OIDC_CREATE_USERS").as_deref() {
+                Ok("true") => true,
+                Ok("false") => false,
+                _ => SignupMode::from_env() == SignupMode::Open,
+            },
+        })
+    }
+
+    /// The role for someone in `groups`. Without a mapping there is nothing
+    /// to go on, so `None` leaves existing users' roles alone.
+    pub fn role_for(&self, groups: &[String]) -> Option<Role> {
+        if self.role_mapping.is_empty() {
+            return None;
+        }
+
+        let role = self
+            .role_mapping
+            .iter()
+            .filter(|(group, _)| groups.contains(group))
+            .map(|(_, role)| *role)
+            .max()
+            .unwrap_or(self.default_role);
+
+        Some(role)
+    }
+}
+
+/// Parses `OIDC_ROLE_MAPPING`, which looks like
+/// `cms-admins=admin,cms-editors=editor`. Entries with an unknown role are
+/// skipped.
+fn parse_role_mapping(mapping: &str) -> Vec<(String, Role)> {
+    mapping
+        .split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .filter_map(|(group, role)| Some((group.trim().to_string(), role.parse().ok()?)))
+        .collect()
+}
+
+/// Who the identity provider says signed in.
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: String,
+    pub given_name: String,
+    pub family_name: String,
+    pub groups: Vec<String>,
+    /// Whether the provider checked that `email` belongs to them.
+    pub email_verified: bool,
+}
+
+/// The provider's endpoints and signing keys, shared between requests so
+/// that they aren't discovered again for every sign in.
+#[derive(Clone, Default)]
+pub struct ProviderMetadataCache(Arc<Mutex<Option<CachedMetadata>>>);
+
+struct CachedMetadata {
+    issuer_url: String,
+    metadata: CoreProviderMetadata,
+    fetched_at: Instant,
+}
+
+impl ProviderMetadataCache {
+    fn get(&self, issuer_url: &str) -> Option<CoreProviderMetadata> {
+        let cached = self.0.lock().expect("metadata cache poisoned");
+        cached
+            .as_ref()
+            .filter(|cached| {
+                cached.issuer_url == issuer_url && cached.fetched_at.elapsed() < METADATA_LIFETIME
+            })
+            .map(|cached| cached.metadata.clone())
+    }
+
+    fn insert(&self, issuer_url: &str, metadata: CoreProviderMetadata) {
+        *self.0.lock().expect("metadata cache poisoned") = Some(CachedMetadata {
+            issuer_url: issuer_url.to_string(),
+            metadata,
+            fetched_at: Instant::now(),
+        });
+    }
+}
+
+pub struct IdentityRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl IdentityRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        IdentityRepository { db: db.clone() }
+    }
+
+    /// The user linked to an account at a provider, noting that it was used.
+    pub async fn find_user_id(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            UPDATE user_identities SET last_used_at = CURRENT_TIMESTAMP
+            WHERE issuer = ? AND subject = ?
+            RETURNING user_id
+            "#,
+            issuer,
+            subject
+        )
+        .fetch_optional(&self.db)
+        .await
+    }
+
+    pub async fn link(&self, user_id: i64, issuer: &str, subject: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_identities (user_id, issuer, subject, last_used_at)
+            VALUES (?, ?, ?, CURRENT_TIMESTAMP)
+            "#,
+            user_id,
+            issuer,
+            subject
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Signs people in through an OpenID Connect provider using the
+/// authorization code flow with PKCE.
+pub struct OidcService {
+    config: OidcConfig,
+    http_client: reqwest::Client,
+    metadata: ProviderMetadataCache,
+    user_repository: UserRepository,
+    identity_repository: IdentityRepository,
+}
+
+impl OidcService {
+    pub fn new(
+        db: &sqlx::SqlitePool,
+        config: OidcConfig,
+        metadata: &ProviderMetadataCache,
+    ) -> Self {
+        let http_client = reqwest::ClientBuilder::new()
+            // following redirects would open us up to SSRF
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("failed to build HTTP client");
+
+        OidcService {
+            config,
+            http_client,
+            metadata: metadata.clone(),
+            user_repository: UserRepository::new(db),
+            identity_repository: IdentityRepository::new(db),
+        }
+    }
+
+    pub fn provider_name(&self) -> &str {
+        &self.config.provider_name
+    }
+
+    /// Returns the provider's authorization URL to send the browser to, and
+    /// a token to keep in a cookie until it comes back to the callback.
+    pub async fn begin(&self, return_to: &str) -> Result<(String, String), ServiceError> {
+        let client = self.client().await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, state, nonce) = client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("email".to_string()))
+            .add_scope(Scope::new("profile".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+
+        let claims = OidcFlowClaims {
+            state: state.secret().to_string(),
+            nonce: nonce.secret().to_string(),
+            pkce_verifier: pkce_verifier.secret().to_string(),
+            return_to: return_to.to_string(),
+            exp: Utc::now()
+                .checked_add_signed(FLOW_LIFETIME)
+                .expect("valid timestamp")
+                .timestamp() as usize,
+        };
+        let key = EncodingKey::from_secret(derived_secret("oidc").as_bytes());
+        let flow = encode(&Header::new(Algorithm::HS256), &claims, &key)
+            .expect("failed to sign sign in flow");
+
+        Ok((url.to_string(), flow))
+    }
+
+    /// Finishes signing in once the provider sends the browser back. Returns
+    /// the signed in user and where they were headed.
+    pub async fn complete(
+        &self,
+        flow: &str,
+        state: &str,
+        code: &str,
+    ) -> Result<(User, String), ServiceError> {
+        let key = DecodingKey::from_secret(derived_secret("oidc").as_bytes());
+        let flow = decode::<OidcFlowClaims>(flow, &key, &Validation::default())
+            .map_err(|_| expired())?
+            .claims;
+        if flow.state != state {
+            return Err(expired());
+        }
+
+        let identity = self
+            .identity(code, flow.pkce_verifier, Nonce::new(flow.nonce))
+            .await?;
+        let user = self.link_user(&identity).await?;
+        if user.is_disabled() {
+            return Err(auth::disabled());
+        }
+
+        Ok((user, flow.return_to))
+    }
+
+    async fn client(
+        &self,
+    ) -> Result<
+        CoreClient<
+            openidconnect::EndpointSet,
+            openidconnect::EndpointNotSet,
+            openidconnect::EndpointNotSet,
+            openidconnect::EndpointNotSet,
+            openidconnect::EndpointMaybeSet,
+            openidconnect::EndpointMaybeSet,
+        >,
+        ServiceError,
+    > {
+        let issuer_url = IssuerUrl::new(self.config.issuer_url.clone()).map_err(|err| {
+            tracing::error!("invalid OIDC_ISSUER_URL: {}", err);
+            self.unavailable()
+        })?;
+        let redirect_url = RedirectUrl::new(self.config.redirect_url.clone()).map_err(|err| {
+            tracing::error!("invalid OIDC_REDIRECT_URL: {}", err);
+            self.unavailable()
+        })?;
+        let metadata = match self.metadata.get(&self.config.issuer_url) {
+            Some(metadata) => metadata,
+            None => {
+                let metadata = CoreProviderMetadata::discover_async(issuer_url, &self.http_client)
+                    .await
+                    .map_err(|err| {
+                        tracing::error!("discovery failed: {}", err);
+                        self.unavailable()
+                    })?;
+                self.metadata
+                    .insert(&self.config.issuer_url, metadata.clone());
+                metadata
+            }
+        };
+
+        Ok(CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(self.config.client_id.clone()),
+            self.config.client_secret.clone().map(ClientSecret::new),
+        )
+        .set_redirect_uri(redirect_url))
+    }
+
+    /// Swaps the authorization code for an ID token and reads who it's for.
+    async fn identity(
+        &self,
+        code: &str,
+        pkce_verifier: String,
+        nonce: Nonce,
+    ) -> Result<OidcIdentity, ServiceError> {
+        let client = self.client().await?;
+        let token_response = client
+            .exchange_code(AuthorizationCode::new(code.to_string()))
+            .map_err(|err| {
+                tracing::error!("provider has no token endpoint: {}", err);
+                self.unavailable()
+            })?
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(&self.http_client)
+            .await
+            .map_err(|err| {
+                tracing::warn!("code exchange failed: {}", err);
+                self.rejected()
+            })?;
+
+        let id_token = token_response.id_token().ok_or_else(|| {
+            tracing::warn!("token response has no ID token");
+            self.rejected()
+        })?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &nonce)
+            .map_err(|err| {
+                tracing::warn!("ID token rejected: {}", err);
+                self.rejected()
+            })?;
+
+        let Some(email) = claims.email() else {
+            return Err(ServiceError::Invalid(format!(
+                "Your {} account doesn't have an email address.",
+                self.config.provider_name
+            )));
+        };
+
+        let given_name = claims
+            .given_name()
+            .and_then(|claim| claim.get(None))
+            .map(|name| name.to_string())
+            .unwrap_or_default();
+        let family_name = claims
+            .family_name()
+            .and_then(|claim| claim.get(None))
+            .map(|name| name.to_string())
+            .unwrap_or_default();
+
+        Ok(OidcIdentity {
+            issuer: claims.issuer().to_string(),
+            subject: claims.subject().to_string(),
+            email: email.to_string(),
+            given_name,
+            family_name,
+            // the token's signature was checked above, so its other claims
+            // can be trusted too
+            groups: self.groups(&id_token.to_string()),
+            email_verified: claims.email_verified() == Some(true),
+        })
+    }
+
+    /// Reads the groups claim, which isn't one of the standard claims.
+    fn groups(&self, id_token: &str) -> Vec<String> {
+        let payload = id_token
+            .split('.')
+            .nth(1)
+            .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+            .and_then(|payload| serde_json::from_slice::<serde_json::Value>(&payload).ok());
+
+        match payload
+            .as_ref()
+            .and_then(|p| p.get(&self.config.groups_claim))
+        {
+            Some(serde_json::Value::Array(groups)) => groups
+                .iter()
+                .filter_map(|group| group.as_str().map(str::to_string))
+                .collect(),
+            Some(serde_json::Value::String(group)) => vec![group.to_string()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Finds the user for an identity, linking it to the account with the
+    /// same email or, if allowed, creating one if there is none. Their role follows their
+    /// groups when a role mapping is configured.
+    ///
+    /// Linking and creating both need the provider to have verified the
+    /// email, since otherwise anyone could claim someone else's address
+    /// there and take over their account here.
+    async fn link_user(&self, identity: &OidcIdentity) -> Result<User, ServiceError> {
+        let linked = self
+            .identity_repository
+            .find_user_id(&identity.issuer, &identity.subject)
+            .await?;
+
+        let mut user = match linked {
+            Some(user_id) => self.user_repository.find_by_id(user_id).await?,
+            None => {
+                if !identity.email_verified {
+                    return Err(ServiceError::Invalid(format!(
+                        "Verify your email address with {} first.",
+                        self.config.provider_name
+                    )));
+                }
+                let user = match self.user_repository.find_by_email(&identity.email).await {
+                    Ok(user) => {
+                        self.user_repository.mark_email_verified(user.id).await?;
+                        user
+                    }
+                    Err(sqlx::Error::RowNotFound) => {
+                        // the first account on a fresh install can always be
+                        // made, the same as when signing up
+                        if !self.config.create_users && self.user_repository.has_users().await? {
+                            return Err(ServiceError::Invalid(format!(
+                                "There's no account for {}. Ask an admin for one.",
+                                identity.email
+                            )));
+                        }
+                        self.create_user(identity).await?
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+                self.identity_repository
+                    .link(user.id, &identity.issuer, &identity.subject)
+                    .await?;
+                user
+            }
+        };
+
+        if let Some(role) = self.config.role_for(&identity.groups)
+            && role != user.role
+            && self.keeps_an_admin(&user, role).await?
+        {
+            self.user_repository.update_role(user.id, role).await?;
+            user.role = role;
+        }
+
+        Ok(user)
+    }
+
+    /// Whether giving the user `role` still leaves an admin. Like an admin
+    /// changing their role by hand, the last admin keeps theirs, even when
+    /// the provider's groups say otherwise.
+    async fn keeps_an_admin(&self, user: &User, role: Role) -> Result<bool, sqlx::Error> {
+        if user.role != Role::Admin || role == Role::Admin || user.is_disabled() {
+            return Ok(true);
+        }
+
+        let keeps_an_admin = self.user_repository.count_admins().await? > 1;
+        if !keeps_an_admin {
+            tracing::warn!(
+                "kept {} as an admin although their groups map to {:?}, as they're the only one",
+                user.email,
+                role
+            );
+        }
+
+        Ok(keeps_an_admin)
+    }
+
+    async fn create_user(&self, identity: &OidcIdentity) -> Result<User, ServiceError> {
+        // they sign in through the provider, so nobody knows this password
+        // and the password policy doesn't apply. They can still set one with
+        // a password reset.
+        let password = hex::encode(rand::random::<[u8; 32]>());
+        let request = CreateUserRequest {
+            email: identity.email.clone(),
+            password: hash_password(&password).await?,
+            given_name: identity.given_name.clone(),
+            family_name: identity.family_name.clone(),
+            invite: None,
+        };
+        let role = self
+            .config
+            .role_for(&identity.groups)
+            .unwrap_or(self.config.default_role);
+
+        Ok(self
+            .user_repository
+            .create_user(&request, role, true)
+            .await?)
+    }
+
+    fn unavailable(&self) -> ServiceError {
+        ServiceError::Invalid(format!(
+            "We couldn't reach {}. Please try again later.",
+            self.config.provider_name
+        ))
+    }
+
+    fn rejected(&self) -> ServiceError {
+        ServiceError::Invalid(format!(
+            "{} didn't confirm who you are. Please try again.",
+            self.config.provider_name
+        ))
+    }
+}
+
+pub fn expired() -> ServiceError {
+    ServiceError::Invalid("That took too long. Please try signing in again.".to_string())
+}
@@ -0,0 +1,650 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Duration;
+use ciborium::Value;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    mailer::app_url,
+    services::error::ServiceError,
+    user::{NewPasskeyRequest, Passkey, PasskeySignInRequest, User},
+};
+
+/// How long the browser has to finish a registration or sign in.
+pub const CHALLENGE_LIFETIME: Duration = Duration::minutes(5);
+
+// COSE algorithm identifiers, see https://www.iana.org/assignments/cose
+const ES256: i64 = -7;
+const EDDSA: i64 = -8;
+const RS256: i64 = -257;
+
+// COSE key types and elliptic curves
+const KTY_OKP: i64 = 1;
+const KTY_EC2: i64 = 2;
+const KTY_RSA: i64 = 3;
+const CRV_P256: i64 = 1;
+const CRV_ED25519: i64 = 6;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ceremony {
+    Register,
+    Authenticate,
+}
+
+impl Ceremony {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Ceremony::Register => "register",
+            Ceremony::Authenticate => "authenticate",
+        }
+    }
+
+    /// The `type` the browser puts in `clientDataJSON` for this ceremony.
+    fn client_data_type(&self) -> &'static str {
+        match self {
+            Ceremony::Register => "webauthn.create",
+            Ceremony::Authenticate => "webauthn.get",
+        }
+    }
+}
+
+/// The site passkeys belong to. Browsers only hand a passkey to pages on
+/// `origin`, and the authenticator signs over a hash of `id`.
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    /// The domain, e.g. `wordford.example.com`.
+    pub id: String,
+    /// The scheme, domain and port, e.g. `https://wordford.example.com`.
+    pub origin: String,
+}
+
+impl RelyingParty {
+    pub fn new(app_url: &str) -> Self {
+        let (scheme, rest) = app_url.split_once("://").unwrap_or(("https", app_url));
+        let host = rest.split('/').next().unwrap_or_default();
+        let id = host.split(':').next().unwrap_or_default();
+
+        RelyingParty {
+            id: id.to_string(),
+            origin: format!("{}://{}", scheme, host),
+        }
+    }
+
+    /// Uses the domain from `APP_URL`.
+    pub fn from_env() -> Self {
+        RelyingParty::new(&app_url())
+    }
+}
+
+struct StoredPasskey {
+    id: i64,
+    user_id: i64,
+    public_key: Vec<u8>,
+    sign_count: i64,
+}
+
+pub struct PasskeyRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl PasskeyRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        PasskeyRepository { db: db.clone() }
+    }
+
+    pub async fn find_all_by_user_id(&self, user_id: i64) -> Result<Vec<Passkey>, sqlx::Error> {
+        let passkeys = sqlx::query!(
+            r#"
+            SELECT id, name, credential_id, last_used_at, created_at
+            FROM passkeys WHERE user_id = ? ORDER BY id
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(passkeys
+            .into_iter()
+            .map(|row| Passkey {
+                id: row.id.expect("id should not be null"),
+                name: row.name,
+                credential_id: row.credential_id,
+                last_used_at: row.last_used_at.map(|ts| ts.to_string()),
+                created_at: row.created_at.to_string(),
+            })
+            .collect())
+    }
+
+    async fn find_by_credential_id(
+        &self,
+        credential_id: &str,
+    ) -> Result<Option<StoredPasskey>, sqlx::Error> {
+        let passkey = sqlx::query!(
+            "SELECT id, user_id, public_key, sign_count FROM passkeys WHERE credential_id = ?",
+            credential_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(passkey.map(|row| StoredPasskey {
+            id: row.id.expect("id should not be null"),
+            user_id: row.user_id,
+            public_key: row.public_key,
+            sign_count: row.sign_count,
+        }))
+    }
+
+    pub async fn create_passkey(
+        &self,
+        user_id: i64,
+        name: &str,
+        credential_id: &str,
+        public_key: &[u8],
+        sign_count: u32,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO passkeys (user_id, name, credential_id, public_key, sign_count)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+            user_id,
+            name,
+            credential_id,
+            public_key,
+            sign_count
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn record_use(&self, id: i64, sign_count: u32) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE passkeys SET sign_count = ?, last_used_at = CURRENT_TIMESTAMP WHERE id = ?",
+            sign_count,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fails with `RowNotFound` unless the passkey belongs to the user.
+    pub async fn delete_passkey(&self, id: i64, user_id: i64) -> Result<(), sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM passkeys WHERE id = ? AND user_id = ?",
+            id,
+            user_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+
+    pub async fn create_challenge(
+        &self,
+        challenge: &str,
+        ceremony: Ceremony,
+        user_id: Option<i64>,
+        lifetime: Duration,
+    ) -> Result<(), sqlx::Error> {
+        let ceremony = ceremony.as_str();
+        let lifetime = format!("+{} seconds", lifetime.num_seconds());
+        sqlx::query!(
+            r#"
+            INSERT INTO passkey_challenges (challenge, ceremony, user_id, expires_at)
+            VALUES (?, ?, ?, datetime('now', ?))
+            "#,
+            challenge,
+            ceremony,
+            user_id,
+            lifetime
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Uses up a challenge. Returns `None` when it doesn't exist, has expired
+    /// or was issued for another ceremony, otherwise the user it was issued to.
+    pub async fn consume_challenge(
+        &self,
+        challenge: &str,
+        ceremony: Ceremony,
+    ) -> Result<Option<Option<i64>>, sqlx::Error> {
+        let ceremony = ceremony.as_str();
+        let row = sqlx::query!(
+            r#"
+            DELETE FROM passkey_challenges
+            WHERE challenge = ? AND ceremony = ? AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id
+            "#,
+            challenge,
+            ceremony
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(|row| row.user_id))
+    }
+
+    pub async fn delete_expired_challenges(&self) -> Result<u64, sqlx::Error> {
+        let result =
+            sqlx::query!("DELETE FROM passkey_challenges WHERE expires_at <= CURRENT_TIMESTAMP")
+                .execute(&self.db)
+                .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Registers passkeys and checks passkey sign ins, following the WebAuthn
+/// ceremonies. Attestation isn't checked, the same as asking browsers for
+/// `"attestation": "none"`.
+pub struct PasskeyService {
+    repository: PasskeyRepository,
+    relying_party: RelyingParty,
+}
+
+impl PasskeyService {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        PasskeyService::with_relying_party(db, RelyingParty::from_env())
+    }
+
+    pub fn with_relying_party(db: &sqlx::SqlitePool, relying_party: RelyingParty) -> Self {
+        PasskeyService {
+            repository: PasskeyRepository::new(db),
+            relying_party,
+        }
+    }
+
+    pub async fn passkeys(&self, user: &User) -> Result<Vec<Passkey>, sqlx::Error> {
+        self.repository.find_all_by_user_id(user.id).await
+    }
+
+    /// The `publicKey` options for `navigator.credentials.create()`, with
+    /// binary values base64url encoded.
+    pub async fn registration_options(
+        &self,
+        user: &User,
+    ) -> Result<serde_json::Value, sqlx::Error> {
+        let challenge = self
+            .new_challenge(Ceremony::Register, Some(user.id))
+            .await?;
+        let exclude_credentials: Vec<serde_json::Value> = self
+            .repository
+            .find_all_by_user_id(user.id)
+            .await?
+            .into_iter()
+            .map(|passkey| serde_json::json!({ "type": "public-key", "id": passkey.credential_id }))
+            .collect();
+
+        Ok(serde_json::json!({
+            "challenge": challenge,
+            "rp": { "id": self.relying_party.id, "name": "Wordford" },
+            "user": {
+                "id": URL_SAFE_NO_PAD.encode(user.id.to_string()),
+                "name": user.email,
+                "displayName": format!("{} {}", user.given_name, user.family_name),
+            },
+            "pubKeyCredParams": [
+                { "type": "public-key", "alg": ES256 },
+                { "type": "public-key", "alg": EDDSA },
+                { "type": "public-key", "alg": RS256 },
+            ],
+            "timeout": CHALLENGE_LIFETIME.num_milliseconds(),
+            "attestation": "none",
+            "authenticatorSelection": {
+                "residentKey": "required",
+                "userVerification": "required",
+            },
+            "excludeCredentials": exclude_credentials,
+        }))
+    }
+
+    pub async fn register(
+        &self,
+        user: &User,
+        request: &NewPasskeyRequest,
+    ) -> Result<(), ServiceError> {
+        let name = request.name.trim();
+        if name.is_empty() {
+            return Err(ServiceError::Invalid(
+                "Give your passkey a name.".to_string(),
+            ));
+        }
+
+        let client_data_json = decode(&request.client_data_json)?;
+        let attestation_object = decode(&request.attestation_object)?;
+
+        let challenge = self.check_client_data(&client_data_json, Ceremony::Register)?;
+        match self
+            .repository
+            .consume_challenge(&challenge, Ceremony::Register)
+            .await?
+        {
+            Some(Some(user_id)) if user_id == user.id => {}
+            _ => return Err(expired()),
+        }
+
+        let authenticator_data = attested_authenticator_data(&attestation_object)?;
+        let authenticator_data = AuthenticatorData::parse(&authenticator_data)?;
+        self.check_authenticator_data(&authenticator_data)?;
+        let Some((credential_id, public_key)) = authenticator_data.attested_credential else {
+            return Err(invalid_response());
+        };
+        PublicKey::from_cose(public_key)?;
+
+        let credential_id = URL_SAFE_NO_PAD.encode(credential_id);
+        match self
+            .repository
+            .create_passkey(
+                user.id,
+                name,
+                &credential_id,
+                public_key,
+                authenticator_data.sign_count,
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => Err(
+                ServiceError::Invalid("That passkey is already registered.".to_string()),
+            ),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn delete(&self, user: &User, id: i64) -> Result<(), sqlx::Error> {
+        self.repository.delete_passkey(id, user.id).await
+    }
+
+    /// The `publicKey` options for `navigator.credentials.get()`. No
+    /// credentials are listed, so the browser offers every passkey it has
+    /// for the site.
+    pub async fn authentication_options(&self) -> Result<serde_json::Value, sqlx::Error> {
+        let challenge = self.new_challenge(Ceremony::Authenticate, None).await?;
+
+        Ok(serde_json::json!({
+            "challenge": challenge,
+            "rpId": self.relying_party.id,
+            "timeout": CHALLENGE_LIFETIME.num_milliseconds(),
+            "userVerification": "required",
+        }))
+    }
+
+    /// Checks a signed assertion from the browser and returns whose passkey
+    /// made it.
+    pub async fn authenticate(&self, request: &PasskeySignInRequest) -> Result<i64, ServiceError> {
+        let client_data_json = decode(&request.client_data_json)?;
+        let authenticator_data = decode(&request.authenticator_data)?;
+        let signature = decode(&request.signature)?;
+
+        let challenge = self.check_client_data(&client_data_json, Ceremony::Authenticate)?;
+        if self
+            .repository
+            .consume_challenge(&challenge, Ceremony::Authenticate)
+            .await?
+            .is_none()
+        {
+            return Err(expired());
+        }
+
+        let Some(passkey) = self.repository.find_by_credential_id(&request.id).await? else {
+            return Err(ServiceError::Invalid(
+                "That passkey isn't registered with Wordford.".to_string(),
+            ));
+        };
+
+        let parsed = AuthenticatorData::parse(&authenticator_data)?;
+        self.check_authenticator_data(&parsed)?;
+
+        let mut message = authenticator_data.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data_json));
+        PublicKey::from_cose(&passkey.public_key)?.verify(&message, &signature)?;
+
+        // a counter that doesn't go up suggests the passkey was cloned, except
+        // for authenticators that don't keep one and always report zero
+        let sign_count = parsed.sign_count;
+        if (sign_count != 0 || passkey.sign_count != 0)
+            && i64::from(sign_count) <= passkey.sign_count
+        {
+            return Err(ServiceError::Invalid(
+                "This passkey can't be trusted anymore. Sign in another way and register it again."
+                    .to_string(),
+            ));
+        }
+
+        self.repository.record_use(passkey.id, sign_count).await?;
+
+        Ok(passkey.user_id)
+    }
+
+    async fn new_challenge(
+        &self,
+        ceremony: Ceremony,
+        user_id: Option<i64>,
+    ) -> Result<String, sqlx::Error> {
+        let challenge = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        self.repository
+            .create_challenge(&challenge, ceremony, user_id, CHALLENGE_LIFETIME)
+            .await?;
+
+        Ok(challenge)
+    }
+
+    /// Checks the browser ran the ceremony we expect on our own site, and
+    /// returns the challenge it was given.
+    fn check_client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony: Ceremony,
+    ) -> Result<String, ServiceError> {
+        #[derive(Deserialize)]
+        struct ClientData {
+            #[serde(rename = "type")]
+            kind: String,
+            challenge: String,
+            origin: String,
+        }
+
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).map_err(|_| invalid_response())?;
+        if client_data.kind != ceremony.client_data_type()
+            || client_data.origin != self.relying_party.origin
+        {
+            return Err(invalid_response());
+        }
+
+        Ok(client_data.challenge)
+    }
+
+    fn check_authenticator_data(
+        &self,
+        authenticator_data: &AuthenticatorData,
+    ) -> Result<(), ServiceError> {
+        let rp_id_hash = Sha256::digest(self.relying_party.id.as_bytes());
+        if authenticator_data.rp_id_hash != rp_id_hash.as_slice() {
+            return Err(invalid_response());
+        }
+
+        let flags = FLAG_USER_PRESENT | FLAG_USER_VERIFIED;
+        if authenticator_data.flags & flags != flags {
+            return Err(ServiceError::Invalid(
+                "Your device didn't confirm it was you. Please try again.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The fixed layout at the start of every authenticator response, see
+/// https://www.w3.org/TR/webauthn-2/#sctn-authenticator-data
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// The new credential's id and COSE public key, when registering.
+    attested_credential: Option<(&'a [u8], &'a [u8])>,
+}
+
+impl<'a> AuthenticatorData<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, ServiceError> {
+        if bytes.len() < 37 {
+            return Err(invalid_response());
+        }
+
+        let flags = bytes[32];
+        let sign_count = u32::from_be_bytes(bytes[33..37].try_into().expect("four bytes"));
+        let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+            // 16 byte AAGUID, then the length of the credential id
+            let rest = bytes.get(37..).filter(|rest| rest.len() >= 18);
+            let rest = rest.ok_or_else(invalid_response)?;
+            let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+            let rest = &rest[18..];
+            let credential_id = rest.get(..id_len).ok_or_else(invalid_response)?;
+
+            // the public key is followed by extensions, if there are any, so
+            // read one CBOR value to find where it ends
+            let key_start = &rest[id_len..];
+            let mut reader = key_start;
+            ciborium::from_reader::<Value, _>(&mut reader).map_err(|_| invalid_response())?;
+            let public_key = &key_start[..key_start.len() - reader.len()];
+
+            Some((credential_id, public_key))
+        } else {
+            None
+        };
+
+        Ok(AuthenticatorData {
+            rp_id_hash: &bytes[..32],
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+}
+
+/// Pulls `authData` out of a CBOR attestation object.
+fn attested_authenticator_data(attestation_object: &[u8]) -> Result<Vec<u8>, ServiceError> {
+    let Ok(Value::Map(entries)) = ciborium::from_reader::<Value, _>(attestation_object) else {
+        return Err(invalid_response());
+    };
+
+    entries
+        .into_iter()
+        .find(|(key, _)| key.as_text() == Some("authData"))
+        .and_then(|(_, value)| value.into_bytes().ok())
+        .ok_or_else(invalid_response)
+}
+
+enum PublicKey {
+    Es256(Vec<u8>),
+    EdDsa(Vec<u8>),
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+impl PublicKey {
+    /// Reads a COSE key, accepting only the key type and curve that go with
+    /// its algorithm, so that a key can't claim one algorithm and carry
+    /// another's parameters.
+    fn from_cose(cose_key: &[u8]) -> Result<Self, ServiceError> {
+        let Ok(Value::Map(entries)) = ciborium::from_reader::<Value, _>(cose_key) else {
+            return Err(invalid_response());
+        };
+        let field = |label: i64| {
+            entries
+                .iter()
+                .find(|(key, _)| {
+                    key.as_integer().and_then(|key| i64::try_from(key).ok()) == Some(label)
+                })
+                .map(|(_, value)| value)
+        };
+        let bytes = |label: i64| {
+            field(label)
+                .and_then(Value::as_bytes)
+                .cloned()
+                .ok_or_else(invalid_response)
+        };
+        let integer = |label: i64| {
+            field(label)
+                .and_then(Value::as_integer)
+                .and_then(|value| i64::try_from(value).ok())
+        };
+
+        match (integer(1), integer(3), integer(-1)) {
+            (Some(KTY_EC2), Some(ES256), Some(CRV_P256)) => {
+                let (x, y) = (bytes(-2)?, bytes(-3)?);
+                if x.len() != 32 || y.len() != 32 {
+                    return Err(invalid_response());
+                }
+                Ok(PublicKey::Es256([&[0x04], &x[..], &y[..]].concat()))
+            }
+            (Some(KTY_OKP), Some(EDDSA), Some(CRV_ED25519)) => {
+                let x = bytes(-2)?;
+                if x.len() != 32 {
+                    return Err(invalid_response());
+                }
+                Ok(PublicKey::EdDsa(x))
+            }
+            (Some(KTY_RSA), Some(RS256), None) => {
+                let (n, e) = (bytes(-1)?, bytes(-2)?);
+                // ring won't verify with anything shorter anyway
+                if n.len() < 256 {
+                    return Err(invalid_response());
+                }
+                Ok(PublicKey::Rs256 { n, e })
+            }
+            _ => Err(ServiceError::Invalid(
+                "Passkeys of this kind aren't supported.".to_string(),
+            )),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), ServiceError> {
+        let result = match self {
+            PublicKey::Es256(point) => {
+                UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                    .verify(message, signature)
+            }
+            PublicKey::EdDsa(key) => {
+                UnparsedPublicKey::new(&signature::ED25519, key).verify(message, signature)
+            }
+            PublicKey::Rs256 { n, e } => RsaPublicKeyComponents { n, e }.verify(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                message,
+                signature,
+            ),
+        };
+
+        result.map_err(|_| invalid_response())
+    }
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, ServiceError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| invalid_response())
+}
+
+fn invalid_response() -> ServiceError {
+    ServiceError::Invalid("Your passkey's response couldn't be verified.".to_string())
+}
+
+fn expired() -> ServiceError {
+    ServiceError::Invalid("That took too long. Please try again.".to_string())
+}
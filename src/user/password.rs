@@ -0,0 +1,236 @@
+use std::{collections::HashSet, env, fs, sync::LazyLock};
+
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::{self, SaltString},
+};
+
+use crate::services::error::ServiceError;
+
+/// A few common picks that are long enough to get past the length rule, for
+/// when no list is configured.
+const BUILT_IN_COMMON_PASSWORDS: &[&str] = &[
+    "123456789012",
+    "1234567890123",
+    "aaaaaaaaaaaa",
+    "abc123abc123",
+    "administrator",
+    "changeme1234",
+    "iloveyou1234",
+    "letmein12345",
+    "password1234",
+    "password12345",
+    "passwordpassword",
+    "qwerty123456",
+    "qwertyuiop123",
+    "trustno1trustno1",
+    "welcome12345",
+    "wordford1234",
+];
+
+/// Passwords people commonly pick, which are the first ones anyone guessing
+/// would try: the built-in few, plus those in `COMMON_PASSWORDS_FILE`. Read
+/// once, on first use.
+static COMMON_PASSWORDS: LazyLock<HashSet<String>> = LazyLock::new(|| {
+    let mut passwords = parse_common_passwords(&BUILT_IN_COMMON_PASSWORDS.join("\n"));
+
+    if let Ok(path) = env::var("COMMON_PASSWORDS_FILE") {
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let list = String::from_utf8_lossy(&bytes);
+                passwords.extend(parse_common_passwords(&list));
+            }
+            Err(err) => {
+                tracing::warn!("couldn't read common passwords from {}: {}", path, err);
+            }
+        }
+    }
+
+    passwords
+});
+
+/// One password per line, as in the usual lists. Blank lines and lines
+/// starting with `#` are skipped.
+fn parse_common_passwords(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Argon2id parameters, from `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and
+/// `ARGON2_PARALLELISM`. The defaults are OWASP's recommended minimum.
+fn argon2() -> Argon2<'static> {
+    let setting = |name: &str, default: u32| {
+        env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    let params = Params::new(
+        setting("ARGON2_MEMORY_KIB", 19 * 1024),
+        setting("ARGON2_ITERATIONS", 2),
+        setting("ARGON2_PARALLELISM", 1),
+        None,
+    )
+    .unwrap_or_else(|err| {
+        tracing::warn!("invalid Argon2 settings, using the defaults: {}", err);
+        Params::default()
+    });
+
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+/// Hashes a password with Argon2id. Hashing is slow on purpose, so it runs
+/// off the async threads.
+pub async fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let password = password.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())?;
+        let hash = argon2().hash_password(password.as_bytes(), &salt)?;
+        Ok(hash.to_string())
+    })
+    .await
+    .expect("password hashing panicked")
+}
+
+/// Checks a password against an Argon2 hash, or a bcrypt one from before
+/// Wordford used Argon2.
+pub async fn verify_password(password: &str, hashed: &str) -> bool {
+    let password = password.to_string();
+    let hashed = hashed.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        if is_bcrypt(&hashed) {
+            return bcrypt::verify(&password, &hashed).unwrap_or(false);
+        }
+
+        PasswordHash::new(&hashed)
+            .and_then(|hash| argon2().verify_password(password.as_bytes(), &hash))
+            .is_ok()
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Whether a hash should be replaced the next time the password is known:
+/// it isn't Argon2id, or was made with other parameters than the current ones.
+pub fn needs_rehash(hashed: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hashed) else {
+        return true;
+    };
+    let Ok(params) = Params::try_from(&hash) else {
+        return true;
+    };
+    let current = argon2();
+    let current = current.params();
+
+    hash.algorithm != Algorithm::Argon2id.ident()
+        || hash.version != Some(Version::V0x13.into())
+        || params.m_cost() != current.m_cost()
+        || params.t_cost() != current.t_cost()
+        || params.p_cost() != current.p_cost()
+}
+
+fn is_bcrypt(hashed: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| hashed.starts_with(prefix))
+}
+
+/// What makes a password acceptable. The minimum length is
+/// `PASSWORD_MIN_LENGTH`, 12 unless set.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        PasswordPolicy {
+            min_length: env::var("PASSWORD_MIN_LENGTH")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(12),
+            max_length: 128,
+        }
+    }
+
+    /// Fails with a message saying what to change. `personal` holds things
+    /// like the person's email and name, which shouldn't be in the password.
+    pub fn check(&self, password: &str, personal: &[&str]) -> Result<(), ServiceError> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(ServiceError::Invalid(format!(
+                "Use at least {} characters for your password.",
+                self.min_length
+            )));
+        }
+        if length > self.max_length {
+            return Err(ServiceError::Invalid(format!(
+                "Use at most {} characters for your password.",
+                self.max_length
+            )));
+        }
+
+        let lowercase = password.to_lowercase();
+        if COMMON_PASSWORDS.contains(&lowercase) {
+            return Err(ServiceError::Invalid(
+                "That password is too common. Please choose another.".to_string(),
+            ));
+        }
+
+        let mut chars = lowercase.chars();
+        let first = chars.next();
+        if chars.all(|c| Some(c) == first) {
+            return Err(ServiceError::Invalid(
+                "Use more than one character in your password.".to_string(),
+            ));
+        }
+
+        let contains_personal = personal
+            .iter()
+            // just the part before the @ for emails
+            .filter_map(|value| value.split('@').next())
+            .map(|value| value.trim().to_lowercase())
+            .filter(|value| value.chars().count() >= 4)
+            .any(|value| lowercase.contains(&value));
+        if contains_personal {
+            return Err(ServiceError::Invalid(
+                "Your password shouldn't contain your name or email address.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_list_of_common_passwords() {
+        let list = "# the most common first\r\nPassword123!\n\n  letmein  \n";
+
+        let passwords = parse_common_passwords(list);
+
+        assert_eq!(
+            passwords,
+            HashSet::from(["password123!".to_string(), "letmein".to_string()])
+        );
+    }
+
+    #[test]
+    fn refuses_a_common_password_in_any_case() {
+        let policy = PasswordPolicy {
+            min_length: 12,
+            max_length: 128,
+        };
+
+        assert!(policy.check("Password1234", &[]).is_err());
+        assert!(policy.check("correct horse battery", &[]).is_ok());
+    }
+}
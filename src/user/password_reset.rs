@@ -0,0 +1,192 @@
+use chrono::Duration;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    services::error::ServiceError,
+    user::{
+        User,
+        password::{PasswordPolicy, hash_password},
+        repository::UserRepository,
+        session::SessionRepository,
+        tokens::TokenRepository,
+    },
+};
+
+/// How long a reset link can be used for.
+pub const RESET_LIFETIME: Duration = Duration::hours(1);
+
+pub struct PasswordResetRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl PasswordResetRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        PasswordResetRepository { db: db.clone() }
+    }
+
+    /// Stores a new reset for the user, retiring any they had asked for
+    /// before so that only the newest link works.
+    pub async fn create_reset(
+        &self,
+        user_id: i64,
+        token_hash: &str,
+        lifetime: Duration,
+    ) -> Result<(), sqlx::Error> {
+        let lifetime = format!("+{} seconds", lifetime.num_seconds());
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!(
+            "UPDATE password_resets SET used_at = CURRENT_TIMESTAMP WHERE user_id = ? AND used_at IS NULL",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO password_resets (user_id, token_hash, expires_at) VALUES (?, ?, datetime('now', ?))",
+            user_id,
+            token_hash,
+            lifetime
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    /// Marks an unused, unexpired reset as used and returns whose it was.
+    /// Fails with `RowNotFound` for any other token.
+    pub async fn consume(&self, token_hash: &str) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            UPDATE password_resets SET used_at = CURRENT_TIMESTAMP
+            WHERE token_hash = ? AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id
+            "#,
+            token_hash
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    pub async fn is_valid(&self, token_hash: &str) -> Result<bool, sqlx::Error> {
+        match self.find_user_id(token_hash).await {
+            Ok(_) => Ok(true),
+            Err(sqlx::Error::RowNotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Whose an unused, unexpired reset is, without using it up. Fails with
+    /// `RowNotFound` for any other token.
+    pub async fn find_user_id(&self, token_hash: &str) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT user_id FROM password_resets
+            WHERE token_hash = ? AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            "#,
+            token_hash
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+}
+
+pub struct PasswordResetService {
+    password_reset_repository: PasswordResetRepository,
+    user_repository: UserRepository,
+    session_repository: SessionRepository,
+    token_repository: TokenRepository,
+}
+
+impl PasswordResetService {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        PasswordResetService {
+            password_reset_repository: PasswordResetRepository::new(db),
+            user_repository: UserRepository::new(db),
+            session_repository: SessionRepository::new(db),
+            token_repository: TokenRepository::new(db),
+        }
+    }
+
+    /// Issues a reset token for the account with this email. Returns `None`
+    /// when there is no such account, which callers should not reveal.
+    pub async fn issue_token(&self, email: &str) -> Result<Option<(User, String)>, sqlx::Error> {
+        let user = match self.user_repository.find_by_email(email.trim()).await {
+            Ok(user) => user,
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        self.password_reset_repository
+            .create_reset(user.id, &hash_token(&token), RESET_LIFETIME)
+            .await?;
+
+        Ok(Some((user, token)))
+    }
+
+    pub async fn is_valid_token(&self, token: &str) -> Result<bool, sqlx::Error> {
+        self.password_reset_repository
+            .is_valid(&hash_token(token))
+            .await
+    }
+
+    /// Sets a new password using a reset token, then signs the user out
+    /// everywhere and revokes their access tokens, so that anyone who got
+    /// in with the old password loses access.
+    pub async fn reset_password(
+        &self,
+        token: &str,
+        password: &str,
+        password_confirmation: &str,
+    ) -> Result<(), ServiceError> {
+        if password != password_confirmation {
+            return Err(ServiceError::Invalid(
+                "The passwords don't match.".to_string(),
+            ));
+        }
+        let token_hash = hash_token(token);
+
+        // the link stays usable if the password is refused, so they can try
+        // another one
+        let user_id = self
+            .password_reset_repository
+            .find_user_id(&token_hash)
+            .await
+            .map_err(used_up)?;
+        let user = self.user_repository.find_by_id(user_id).await?;
+        PasswordPolicy::from_env().check(
+            password,
+            &[&user.email, &user.given_name, &user.family_name],
+        )?;
+        let user_id = self
+            .password_reset_repository
+            .consume(&token_hash)
+            .await
+            .map_err(used_up)?;
+
+        let password_hash = hash_password(password).await?;
+        self.user_repository
+            .update_password(user_id, &password_hash)
+            .await?;
+        self.session_repository.revoke_all_for_user(user_id).await?;
+        self.token_repository.revoke_all_for_user(user_id).await?;
+
+        Ok(())
+    }
+}
+
+/// The error for a reset token that can't be used, or the lookup failing.
+fn used_up(err: sqlx::Error) -> ServiceError {
+    match err {
+        sqlx::Error::RowNotFound => {
+            ServiceError::Invalid("This reset link has expired or already been used.".to_string())
+        }
+        err => err.into(),
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,340 @@
+use chrono::{Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+
+use crate::{
+    mailer::absolute_url,
+    services::error::ServiceError,
+    user::{
+        EmailChangeClaims, User,
+        auth::derived_secret,
+        password::{PasswordPolicy, hash_password, verify_password},
+        repository::UserRepository,
+        role::Role,
+        session::SessionRepository,
+        tokens::TokenRepository,
+    },
+};
+
+/// How many `critter_*.svg` avatars ship in `public/images`.
+pub const CRITTER_COUNT: u8 = 11;
+
+/// The largest avatar that can be uploaded, in bytes.
+pub const MAX_AVATAR_SIZE: usize = 1024 * 1024;
+
+/// How long the link confirming a new email address can be used for.
+pub const EMAIL_CHANGE_LIFETIME: Duration = Duration::days(1);
+
+pub struct AvatarRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl AvatarRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        AvatarRepository { db: db.clone() }
+    }
+
+    /// The uploaded image's content type and bytes.
+    pub async fn find(&self, user_id: i64) -> Result<(String, Vec<u8>), sqlx::Error> {
+        let avatar = sqlx::query!(
+            "SELECT content_type, data FROM user_avatars WHERE user_id = ?",
+            user_id
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok((avatar.content_type, avatar.data))
+    }
+
+    pub async fn save(
+        &self,
+        user_id: i64,
+        content_type: &str,
+        data: &[u8],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_avatars (user_id, content_type, data) VALUES (?, ?, ?)
+            ON CONFLICT (user_id) DO UPDATE
+            SET content_type = excluded.content_type, data = excluded.data,
+                created_at = CURRENT_TIMESTAMP
+            "#,
+            user_id,
+            content_type,
+            data
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, user_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM user_avatars WHERE user_id = ?", user_id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Lets people look after their own account: their name, avatar, email and
+/// password, or deleting it altogether.
+pub struct ProfileService {
+    user_repository: UserRepository,
+    avatar_repository: AvatarRepository,
+    session_repository: SessionRepository,
+    token_repository: TokenRepository,
+}
+
+impl ProfileService {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        ProfileService {
+            user_repository: UserRepository::new(db),
+            avatar_repository: AvatarRepository::new(db),
+            session_repository: SessionRepository::new(db),
+            token_repository: TokenRepository::new(db),
+        }
+    }
+
+    pub async fn update_name(
+        &self,
+        user: &User,
+        given_name: &str,
+        family_name: &str,
+    ) -> Result<User, ServiceError> {
+        let (given_name, family_name) = (given_name.trim(), family_name.trim());
+        if given_name.is_empty() || family_name.is_empty() {
+            return Err(ServiceError::Invalid(
+                "Enter both your first and last name.".to_string(),
+            ));
+        }
+        if given_name.chars().count() > 20 || family_name.chars().count() > 20 {
+            return Err(ServiceError::Invalid(
+                "Names can be at most 20 characters.".to_string(),
+            ));
+        }
+
+        self.user_repository
+            .update_name(user.id, given_name, family_name)
+            .await?;
+
+        Ok(User {
+            given_name: given_name.to_string(),
+            family_name: family_name.to_string(),
+            ..user.clone()
+        })
+    }
+
+    /// Sets a new password once the current one checks out. Every other
+    /// session is signed out, leaving only `session_id`, and their access
+    /// tokens are revoked.
+    pub async fn change_password(
+        &self,
+        user: &User,
+        session_id: &str,
+        current_password: &str,
+        password: &str,
+        password_confirmation: &str,
+    ) -> Result<(), ServiceError> {
+        self.check_password(user, current_password).await?;
+        if password != password_confirmation {
+            return Err(ServiceError::Invalid(
+                "The new passwords don't match.".to_string(),
+            ));
+        }
+        PasswordPolicy::from_env().check(
+            password,
+            &[&user.email, &user.given_name, &user.family_name],
+        )?;
+
+        let password_hash = hash_password(password).await?;
+        self.user_repository
+            .update_password(user.id, &password_hash)
+            .await?;
+        self.session_repository
+            .revoke_others(user.id, session_id)
+            .await?;
+        self.token_repository.revoke_all_for_user(user.id).await?;
+
+        Ok(())
+    }
+
+    /// Checks that someone may move their account to `new_email`, and returns
+    /// the token for the link that confirms they own it. The email only
+    /// changes once that link is followed.
+    pub async fn request_email_change(
+        &self,
+        user: &User,
+        new_email: &str,
+        current_password: &str,
+    ) -> Result<String, ServiceError> {
+        self.check_password(user, current_password).await?;
+
+        let new_email = new_email.trim();
+        if !new_email.contains('@') {
+            return Err(ServiceError::Invalid(
+                "Enter a valid email address.".to_string(),
+            ));
+        }
+        if new_email.eq_ignore_ascii_case(&user.email) {
+            return Err(ServiceError::Invalid(
+                "That's already your email address.".to_string(),
+            ));
+        }
+        match self.user_repository.find_by_email(new_email).await {
+            Ok(_) => {
+                return Err(ServiceError::Invalid(
+                    "Another account already uses that email address.".to_string(),
+                ));
+            }
+            Err(sqlx::Error::RowNotFound) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let claims = EmailChangeClaims {
+            sub: user.id,
+            old_email: user.email.clone(),
+            new_email: new_email.to_string(),
+            exp: Utc::now()
+                .checked_add_signed(EMAIL_CHANGE_LIFETIME)
+                .expect("valid timestamp")
+                .timestamp() as usize,
+        };
+        let key = EncodingKey::from_secret(derived_secret("email-change").as_bytes());
+
+        Ok(encode(&Header::new(Algorithm::HS256), &claims, &key)
+            .expect("failed to sign email change token"))
+    }
+
+    /// Moves the account to the address in a confirmation link. Returns the
+    /// user as they were, so the old address can be told about the change.
+    pub async fn confirm_email_change(&self, token: &str) -> Result<(User, String), ServiceError> {
+        let key = DecodingKey::from_secret(derived_secret("email-change").as_bytes());
+        let invalid = || {
+            ServiceError::Invalid(
+                "This confirmation link is invalid or has expired. You can ask for a new one from your account settings."
+                    .to_string(),
+            )
+        };
+
+        let claims = decode::<EmailChangeClaims>(token, &key, &Validation::default())
+            .map_err(|_| invalid())?
+            .claims;
+        let user = match self.user_repository.find_by_id(claims.sub).await {
+            Ok(user) if user.email == claims.old_email => user,
+            Ok(_) | Err(sqlx::Error::RowNotFound) => return Err(invalid()),
+            Err(err) => return Err(err.into()),
+        };
+
+        match self
+            .user_repository
+            .update_email(user.id, &claims.new_email)
+            .await
+        {
+            Ok(_) => Ok((user, claims.new_email)),
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                Err(ServiceError::Invalid(
+                    "Another account has started using that email address.".to_string(),
+                ))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Switches to one of the bundled critter avatars.
+    pub async fn choose_critter(&self, user: &User, critter: u8) -> Result<String, ServiceError> {
+        if !(1..=CRITTER_COUNT).contains(&critter) {
+            return Err(ServiceError::Invalid(
+                "Pick one of the critters.".to_string(),
+            ));
+        }
+
+        let avatar_url = absolute_url(&format!("/assets/images/critter_{}.svg", critter));
+        self.user_repository
+            .update_avatar_url(user.id, &avatar_url)
+            .await?;
+        self.avatar_repository.delete(user.id).await?;
+
+        Ok(avatar_url)
+    }
+
+    /// Uses an uploaded PNG, JPEG, GIF or WebP image as the avatar. The type
+    /// is worked out from the image itself rather than trusting the upload,
+    /// and SVGs aren't accepted because they can carry scripts.
+    pub async fn upload_avatar(&self, user: &User, data: &[u8]) -> Result<String, ServiceError> {
+        if data.len() > MAX_AVATAR_SIZE {
+            return Err(ServiceError::Invalid(
+                "Avatars can be at most 1 MB.".to_string(),
+            ));
+        }
+        let Some(content_type) = image_type(data) else {
+            return Err(ServiceError::Invalid(
+                "Upload a PNG, JPEG, GIF or WebP image.".to_string(),
+            ));
+        };
+
+        self.avatar_repository
+            .save(user.id, content_type, data)
+            .await?;
+        // a new address each time, so browsers don't keep showing the old one
+        let avatar_url = absolute_url(&format!(
+            "/users/{}/avatar?v={}",
+            user.id,
+            hex::encode(rand::random::<[u8; 4]>())
+        ));
+        self.user_repository
+            .update_avatar_url(user.id, &avatar_url)
+            .await?;
+
+        Ok(avatar_url)
+    }
+
+    pub async fn avatar(&self, user_id: i64) -> Result<(String, Vec<u8>), sqlx::Error> {
+        self.avatar_repository.find(user_id).await
+    }
+
+    /// Deletes the account once the password checks out. The last admin
+    /// can't leave, or nobody could manage Wordford any more.
+    pub async fn delete_account(
+        &self,
+        user: &User,
+        current_password: &str,
+    ) -> Result<(), ServiceError> {
+        self.check_password(user, current_password).await?;
+        if user.role == Role::Admin && self.user_repository.count_admins().await? <= 1 {
+            return Err(ServiceError::Invalid(
+                "You're the only admin. Make someone else an admin before deleting your account."
+                    .to_string(),
+            ));
+        }
+
+        self.user_repository.delete(user.id).await?;
+
+        Ok(())
+    }
+
+    async fn check_password(&self, user: &User, password: &str) -> Result<(), ServiceError> {
+        let password_hash = self.user_repository.find_password_hash(user.id).await?;
+        if !verify_password(password, &password_hash).await {
+            return Err(ServiceError::Invalid(
+                "Your current password isn't right.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() > 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
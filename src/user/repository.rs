@@ -1,4 +1,4 @@
-use crate::user::{CreateUserRequest, User};
+use crate::user::{CreateUserRequest, User, role::Role};
 
 pub struct UserRepository {
     db: sqlx::SqlitePool,
@@ -19,9 +19,13 @@ impl UserRepository {
             given_name: user.given_name,
             family_name: user.family_name,
             avatar_url: user.avatar_url,
-            role: user.role,
+            role: Role::from(user.role),
+            email_verified_at: user.email_verified_at.map(|t| t.to_string()),
+            disabled_at: user.disabled_at.map(|t| t.to_string()),
             created_at: user.created_at.to_string(),
             updated_at: user.updated_at.to_string(),
+            token_scopes: None,
+            api_key_id: None,
         })
     }
 
@@ -35,41 +39,258 @@ impl UserRepository {
             given_name: user.given_name,
             family_name: user.family_name,
             avatar_url: user.avatar_url,
-            role: user.role,
+            role: Role::from(user.role),
+            email_verified_at: user.email_verified_at.map(|t| t.to_string()),
+            disabled_at: user.disabled_at.map(|t| t.to_string()),
             created_at: user.created_at.to_string(),
             updated_at: user.updated_at.to_string(),
+            token_scopes: None,
+            api_key_id: None,
         })
     }
 
+    /// Inserts a user with the given role. The very first user is always
+    /// made an admin, so that a fresh install can be set up.
     pub async fn create_user(
         &self,
         create_user_request: &CreateUserRequest,
+        role: Role,
+        email_verified: bool,
     ) -> Result<User, sqlx::Error> {
         let critter_num = rand::random::<u8>() % 11 + 1;
         let avatar_url = format!(
             "http://localhost:3000/assets/images/critter_{}.svg",
             critter_num
         );
+        let role = role.as_i64();
+        let admin = Role::Admin.as_i64();
         let user = sqlx::query!(
-            "INSERT INTO users (email, given_name, family_name, password_hash, avatar_url) VALUES (?, ?, ?, ?, ?)",
+            r#"
+            INSERT INTO users (email, given_name, family_name, password_hash, avatar_url, role, email_verified_at)
+            VALUES (?, ?, ?, ?, ?,
+                CASE WHEN EXISTS (SELECT 1 FROM users) THEN ? ELSE ? END,
+                CASE WHEN ? THEN CURRENT_TIMESTAMP END)
+            RETURNING id, role, email_verified_at, disabled_at, created_at, updated_at
+            "#,
             create_user_request.email,
             create_user_request.given_name,
             create_user_request.family_name,
             create_user_request.password,
-            avatar_url
+            avatar_url,
+            role,
+            admin,
+            email_verified
         )
-        .execute(&self.db)
+        .fetch_one(&self.db)
         .await?;
 
         Ok(User {
-            id: user.last_insert_rowid(),
+            id: user.id,
             email: create_user_request.email.to_string(),
             given_name: create_user_request.given_name.to_string(),
             family_name: create_user_request.family_name.to_string(),
-            avatar_url: avatar_url,
-            role: 1,
-            created_at: chrono::Utc::now().to_string(),
-            updated_at: chrono::Utc::now().to_string(),
+            avatar_url,
+            role: Role::from(user.role),
+            email_verified_at: user.email_verified_at.map(|t| t.to_string()),
+            disabled_at: user.disabled_at.map(|t| t.to_string()),
+            created_at: user.created_at.to_string(),
+            updated_at: user.updated_at.to_string(),
+            token_scopes: None,
+            api_key_id: None,
         })
     }
+
+    pub async fn has_users(&self) -> Result<bool, sqlx::Error> {
+        let user = sqlx::query!("SELECT id FROM users LIMIT 1")
+            .fetch_optional(&self.db)
+            .await?;
+
+        Ok(user.is_some())
+    }
+
+    pub async fn update_password(&self, id: i64, password_hash: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE users SET password_hash = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            password_hash,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_role(&self, id: i64, role: Role) -> Result<(), sqlx::Error> {
+        let role = role.as_i64();
+        sqlx::query!(
+            "UPDATE users SET role = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            role,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_name(
+        &self,
+        id: i64,
+        given_name: &str,
+        family_name: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE users SET given_name = ?, family_name = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            given_name,
+            family_name,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Changes the user's email to one they have just proven they own.
+    pub async fn update_email(&self, id: i64, email: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET email = ?, email_verified_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            "#,
+            email,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_avatar_url(&self, id: i64, avatar_url: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE users SET avatar_url = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            avatar_url,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_password_hash(&self, id: i64) -> Result<String, sqlx::Error> {
+        sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = ?", id)
+            .fetch_one(&self.db)
+            .await
+    }
+
+    /// Admins whose accounts aren't disabled.
+    pub async fn count_admins(&self) -> Result<i64, sqlx::Error> {
+        let admin = Role::Admin.as_i64();
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM users WHERE role = ? AND disabled_at IS NULL"#,
+            admin
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    /// Deletes the user along with everything that only makes sense for
+    /// them. Content history they wrote is kept without their name.
+    pub async fn delete(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM users WHERE id = ?", id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Users whose name or email contains `search_str`, newest first.
+    pub async fn search(
+        &self,
+        search_str: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>, sqlx::Error> {
+        let pattern = format!("%{}%", search_str.trim());
+        let users = sqlx::query!(
+            r#"
+            SELECT * FROM users
+            WHERE LOWER(email) LIKE LOWER(?)
+               OR LOWER(given_name || ' ' || family_name) LIKE LOWER(?)
+            ORDER BY id DESC
+            LIMIT ? OFFSET ?
+            "#,
+            pattern,
+            pattern,
+            limit,
+            offset
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(users
+            .into_iter()
+            .map(|user| User {
+                id: user.id,
+                email: user.email,
+                given_name: user.given_name,
+                family_name: user.family_name,
+                avatar_url: user.avatar_url,
+                role: Role::from(user.role),
+                email_verified_at: user.email_verified_at.map(|t| t.to_string()),
+                disabled_at: user.disabled_at.map(|t| t.to_string()),
+                created_at: user.created_at.to_string(),
+                updated_at: user.updated_at.to_string(),
+                token_scopes: None,
+                api_key_id: None,
+            })
+            .collect())
+    }
+
+    /// How many users `search` would find without a limit.
+    pub async fn count_matching(&self, search_str: &str) -> Result<i64, sqlx::Error> {
+        let pattern = format!("%{}%", search_str.trim());
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!: i64" FROM users
+            WHERE LOWER(email) LIKE LOWER(?)
+               OR LOWER(given_name || ' ' || family_name) LIKE LOWER(?)
+            "#,
+            pattern,
+            pattern
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    pub async fn set_disabled(&self, id: i64, disabled: bool) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET disabled_at = CASE WHEN ? THEN COALESCE(disabled_at, CURRENT_TIMESTAMP) END,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            "#,
+            disabled,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_email_verified(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE users SET email_verified_at = CURRENT_TIMESTAMP WHERE id = ? AND email_verified_at IS NULL",
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
 }
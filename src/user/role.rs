@@ -0,0 +1,123 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::user::User;
+
+/// Roles are stored in `users.role` as integers. The numbering is ordered so
+/// that every role can do everything the roles below it can.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer = 0,
+    Editor = 1,
+    Publisher = 2,
+    Admin = 3,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Viewer, Role::Editor, Role::Publisher, Role::Admin];
+
+    pub fn as_i64(&self) -> i64 {
+        *self as i64
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Publisher => "publisher",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        *self >= permission.minimum_role()
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    /// Parses the lowercase names used in forms and settings.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == name.trim().to_ascii_lowercase())
+            .ok_or(())
+    }
+}
+
+impl From<i64> for Role {
+    /// Unknown values fall back to the least privileged role.
+    fn from(value: i64) -> Self {
+        match value {
+            1 => Role::Editor,
+            2 => Role::Publisher,
+            3 => Role::Admin,
+            _ => Role::Viewer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    EditContent,
+    CreatePages,
+    PublishContent,
+    DeletePages,
+    ManageApps,
+    ManageApiKeys,
+    ManageMembers,
+    InviteUsers,
+    ManageSecurity,
+    ManageUsers,
+}
+
+impl Permission {
+    fn minimum_role(&self) -> Role {
+        match self {
+            Permission::EditContent | Permission::CreatePages => Role::Editor,
+            Permission::PublishContent | Permission::DeletePages => Role::Publisher,
+            Permission::ManageApps
+            | Permission::ManageApiKeys
+            | Permission::ManageMembers
+            | Permission::InviteUsers
+            | Permission::ManageSecurity
+            | Permission::ManageUsers => Role::Admin,
+        }
+    }
+}
+
+/// What a user may do, flattened so templates can hide actions with
+/// `{% if can.publish_content %}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Permissions {
+    pub edit_content: bool,
+    pub create_pages: bool,
+    pub publish_content: bool,
+    pub delete_pages: bool,
+    pub manage_apps: bool,
+    pub manage_api_keys: bool,
+    pub manage_members: bool,
+    pub invite_users: bool,
+    pub manage_security: bool,
+    pub manage_users: bool,
+}
+
+impl From<&User> for Permissions {
+    fn from(user: &User) -> Self {
+        Permissions {
+            edit_content: user.can(Permission::EditContent),
+            create_pages: user.can(Permission::CreatePages),
+            publish_content: user.can(Permission::PublishContent),
+            delete_pages: user.can(Permission::DeletePages),
+            manage_apps: user.can(Permission::ManageApps),
+            manage_api_keys: user.can(Permission::ManageApiKeys),
+            manage_members: user.can(Permission::ManageMembers),
+            invite_users: user.can(Permission::InviteUsers),
+            manage_security: user.can(Permission::ManageSecurity),
+            manage_users: user.can(Permission::ManageUsers),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    AppState,
+    extractors::current_user::CurrentUser,
+    mailer::{Email, absolute_url},
+    routes::{forbidden, insert_user},
+    services::error::ServiceError,
+    user::{
+        ChangeRoleRequest, User, UserSearchParams, admin::UserAdminService, role::Role,
+        routes::insert_error,
+    },
+};
+use axum::{
+    Form, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::{get, post, put},
+};
+use std::sync::Arc;
+
+/// The admin console for everyone's accounts. These sit behind the auth
+/// middleware.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().nest(
+        "/admin/users",
+        Router::new()
+            .route("/", get(users_page))
+            .route("/{id}/role", put(change_user_role))
+            .route("/{id}/disable", post(disable_user))
+            .route("/{id}/enable", post(enable_user))
+            .route("/{id}/reset-password", post(force_password_reset))
+            .route("/{id}/sign-out", post(sign_out_user)),
+    )
+}
+
+pub async fn users_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UserSearchParams>,
+) -> Response {
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+    render_users(&state, &user, context, &params, "admin/users.html").await
+}
+
+async fn render_users(
+    state: &AppState,
+    user: &User,
+    mut context: tera::Context,
+    params: &UserSearchParams,
+    template: &str,
+) -> Response {
+    let user_admin_service = UserAdminService::new(&state.db);
+
+    match user_admin_service
+        .list(user, &params.q, params.page.unwrap_or(1))
+        .await
+    {
+        Ok(page) => context.insert("users", &page),
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+    context.insert("roles", &Role::ALL);
+    context.insert("current_user_id", &user.id);
+
+    Html(state.tera.render(template, &context).unwrap()).into_response()
+}
+
+/// Shows how an action on a user went, above the list as it was being
+/// looked at.
+async fn render_user_action(
+    state: &AppState,
+    user: &User,
+    params: &UserSearchParams,
+    result: Result<String, ServiceError>,
+) -> Response {
+    let mut context = tera::Context::new();
+    match result {
+        Ok(message) => context.insert("success", &message),
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            context.insert("error", "That user no longer exists.")
+        }
+        Err(err) => insert_error(&mut context, err),
+    }
+
+    render_users(state, user, context, params, "admin/users_list.html").await
+}
+
+pub async fn change_user_role(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<UserSearchParams>,
+    Form(request): Form<ChangeRoleRequest>,
+) -> Response {
+    let user_admin_service = UserAdminService::new(&state.db);
+    let result = user_admin_service
+        .change_role(&user, id, request.role)
+        .await
+        .map(|changed| format!("{} is now {}.", changed.email, request.role.as_str()));
+
+    render_user_action(&state, &user, &params, result).await
+}
+
+pub async fn disable_user(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<UserSearchParams>,
+) -> Response {
+    let user_admin_service = UserAdminService::new(&state.db);
+    let result = user_admin_service.disable(&user, id).await.map(|disabled| {
+        format!(
+            "Disabled {}. They've been signed out and can't sign in again.",
+            disabled.email
+        )
+    });
+
+    render_user_action(&state, &user, &params, result).await
+}
+
+pub async fn enable_user(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<UserSearchParams>,
+) -> Response {
+    let user_admin_service = UserAdminService::new(&state.db);
+    let result = user_admin_service
+        .enable(&user, id)
+        .await
+        .map(|enabled| format!("Enabled {}.", enabled.email));
+
+    render_user_action(&state, &user, &params, result).await
+}
+
+/// Locks the user out of their password and emails them a link to choose a
+/// new one.
+pub async fn force_password_reset(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<UserSearchParams>,
+) -> Response {
+    let user_admin_service = UserAdminService::new(&state.db);
+
+    let result = match user_admin_service.force_password_reset(&user, id).await {
+        Ok((reset_user, token)) => {
+            let mut email_context = tera::Context::new();
+            email_context.insert("user", &reset_user);
+            email_context.insert(
+                "reset_url",
+                &absolute_url(&format!("/password/reset?token={}", token)),
+            );
+            let email = Email {
+                to: reset_user.email.clone(),
+                subject: "Choose a new Wordford password".to_string(),
+                body: state
+                    .tera
+                    .render("emails/forced_password_reset.txt", &email_context)
+                    .unwrap(),
+            };
+
+            match state.mailer.send(email).await {
+                Ok(_) => Ok(format!(
+                    "Reset the password for {} and sent them a link to choose a new one.",
+                    reset_user.email
+                )),
+                Err(err) => {
+                    tracing::error!("failed to send email: {}", err);
+                    Err(ServiceError::Invalid(format!(
+                        "Reset the password for {}, but couldn't send them the email. They can still use \"Forgot password\".",
+                        reset_user.email
+                    )))
+                }
+            }
+        }
+        Err(err) => Err(err),
+    };
+
+    render_user_action(&state, &user, &params, result).await
+}
+
+pub async fn sign_out_user(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<UserSearchParams>,
+) -> Response {
+    let user_admin_service = UserAdminService::new(&state.db);
+    let result = user_admin_service
+        .sign_out(&user, id)
+        .await
+        .map(|(sessions, tokens)| {
+            let sessions = match sessions {
+                1 => "Ended 1 session".to_string(),
+                count => format!("Ended {} sessions", count),
+            };
+            match tokens {
+                0 => format!("{}.", sessions),
+                1 => format!("{} and revoked 1 access token.", sessions),
+                count => format!("{} and revoked {} access tokens.", sessions, count),
+            }
+        });
+
+    render_user_action(&state, &user, &params, result).await
+}
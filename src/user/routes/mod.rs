@@ -0,0 +1,81 @@
+use crate::{
+    AppState,
+    middleware::auth::safe_return_to,
+    services::error::ServiceError,
+    user::{auth::SESSION_LIFETIME, oidc::OidcConfig},
+};
+use axum::{
+    Router,
+    http::{HeaderValue, header::SET_COOKIE},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use std::sync::Arc;
+use time::Duration;
+
+pub mod admin;
+pub mod oidc;
+pub mod passkeys;
+pub mod password_reset;
+pub mod profile;
+pub mod security;
+pub mod sessions;
+pub mod signup;
+pub mod tokens;
+pub mod two_factor;
+
+/// Everything reachable without signing in: signing up, signing in and
+/// out, and getting back into an account.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .merge(signup::public_routes())
+        .merge(sessions::public_routes())
+        .merge(passkeys::public_routes())
+        .merge(oidc::public_routes())
+        .merge(password_reset::public_routes())
+        .merge(profile::public_routes())
+}
+
+/// Lets the sign in page offer single sign-on when it's configured.
+fn insert_oidc_provider(context: &mut tera::Context) {
+    if let Some(config) = OidcConfig::from_env() {
+        context.insert("oidc_provider", &config.provider_name);
+    }
+}
+
+fn auth_cookie(token: String, max_age_secs: i64) -> Cookie<'static> {
+    Cookie::build(("auth_token", token))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(true)
+        .max_age(Duration::seconds(max_age_secs))
+        .build()
+}
+
+fn signed_in(token: String, return_to: Option<&str>) -> Response {
+    let cookie = auth_cookie(token, SESSION_LIFETIME.num_seconds());
+    let return_to = safe_return_to(return_to);
+    (
+        [
+            (
+                "HX-Redirect",
+                HeaderValue::from_str(return_to).expect("return_to is a valid header"),
+            ),
+            (
+                SET_COOKIE.as_str(),
+                HeaderValue::from_str(&cookie.to_string())
+                    .expect("failed to convert cookie to string"),
+            ),
+        ],
+        (),
+    )
+        .into_response()
+}
+
+fn insert_error(context: &mut tera::Context, err: ServiceError) {
+    match err {
+        ServiceError::Invalid(message) => context.insert("error", &message),
+        _ => context.insert("error", "An unexpected error occurred. Please try again."),
+    }
+}
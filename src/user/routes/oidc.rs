@@ -0,0 +1,171 @@
+use crate::{
+    AppState,
+    middleware::auth::safe_return_to,
+    services::error::ServiceError,
+    user::{
+        OidcCallbackParams, SignInParams,
+        auth::{AuthService, IdentityLogin, SESSION_LIFETIME},
+        oidc::{self, FLOW_LIFETIME, OidcConfig, OidcService},
+        routes::{auth_cookie, insert_oidc_provider},
+        two_factor::TwoFactorService,
+    },
+};
+use axum::{
+    Router,
+    extract::{Query, State},
+    http::{HeaderValue, StatusCode, header::SET_COOKIE},
+    response::{AppendHeaders, Html, IntoResponse, Redirect, Response},
+    routing::get,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use std::sync::Arc;
+use time::Duration;
+
+pub fn public_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/signin/oidc", get(signin_with_oidc))
+        .route("/signin/oidc/callback", get(oidc_callback))
+}
+
+/// Sends the browser to the identity provider to sign in.
+pub async fn signin_with_oidc(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SignInParams>,
+) -> Response {
+    let Some(config) = OidcConfig::from_env() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let oidc_service = OidcService::new(&state.db, config, &state.oidc_metadata);
+    let return_to = safe_return_to(params.return_to.as_deref());
+
+    match oidc_service.begin(return_to).await {
+        Ok((url, flow)) => {
+            let cookie = oidc_flow_cookie(flow, FLOW_LIFETIME.num_seconds());
+            (
+                [(
+                    SET_COOKIE,
+                    HeaderValue::from_str(&cookie.to_string())
+                        .expect("failed to convert cookie to string"),
+                )],
+                Redirect::to(&url),
+            )
+                .into_response()
+        }
+        Err(err) => oidc_failed(&state, err),
+    }
+}
+
+/// Where the identity provider sends the browser back to after signing in.
+pub async fn oidc_callback(
+    State(state): State<Arc<AppState>>,
+    jar: CookieJar,
+    Query(params): Query<OidcCallbackParams>,
+) -> Response {
+    let Some(config) = OidcConfig::from_env() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let oidc_service = OidcService::new(&state.db, config, &state.oidc_metadata);
+
+    if let Some(error) = params.error {
+        tracing::warn!("provider returned an error: {}", error);
+        return oidc_failed(
+            &state,
+            ServiceError::Invalid(format!(
+                "Signing in with {} was cancelled.",
+                oidc_service.provider_name()
+            )),
+        );
+    }
+    let (Some(flow), Some(state_param), Some(code)) =
+        (jar.get("oidc_flow"), params.state, params.code)
+    else {
+        return oidc_failed(&state, oidc::expired());
+    };
+
+    let result = oidc_service
+        .complete(flow.value(), &state_param, &code)
+        .await;
+    let (user, return_to) = match result {
+        Ok(signed_in) => signed_in,
+        Err(err) => return oidc_failed(&state, err),
+    };
+
+    let auth_service = AuthService::new(state.db.clone());
+    match auth_service.login_with_identity(&user).await {
+        Ok(IdentityLogin::Session(token)) => {
+            // people whose role needs two-factor authentication can't use
+            // the admin pages until they've set it up, so that comes first
+            let two_factor_service = TwoFactorService::new(&state.db);
+            let return_to = match two_factor_service.must_enrol(&user).await {
+                Ok(true) => "/me/two-factor",
+                Ok(false) => safe_return_to(Some(&return_to)),
+                Err(err) => return oidc_failed(&state, err.into()),
+            };
+            let cookie = auth_cookie(token, SESSION_LIFETIME.num_seconds());
+            let flow_cookie = oidc_flow_cookie(String::new(), 0);
+            (
+                AppendHeaders([
+                    (
+                        SET_COOKIE,
+                        HeaderValue::from_str(&cookie.to_string())
+                            .expect("failed to convert cookie to string"),
+                    ),
+                    (
+                        SET_COOKIE,
+                        HeaderValue::from_str(&flow_cookie.to_string())
+                            .expect("failed to convert cookie to string"),
+                    ),
+                ]),
+                Redirect::to(return_to),
+            )
+                .into_response()
+        }
+        Ok(IdentityLogin::TwoFactor(challenge)) => {
+            let mut context = tera::Context::new();
+            context.insert("challenge", &challenge);
+            context.insert("return_to", &return_to);
+            let flow_cookie = oidc_flow_cookie(String::new(), 0);
+            (
+                [(
+                    SET_COOKIE,
+                    HeaderValue::from_str(&flow_cookie.to_string())
+                        .expect("failed to convert cookie to string"),
+                )],
+                Html(state.tera.render("auth/signin.html", &context).unwrap()),
+            )
+                .into_response()
+        }
+        Err(err) => oidc_failed(&state, err.into()),
+    }
+}
+
+fn oidc_failed(state: &AppState, err: ServiceError) -> Response {
+    let mut context = tera::Context::new();
+    match err {
+        ServiceError::Invalid(message) => context.insert("error", &message),
+        _ => context.insert("error", "An unexpected error occurred. Please try again."),
+    }
+    insert_oidc_provider(&mut context);
+
+    let cookie = oidc_flow_cookie(String::new(), 0);
+    (
+        StatusCode::BAD_REQUEST,
+        [(
+            SET_COOKIE,
+            HeaderValue::from_str(&cookie.to_string()).expect("failed to convert cookie to string"),
+        )],
+        Html(state.tera.render("auth/signin.html", &context).unwrap()),
+    )
+        .into_response()
+}
+
+// Lax so that it comes back with the provider's redirect to the callback
+fn oidc_flow_cookie(flow: String, max_age_secs: i64) -> Cookie<'static> {
+    Cookie::build(("oidc_flow", flow))
+        .path("/signin/oidc")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(true)
+        .max_age(Duration::seconds(max_age_secs))
+        .build()
+}
@@ -0,0 +1,154 @@
+use crate::{
+    AppState,
+    extractors::current_user::CurrentUser,
+    middleware::auth::safe_return_to,
+    routes::insert_user,
+    services::error::ServiceError,
+    user::{
+        NewPasskeyRequest, PasskeySignInRequest, User,
+        auth::{AuthService, SESSION_LIFETIME},
+        passkey::PasskeyService,
+        routes::auth_cookie,
+    },
+};
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderValue, StatusCode, header::SET_COOKIE},
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, post, put},
+};
+use std::sync::Arc;
+
+pub fn public_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/signin/passkey", put(signin_with_passkey))
+        .route("/signin/passkey/options", post(passkey_signin_options))
+}
+
+/// Where users manage their passkeys. Like two-factor, these only need a
+/// session.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().nest(
+        "/me/passkeys",
+        Router::new()
+            .route("/", get(passkeys_page).put(register_passkey))
+            .route("/options", post(passkey_registration_options))
+            .route("/{id}", delete(delete_passkey)),
+    )
+}
+
+pub async fn passkey_signin_options(State(state): State<Arc<AppState>>) -> Response {
+    let passkey_service = PasskeyService::new(&state.db);
+
+    match passkey_service.authentication_options().await {
+        Ok(options) => Json(options).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Called from script rather than htmx, so it answers in JSON: where to go
+/// next, or what went wrong.
+pub async fn signin_with_passkey(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<PasskeySignInRequest>,
+) -> Response {
+    let auth_service = AuthService::new(state.db.clone());
+
+    match auth_service.login_with_passkey(&request).await {
+        Ok(token) => {
+            let cookie = auth_cookie(token, SESSION_LIFETIME.num_seconds());
+            (
+                [(
+                    SET_COOKIE,
+                    HeaderValue::from_str(&cookie.to_string())
+                        .expect("failed to convert cookie to string"),
+                )],
+                Json(serde_json::json!({
+                    "redirect": safe_return_to(request.return_to.as_deref()),
+                })),
+            )
+                .into_response()
+        }
+        Err(ServiceError::Invalid(message)) => (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": { "code": "passkey_rejected", "message": message }
+            })),
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn passkeys_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+    render_passkeys(&state, &user, context, "passkeys/index.html").await
+}
+
+async fn render_passkeys(
+    state: &AppState,
+    user: &User,
+    mut context: tera::Context,
+    template: &str,
+) -> Response {
+    let passkey_service = PasskeyService::new(&state.db);
+
+    match passkey_service.passkeys(user).await {
+        Ok(passkeys) => {
+            context.insert("passkeys", &passkeys);
+            Html(state.tera.render(template, &context).unwrap()).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn passkey_registration_options(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let passkey_service = PasskeyService::new(&state.db);
+
+    match passkey_service.registration_options(&user).await {
+        Ok(options) => Json(options).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn register_passkey(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<NewPasskeyRequest>,
+) -> Response {
+    let passkey_service = PasskeyService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match passkey_service.register(&user, &request).await {
+        Ok(_) => context.insert(
+            "success",
+            "Passkey added. You can use it next time you sign in.",
+        ),
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(_) => context.insert("error", "Something went wrong adding the passkey."),
+    }
+
+    render_passkeys(&state, &user, context, "passkeys/list.html").await
+}
+
+pub async fn delete_passkey(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let passkey_service = PasskeyService::new(&state.db);
+
+    match passkey_service.delete(&user, id).await {
+        Ok(_) => render_passkeys(&state, &user, tera::Context::new(), "passkeys/list.html").await,
+        Err(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    AppState,
+    mailer::{Email, absolute_url},
+    services::error::ServiceError,
+    user::{
+        ForgotPasswordRequest, ResetPasswordParams, ResetPasswordRequest,
+        password_reset::PasswordResetService,
+    },
+};
+use axum::{
+    Form, Router,
+    extract::{Query, State},
+    response::{Html, IntoResponse},
+    routing::get,
+};
+use std::sync::Arc;
+
+pub fn public_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/password/forgot",
+            get(forgot_password_html).put(forgot_password),
+        )
+        .route(
+            "/password/reset",
+            get(reset_password_html).put(reset_password),
+        )
+}
+
+pub async fn forgot_password_html(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Html(
+        state
+            .tera
+            .render("auth/forgot_password.html", &tera::Context::new())
+            .unwrap(),
+    )
+}
+
+/// Always answers the same way, whether or not the email belongs to an
+/// account, so the form can't be used to find out who has signed up.
+pub async fn forgot_password(
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ForgotPasswordRequest>,
+) -> impl IntoResponse {
+    let password_reset_service = PasswordResetService::new(&state.db);
+    let mut context = tera::Context::new();
+    let template = "auth/forgot_password_form.html";
+
+    match password_reset_service.issue_token(&request.email).await {
+        Ok(Some((user, token))) => {
+            let mut email_context = tera::Context::new();
+            email_context.insert("user", &user);
+            email_context.insert(
+                "reset_url",
+                &absolute_url(&format!("/password/reset?token={}", token)),
+            );
+            let email = Email {
+                to: user.email.clone(),
+                subject: "Reset your Wordford password".to_string(),
+                body: state
+                    .tera
+                    .render("emails/password_reset.txt", &email_context)
+                    .unwrap(),
+            };
+
+            if let Err(err) = state.mailer.send(email).await {
+                tracing::error!("failed to send email: {}", err);
+                context.insert(
+                    "error",
+                    "We couldn't send the email. Please try again later.",
+                );
+                return Html(state.tera.render(template, &context).unwrap());
+            }
+        }
+        Ok(None) => {}
+        Err(_) => {
+            context.insert("error", "An unexpected error occurred. Please try again.");
+            return Html(state.tera.render(template, &context).unwrap());
+        }
+    }
+
+    context.insert(
+        "success",
+        "If that email belongs to an account, a link to reset its password is on its way.",
+    );
+    Html(state.tera.render(template, &context).unwrap())
+}
+
+pub async fn reset_password_html(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ResetPasswordParams>,
+) -> impl IntoResponse {
+    let password_reset_service = PasswordResetService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match password_reset_service.is_valid_token(&params.token).await {
+        Ok(true) => context.insert("token", &params.token),
+        Ok(false) => context.insert(
+            "error",
+            "This reset link has expired or already been used. You can ask for a new one.",
+        ),
+        Err(_) => context.insert("error", "An unexpected error occurred. Please try again."),
+    }
+
+    Html(
+        state
+            .tera
+            .render("auth/reset_password.html", &context)
+            .unwrap(),
+    )
+}
+
+pub async fn reset_password(
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ResetPasswordRequest>,
+) -> impl IntoResponse {
+    let password_reset_service = PasswordResetService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match password_reset_service
+        .reset_password(
+            &request.token,
+            &request.password,
+            &request.password_confirmation,
+        )
+        .await
+    {
+        Ok(_) => context.insert("success", "Your password has been changed."),
+        Err(ServiceError::Invalid(message)) => {
+            context.insert("token", &request.token);
+            context.insert("error", &message);
+        }
+        Err(_) => {
+            context.insert("token", &request.token);
+            context.insert("error", "An unexpected error occurred. Please try again.");
+        }
+    }
+
+    Html(
+        state
+            .tera
+            .render("auth/reset_password_form.html", &context)
+            .unwrap(),
+    )
+}
@@ -0,0 +1,350 @@
+use crate::{
+    AppState,
+    extractors::current_user::{CurrentSession, CurrentUser},
+    mailer::{Email, absolute_url},
+    routes::insert_user,
+    services::error::ServiceError,
+    user::{
+        ChangeEmailRequest, ChangePasswordRequest, ChooseAvatarRequest, ConfirmEmailParams,
+        DeleteAccountRequest, UpdateProfileRequest, User,
+        profile::{CRITTER_COUNT, ProfileService},
+        routes::{auth_cookie, insert_error, insert_oidc_provider},
+    },
+};
+use axum::{
+    Form, Router,
+    extract::{Multipart, Path, Query, State},
+    http::{
+        HeaderValue, StatusCode,
+        header::{CACHE_CONTROL, CONTENT_TYPE, SET_COOKIE, X_CONTENT_TYPE_OPTIONS},
+    },
+    response::{Html, IntoResponse, Response},
+    routing::{get, post, put},
+};
+use std::sync::Arc;
+
+/// Links from emails and pages anyone may see.
+pub fn public_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/users/email/confirm", get(confirm_email_change))
+        .route("/users/{id}/avatar", get(avatar))
+}
+
+/// Account settings, reachable with just a session like two-factor.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().nest(
+        "/me",
+        Router::new()
+            .route("/", get(profile_page))
+            .route("/profile", put(update_profile))
+            .route("/password", put(change_password))
+            .route("/email", put(change_email))
+            .route("/avatar", put(choose_avatar).post(upload_avatar))
+            .route("/delete", post(delete_account)),
+    )
+}
+
+pub async fn profile_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+    context.insert("critters", &(1..=CRITTER_COUNT).collect::<Vec<_>>());
+
+    Html(state.tera.render("profile/index.html", &context).unwrap()).into_response()
+}
+
+pub async fn update_profile(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<UpdateProfileRequest>,
+) -> Response {
+    let profile_service = ProfileService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match profile_service
+        .update_name(&user, &request.given_name, &request.family_name)
+        .await
+    {
+        Ok(user) => {
+            insert_user(&mut context, &user);
+            context.insert("success", "Saved.");
+        }
+        Err(err) => {
+            insert_user(&mut context, &user);
+            insert_error(&mut context, err);
+        }
+    }
+
+    Html(
+        state
+            .tera
+            .render("profile/profile_form.html", &context)
+            .unwrap(),
+    )
+    .into_response()
+}
+
+pub async fn change_password(
+    CurrentUser(user): CurrentUser,
+    CurrentSession(session): CurrentSession,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ChangePasswordRequest>,
+) -> Response {
+    let profile_service = ProfileService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match profile_service
+        .change_password(
+            &user,
+            session.as_deref().unwrap_or_default(),
+            &request.current_password,
+            &request.password,
+            &request.password_confirmation,
+        )
+        .await
+    {
+        Ok(_) => context.insert(
+            "success",
+            "Your password has been changed. You've been signed out everywhere else, and your access tokens have been revoked.",
+        ),
+        Err(err) => insert_error(&mut context, err),
+    }
+
+    Html(
+        state
+            .tera
+            .render("profile/password_form.html", &context)
+            .unwrap(),
+    )
+    .into_response()
+}
+
+/// Sends a link to the new address. The email only changes once it's
+/// followed, see `confirm_email_change`.
+pub async fn change_email(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ChangeEmailRequest>,
+) -> Response {
+    let profile_service = ProfileService::new(&state.db);
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+
+    match profile_service
+        .request_email_change(&user, &request.email, &request.current_password)
+        .await
+    {
+        Ok(token) => {
+            let new_email = request.email.trim();
+            let mut email_context = tera::Context::new();
+            email_context.insert("user", &user);
+            email_context.insert(
+                "confirm_url",
+                &absolute_url(&format!("/users/email/confirm?token={}", token)),
+            );
+            let sent = state
+                .mailer
+                .send(Email {
+                    to: new_email.to_string(),
+                    subject: "Confirm your new Wordford email address".to_string(),
+                    body: state
+                        .tera
+                        .render("emails/confirm_email_change.txt", &email_context)
+                        .unwrap(),
+                })
+                .await;
+
+            match sent {
+                Ok(_) => context.insert(
+                    "success",
+                    &format!(
+                        "We sent a link to {}. Your email will change once you follow it.",
+                        new_email
+                    ),
+                ),
+                Err(err) => {
+                    tracing::error!("failed to send email: {}", err);
+                    context.insert("error", "We couldn't send the confirmation email.");
+                }
+            }
+        }
+        Err(err) => {
+            context.insert("email", &request.email);
+            insert_error(&mut context, err);
+        }
+    }
+
+    Html(
+        state
+            .tera
+            .render("profile/email_form.html", &context)
+            .unwrap(),
+    )
+    .into_response()
+}
+
+/// Where the link sent by `change_email` leads. The old address is told
+/// about the change, in case it wasn't its owner who made it.
+pub async fn confirm_email_change(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ConfirmEmailParams>,
+) -> impl IntoResponse {
+    let profile_service = ProfileService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match profile_service.confirm_email_change(&params.token).await {
+        Ok((user, new_email)) => {
+            let mut email_context = tera::Context::new();
+            email_context.insert("user", &user);
+            email_context.insert("new_email", &new_email);
+            let notice = Email {
+                to: user.email.clone(),
+                subject: "Your Wordford email address was changed".to_string(),
+                body: state
+                    .tera
+                    .render("emails/email_changed.txt", &email_context)
+                    .unwrap(),
+            };
+            if let Err(err) = state.mailer.send(notice).await {
+                tracing::error!("failed to send email: {}", err);
+            }
+
+            context.insert("email", &new_email);
+            context.insert(
+                "success",
+                "Your email address has been changed. Use the new one to sign in from now on.",
+            );
+        }
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(_) => context.insert("error", "An unexpected error occurred. Please try again."),
+    }
+
+    insert_oidc_provider(&mut context);
+
+    Html(state.tera.render("auth/signin.html", &context).unwrap())
+}
+
+pub async fn choose_avatar(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ChooseAvatarRequest>,
+) -> Response {
+    let profile_service = ProfileService::new(&state.db);
+    let result = profile_service.choose_critter(&user, request.critter).await;
+
+    render_avatar_form(&state, user, result)
+}
+
+/// Takes the image from the `avatar` field of a multipart form.
+pub async fn upload_avatar(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Response {
+    let profile_service = ProfileService::new(&state.db);
+
+    let mut data = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("avatar") {
+            data = field.bytes().await.ok();
+            break;
+        }
+    }
+    let result = match data {
+        Some(data) if !data.is_empty() => profile_service.upload_avatar(&user, &data).await,
+        Some(_) | None => Err(ServiceError::Invalid(
+            "Choose an image to upload.".to_string(),
+        )),
+    };
+
+    render_avatar_form(&state, user, result)
+}
+
+fn render_avatar_form(
+    state: &AppState,
+    mut user: User,
+    result: Result<String, ServiceError>,
+) -> Response {
+    let mut context = tera::Context::new();
+    match result {
+        Ok(avatar_url) => {
+            user.avatar_url = avatar_url;
+            context.insert("success", "Your avatar has been updated.");
+        }
+        Err(err) => insert_error(&mut context, err),
+    }
+    insert_user(&mut context, &user);
+    context.insert("critters", &(1..=CRITTER_COUNT).collect::<Vec<_>>());
+
+    Html(
+        state
+            .tera
+            .render("profile/avatar_form.html", &context)
+            .unwrap(),
+    )
+    .into_response()
+}
+
+/// Serves an uploaded avatar.
+pub async fn avatar(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let profile_service = ProfileService::new(&state.db);
+
+    match profile_service.avatar(id).await {
+        Ok((content_type, data)) => (
+            [
+                (CONTENT_TYPE, content_type),
+                (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+                (CACHE_CONTROL, "public, max-age=31536000".to_string()),
+            ],
+            data,
+        )
+            .into_response(),
+        Err(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn delete_account(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<DeleteAccountRequest>,
+) -> Response {
+    let profile_service = ProfileService::new(&state.db);
+
+    match profile_service
+        .delete_account(&user, &request.current_password)
+        .await
+    {
+        Ok(_) => {
+            let cookie = auth_cookie(String::new(), 0);
+            (
+                [
+                    (
+                        SET_COOKIE,
+                        HeaderValue::from_str(&cookie.to_string())
+                            .expect("failed to convert cookie to string"),
+                    ),
+                    (
+                        "HX-Redirect".parse().unwrap(),
+                        HeaderValue::from_static("/"),
+                    ),
+                ],
+                (),
+            )
+                .into_response()
+        }
+        Err(err) => {
+            let mut context = tera::Context::new();
+            insert_error(&mut context, err);
+            Html(
+                state
+                    .tera
+                    .render("profile/delete_form.html", &context)
+                    .unwrap(),
+            )
+            .into_response()
+        }
+    }
+}
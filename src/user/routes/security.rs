@@ -0,0 +1,129 @@
+use crate::{
+    AppState,
+    extractors::{client_ip::ClientIp, current_user::CurrentUser},
+    routes::{forbidden, insert_user},
+    services::error::ServiceError,
+    user::{
+        UnlockAccountRequest, User,
+        lockout::LockoutService,
+        role::{Permission, Role},
+        two_factor::TwoFactorService,
+    },
+};
+use axum::{
+    Form, Router,
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+};
+use std::{collections::HashMap, sync::Arc};
+
+/// Admin settings for how people sign in. These sit behind the auth middleware.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/security", get(security_page).put(update_security))
+        .route("/admin/sign-ins", get(sign_ins_page))
+        .route("/admin/sign-ins/unlock", post(unlock_account))
+}
+
+pub async fn security_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if !user.can(Permission::ManageSecurity) {
+        return forbidden();
+    }
+
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+    render_security(&state, context, "admin/security.html").await
+}
+
+async fn render_security(state: &AppState, mut context: tera::Context, template: &str) -> Response {
+    let two_factor_service = TwoFactorService::new(&state.db);
+
+    match two_factor_service.required_roles().await {
+        Ok(required_roles) => {
+            context.insert("roles", &Role::ALL);
+            context.insert("required_roles", &required_roles);
+            Html(state.tera.render(template, &context).unwrap()).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// The form has a checkbox named after each role that should have to use
+/// two-factor authentication.
+pub async fn update_security(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let two_factor_service = TwoFactorService::new(&state.db);
+    let roles: Vec<Role> = Role::ALL
+        .into_iter()
+        .filter(|role| form.contains_key(role.as_str()))
+        .collect();
+    let mut context = tera::Context::new();
+
+    match two_factor_service.set_required_roles(&user, &roles).await {
+        Ok(_) => context.insert("success", "Saved."),
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(_) => context.insert("error", "Something went wrong saving the settings."),
+    }
+
+    render_security(&state, context, "admin/security_form.html").await
+}
+
+/// Recent sign in attempts and the accounts locked by them.
+pub async fn sign_ins_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+    render_sign_ins(&state, &user, context, "admin/sign_ins.html").await
+}
+
+async fn render_sign_ins(
+    state: &AppState,
+    user: &User,
+    mut context: tera::Context,
+    template: &str,
+) -> Response {
+    let lockout_service = LockoutService::new(&state.db);
+
+    let (attempts, locked) = match (
+        lockout_service.recent_attempts(user).await,
+        lockout_service.locked_accounts(user).await,
+    ) {
+        (Ok(attempts), Ok(locked)) => (attempts, locked),
+        (Err(ServiceError::Forbidden(_)), _) | (_, Err(ServiceError::Forbidden(_))) => {
+            return forbidden();
+        }
+        _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    context.insert("attempts", &attempts);
+    context.insert("locked_accounts", &locked);
+    Html(state.tera.render(template, &context).unwrap()).into_response()
+}
+
+pub async fn unlock_account(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Form(request): Form<UnlockAccountRequest>,
+) -> Response {
+    let lockout_service = LockoutService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match lockout_service.unlock(&user, &request.email, &ip).await {
+        Ok(_) => context.insert("success", &format!("Unlocked {}.", request.email)),
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(_) => context.insert("error", "Something went wrong unlocking the account."),
+    }
+
+    render_sign_ins(&state, &user, context, "admin/sign_ins_list.html").await
+}
@@ -0,0 +1,190 @@
+use crate::{
+    AppState,
+    extractors::{
+        client_ip::ClientIp,
+        current_user::{CurrentSession, CurrentUser},
+    },
+    middleware::auth::safe_return_to,
+    services::error::ServiceError,
+    user::{
+        SignInParams, SignInRequest, TwoFactorSignInRequest,
+        auth::{self, AuthService, LoginOutcome},
+        routes::{auth_cookie, insert_oidc_provider, signed_in},
+    },
+};
+use axum::{
+    Form, Router,
+    extract::{Query, State},
+    http::{HeaderValue, StatusCode, header::SET_COOKIE},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post, put},
+};
+use std::sync::Arc;
+
+pub fn public_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/signin", get(signin_html).put(signin))
+        .route("/signin/two-factor", put(signin_two_factor))
+        .route("/signout", post(signout))
+        .route("/signout/everywhere", post(signout_everywhere))
+}
+
+pub async fn signin_html(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SignInParams>,
+) -> impl IntoResponse {
+    let mut context = tera::Context::new();
+    context.insert("return_to", safe_return_to(params.return_to.as_deref()));
+    insert_oidc_provider(&mut context);
+
+    Html(state.tera.render("auth/signin.html", &context).unwrap())
+}
+
+pub async fn signin(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Form(request): Form<SignInRequest>,
+) -> impl IntoResponse {
+    let auth_service = AuthService::new(state.db.clone());
+    let mut context = tera::Context::from_serialize(&request).unwrap();
+
+    let template = "auth/signin_form.html";
+    match auth_service
+        .login(&request.email, &request.password, &ip)
+        .await
+    {
+        Ok(LoginOutcome::Success(token)) => signed_in(token, request.return_to.as_deref()),
+        Ok(LoginOutcome::TwoFactorRequired(challenge)) => {
+            let mut context = tera::Context::new();
+            context.insert("challenge", &challenge);
+            context.insert("return_to", &request.return_to);
+            state
+                .tera
+                .render("auth/two_factor_form.html", &context)
+                .unwrap()
+                .into_response()
+        }
+        Ok(LoginOutcome::InvalidCredentials) => {
+            context.insert("error", "Invalid email or password. Please try again.");
+            state
+                .tera
+                .render(template, &context)
+                .unwrap()
+                .into_response()
+        }
+        Ok(LoginOutcome::Throttled(throttle)) => {
+            context.insert("error", &throttle.message());
+            state
+                .tera
+                .render(template, &context)
+                .unwrap()
+                .into_response()
+        }
+        Ok(LoginOutcome::Unverified) => {
+            context.insert(
+                "error",
+                "Please verify your email address before signing in. Check your inbox for the link we sent you.",
+            );
+            context.insert("unverified", &true);
+            state
+                .tera
+                .render(template, &context)
+                .unwrap()
+                .into_response()
+        }
+        Ok(LoginOutcome::Disabled) => {
+            context.insert("error", &auth::disabled().to_string());
+            state
+                .tera
+                .render(template, &context)
+                .unwrap()
+                .into_response()
+        }
+        Err(_) => {
+            context.insert("error", "An unexpected error occurred. Please try again.");
+            state
+                .tera
+                .render(template, &context)
+                .unwrap()
+                .into_response()
+        }
+    }
+}
+
+/// The second step of signing in for users with two-factor authentication.
+/// The `auth_token` cookie is only set once the code checks out.
+pub async fn signin_two_factor(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Form(request): Form<TwoFactorSignInRequest>,
+) -> Response {
+    let auth_service = AuthService::new(state.db.clone());
+
+    match auth_service
+        .complete_two_factor(&request.challenge, &request.code, &ip)
+        .await
+    {
+        Ok(token) => signed_in(token, request.return_to.as_deref()),
+        Err(err) => {
+            let mut context = tera::Context::new();
+            context.insert("challenge", &request.challenge);
+            context.insert("return_to", &request.return_to);
+            match err {
+                ServiceError::Invalid(message) => context.insert("error", &message),
+                _ => context.insert("error", "An unexpected error occurred. Please try again."),
+            }
+            Html(
+                state
+                    .tera
+                    .render("auth/two_factor_form.html", &context)
+                    .unwrap(),
+            )
+            .into_response()
+        }
+    }
+}
+
+pub async fn signout(
+    CurrentSession(session): CurrentSession,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if let Some(session) = session {
+        let auth_service = AuthService::new(state.db.clone());
+        if auth_service.logout(&session).await.is_err() {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    signed_out()
+}
+
+/// Ends every session the user has, including the one making the request.
+/// Only a session can do this, not a personal access token.
+pub async fn signout_everywhere(
+    CurrentUser(user): CurrentUser,
+    CurrentSession(session): CurrentSession,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if session.is_none() {
+        return (StatusCode::UNAUTHORIZED, "Sign in to sign out everywhere.").into_response();
+    }
+
+    let auth_service = AuthService::new(state.db.clone());
+
+    match auth_service.logout_everywhere(user.id).await {
+        Ok(_) => signed_out(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+fn signed_out() -> Response {
+    let cookie = auth_cookie(String::new(), 0);
+    (
+        [(
+            SET_COOKIE,
+            HeaderValue::from_str(&cookie.to_string()).expect("failed to convert cookie to string"),
+        )],
+        Redirect::to("/signin"),
+    )
+        .into_response()
+}
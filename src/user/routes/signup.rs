@@ -0,0 +1,323 @@
+use crate::{
+    AppState,
+    extractors::current_user::CurrentUser,
+    mailer::{Email, MailError, absolute_url},
+    routes::{forbidden, insert_user},
+    services::error::ServiceError,
+    user::{
+        CreateUserRequest, NewInviteRequest, ResendVerificationRequest, SignUpParams, User,
+        VerifyEmailParams,
+        repository::UserRepository,
+        role::{Permission, Role},
+        routes::insert_oidc_provider,
+        service::UserService,
+        signup::{SignupMode, SignupService},
+    },
+};
+use axum::{
+    Form, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, put},
+};
+use std::sync::Arc;
+
+pub fn public_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/signup", get(signup_html))
+        .route("/users", put(create_user))
+        .route("/users/verify", get(verify_email))
+        .route("/users/verify/resend", put(resend_verification))
+}
+
+/// Screens for managing who can sign up. These sit behind the auth middleware.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().nest(
+        "/invites",
+        Router::new()
+            .route("/", get(invites_page).put(create_invite))
+            .route("/{id}", delete(revoke_invite)),
+    )
+}
+
+pub async fn signup_html(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SignUpParams>,
+) -> impl IntoResponse {
+    let signup_service = SignupService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match params.invite.filter(|token| !token.is_empty()) {
+        Some(token) => match signup_service.find_invite(&token).await {
+            Ok(invite) => {
+                context.insert("invite", &token);
+                context.insert("email", &invite.email);
+            }
+            Err(_) => {
+                context.insert("signup_closed", &true);
+                context.insert(
+                    "error",
+                    "This invite has expired or has already been used. Ask whoever invited you for a new one.",
+                );
+            }
+        },
+        None => {
+            if !signup_service.is_open().await.unwrap_or(false) {
+                context.insert("signup_closed", &true);
+                context.insert(
+                    "error",
+                    match signup_service.mode() {
+                        SignupMode::Closed => "Signing up is closed.",
+                        _ => "Wordford is invite only. Ask an admin to invite you.",
+                    },
+                );
+            }
+        }
+    }
+
+    Html(state.tera.render("user/signup.html", &context).unwrap())
+}
+
+pub async fn create_user(
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<CreateUserRequest>,
+) -> impl IntoResponse {
+    let signup_service = SignupService::new(&state.db);
+    let user_service = UserService::new(UserRepository::new(&state.db));
+    let mut context = tera::Context::new();
+
+    let template = "user/create_user_form.html";
+    let mut request = request.clone();
+    let invite = request.invite.clone();
+    let error_context = |message: &str, request: &CreateUserRequest| {
+        let mut context = tera::Context::from_serialize(request).unwrap();
+        context.insert("invite", &invite);
+        context.insert("error", message);
+        context
+    };
+    let html = match signup_service.register(&mut request).await {
+        Ok(user) if user.is_verified() => {
+            context.insert("success", "Welcome aboard! You may now sign in.");
+            state.tera.render(template, &context)
+        }
+        Ok(user) => {
+            if let Err(err) = send_verification_email(&state, &user_service, &user).await {
+                tracing::error!("failed to send email: {}", err);
+            }
+            context.insert(
+                "success",
+                "Congratulations! Check your email for a link to verify your address, then you can sign in.",
+            );
+            state.tera.render(template, &context)
+        }
+        Err(ServiceError::Invalid(message)) => {
+            let context = error_context(&message, &request);
+            state.tera.render(template, &context)
+        }
+        Err(ServiceError::Database(sqlx::Error::Database(err))) if err.is_unique_violation() => {
+            let context = error_context("Email already exists. Please try again.", &request);
+            state.tera.render(template, &context)
+        }
+        Err(_) => {
+            let context = error_context(
+                "An unexpected error occurred. Please try again.",
+                &request,
+            );
+            state.tera.render(template, &context)
+        }
+    }
+    .unwrap();
+
+    Html(html)
+}
+
+async fn send_verification_email(
+    state: &AppState,
+    user_service: &UserService,
+    user: &User,
+) -> Result<(), MailError> {
+    let token = user_service.create_verification_token(user);
+    let mut context = tera::Context::new();
+    context.insert("user", user);
+    context.insert(
+        "verify_url",
+        &absolute_url(&format!("/users/verify?token={}", token)),
+    );
+
+    state
+        .mailer
+        .send(Email {
+            to: user.email.clone(),
+            subject: "Verify your Wordford email address".to_string(),
+            body: state
+                .tera
+                .render("emails/verify_email.txt", &context)
+                .unwrap(),
+        })
+        .await
+}
+
+pub async fn verify_email(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<VerifyEmailParams>,
+) -> impl IntoResponse {
+    let user_service = UserService::new(UserRepository::new(&state.db));
+    let mut context = tera::Context::new();
+
+    match user_service.verify_email(&params.token).await {
+        Ok(user) => {
+            context.insert("email", &user.email);
+            context.insert(
+                "success",
+                "Thanks, your email address is verified. You can sign in now.",
+            );
+        }
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(_) => context.insert("error", "An unexpected error occurred. Please try again."),
+    }
+
+    insert_oidc_provider(&mut context);
+
+    Html(state.tera.render("auth/signin.html", &context).unwrap())
+}
+
+/// Like the forgot password form, this answers the same way whether or not
+/// the email belongs to an account.
+pub async fn resend_verification(
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ResendVerificationRequest>,
+) -> impl IntoResponse {
+    let user_service = UserService::new(UserRepository::new(&state.db));
+    let mut context = tera::Context::new();
+    context.insert("email", &request.email);
+
+    match user_service.find_user_by_email(request.email.trim()).await {
+        Ok(user) if !user.is_verified() => {
+            if let Err(err) = send_verification_email(&state, &user_service, &user).await {
+                tracing::error!("failed to send email: {}", err);
+                context.insert(
+                    "error",
+                    "We couldn't send the email. Please try again later.",
+                );
+                return Html(
+                    state
+                        .tera
+                        .render("auth/signin_form.html", &context)
+                        .unwrap(),
+                );
+            }
+        }
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {}
+        Err(_) => {
+            context.insert("error", "An unexpected error occurred. Please try again.");
+            return Html(
+                state
+                    .tera
+                    .render("auth/signin_form.html", &context)
+                    .unwrap(),
+            );
+        }
+    }
+
+    context.insert(
+        "success",
+        "If that account still needs verifying, a new link is on its way.",
+    );
+    Html(
+        state
+            .tera
+            .render("auth/signin_form.html", &context)
+            .unwrap(),
+    )
+}
+
+pub async fn invites_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if !user.can(Permission::InviteUsers) {
+        return forbidden();
+    }
+
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+    render_invites(&state, context, "invites/index.html").await
+}
+
+async fn render_invites(state: &AppState, mut context: tera::Context, template: &str) -> Response {
+    let signup_service = SignupService::new(&state.db);
+
+    match signup_service.pending_invites().await {
+        Ok(invites) => {
+            context.insert("invites", &invites);
+            context.insert("roles", &Role::ALL);
+            context.insert(
+                "signup_closed",
+                &(signup_service.mode() == SignupMode::Closed),
+            );
+            Html(state.tera.render(template, &context).unwrap()).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn create_invite(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<NewInviteRequest>,
+) -> Response {
+    let signup_service = SignupService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match signup_service.create_invite(&user, request).await {
+        Ok((invite, token)) => {
+            let url = absolute_url(&format!("/signup?invite={}", token));
+            let mut email_context = tera::Context::new();
+            email_context.insert("inviter", &user);
+            email_context.insert("invite_url", &url);
+            let email = Email {
+                to: invite.email.clone(),
+                subject: "You've been invited to Wordford".to_string(),
+                body: state
+                    .tera
+                    .render("emails/invite.txt", &email_context)
+                    .unwrap(),
+            };
+
+            if let Err(err) = state.mailer.send(email).await {
+                tracing::error!("failed to send email: {}", err);
+                context.insert(
+                    "error",
+                    "We couldn't email the invite, send them the link below instead.",
+                );
+            }
+            context.insert(
+                "created",
+                &serde_json::json!({ "invite": invite, "url": url }),
+            );
+        }
+        Err(ServiceError::Forbidden(_)) => return forbidden(),
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(_) => context.insert("error", "Something went wrong creating the invite."),
+    }
+
+    render_invites(&state, context, "invites/list.html").await
+}
+
+pub async fn revoke_invite(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let signup_service = SignupService::new(&state.db);
+
+    match signup_service.revoke_invite(&user, id).await {
+        Ok(_) => render_invites(&state, tera::Context::new(), "invites/list.html").await,
+        Err(ServiceError::Forbidden(_)) => forbidden(),
+        Err(ServiceError::Database(sqlx::Error::RowNotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
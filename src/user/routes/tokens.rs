@@ -0,0 +1,87 @@
+use crate::{
+    AppState,
+    extractors::current_user::CurrentUser,
+    routes::insert_user,
+    user::{
+        NewTokenRequest, User,
+        routes::insert_error,
+        tokens::{EXPIRY_CHOICES, TokenScope, TokenService},
+    },
+};
+use axum::{
+    Form, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get},
+};
+use std::sync::Arc;
+
+/// Where users mint personal access tokens for their scripts.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().nest(
+        "/me/tokens",
+        Router::new()
+            .route("/", get(tokens_page).put(create_token))
+            .route("/{id}", delete(revoke_token)),
+    )
+}
+
+pub async fn tokens_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+    render_tokens(&state, &user, context, "tokens/index.html").await
+}
+
+async fn render_tokens(
+    state: &AppState,
+    user: &User,
+    mut context: tera::Context,
+    template: &str,
+) -> Response {
+    let token_service = TokenService::new(&state.db);
+
+    match token_service.tokens(user).await {
+        Ok(tokens) => {
+            context.insert("tokens", &tokens);
+            context.insert("scopes", &TokenScope::ALL);
+            context.insert("expiry_choices", &EXPIRY_CHOICES);
+            Html(state.tera.render(template, &context).unwrap()).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// The form ticks one box per scope, so it's read as a list of fields.
+pub async fn create_token(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Response {
+    let token_service = TokenService::new(&state.db);
+    let request = NewTokenRequest::from_pairs(fields);
+    let mut context = tera::Context::new();
+
+    match token_service.create_token(&user, &request).await {
+        Ok(created) => context.insert("created", &created),
+        Err(err) => insert_error(&mut context, err),
+    }
+
+    render_tokens(&state, &user, context, "tokens/list.html").await
+}
+
+pub async fn revoke_token(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let token_service = TokenService::new(&state.db);
+
+    match token_service.revoke(&user, id).await {
+        Ok(_) => render_tokens(&state, &user, tera::Context::new(), "tokens/list.html").await,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{
+    AppState,
+    extractors::current_user::CurrentUser,
+    routes::insert_user,
+    services::error::ServiceError,
+    user::{TwoFactorCodeRequest, User, two_factor::TwoFactorService},
+};
+use axum::{
+    Form, Router,
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+};
+use std::sync::Arc;
+
+/// Where users set up two-factor authentication. These only need a session,
+/// so that people whose role requires it can still get here to set it up.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().nest(
+        "/me/two-factor",
+        Router::new()
+            .route("/", get(two_factor_page).put(confirm_two_factor))
+            .route("/setup", post(begin_two_factor))
+            .route("/recovery-codes", post(regenerate_recovery_codes))
+            .route("/disable", post(disable_two_factor)),
+    )
+}
+
+pub async fn two_factor_page(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let mut context = tera::Context::new();
+    insert_user(&mut context, &user);
+    render_two_factor(&state, &user, context, "two_factor/index.html").await
+}
+
+async fn render_two_factor(
+    state: &AppState,
+    user: &User,
+    mut context: tera::Context,
+    template: &str,
+) -> Response {
+    let two_factor_service = TwoFactorService::new(&state.db);
+
+    match two_factor_service.status(user).await {
+        Ok(status) => {
+            context.insert("two_factor", &status);
+            Html(state.tera.render(template, &context).unwrap()).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn begin_two_factor(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let two_factor_service = TwoFactorService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match two_factor_service.begin_enrolment(&user).await {
+        Ok(enrolment) => context.insert("enrolment", &enrolment),
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    render_two_factor(&state, &user, context, "two_factor/panel.html").await
+}
+
+pub async fn confirm_two_factor(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<TwoFactorCodeRequest>,
+) -> Response {
+    let two_factor_service = TwoFactorService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match two_factor_service
+        .confirm_enrolment(&user, &request.code)
+        .await
+    {
+        Ok(codes) => {
+            context.insert("success", "Two-factor authentication is on.");
+            context.insert("recovery_codes", &codes);
+        }
+        Err(ServiceError::Invalid(message)) => {
+            // keep showing the same QR code so they can try another code
+            match two_factor_service.pending_enrolment(&user).await {
+                Ok(enrolment) => context.insert("enrolment", &enrolment),
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+            context.insert("error", &message);
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    render_two_factor(&state, &user, context, "two_factor/panel.html").await
+}
+
+pub async fn regenerate_recovery_codes(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<TwoFactorCodeRequest>,
+) -> Response {
+    let two_factor_service = TwoFactorService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match two_factor_service
+        .regenerate_recovery_codes(&user, &request.code)
+        .await
+    {
+        Ok(codes) => {
+            context.insert(
+                "success",
+                "Here are your new recovery codes. The old ones no longer work.",
+            );
+            context.insert("recovery_codes", &codes);
+        }
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    render_two_factor(&state, &user, context, "two_factor/panel.html").await
+}
+
+pub async fn disable_two_factor(
+    CurrentUser(user): CurrentUser,
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<TwoFactorCodeRequest>,
+) -> Response {
+    let two_factor_service = TwoFactorService::new(&state.db);
+    let mut context = tera::Context::new();
+
+    match two_factor_service.disable(&user, &request.code).await {
+        Ok(_) => context.insert("success", "Two-factor authentication is off."),
+        Err(ServiceError::Invalid(message)) => context.insert("error", &message),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    render_two_factor(&state, &user, context, "two_factor/panel.html").await
+}
@@ -1,4 +1,16 @@
-use crate::user::{CreateUserRequest, User, auth::hash_password, repository::UserRepository};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+
+use crate::{
+    services::error::ServiceError,
+    user::{
+        CreateUserRequest, User, VerificationClaims,
+        auth::derived_secret,
+        password::{PasswordPolicy, hash_password},
+        repository::UserRepository,
+        role::Role,
+    },
+};
 
 pub struct UserService {
     repository: UserRepository,
@@ -17,8 +29,66 @@ impl UserService {
         self.repository.find_by_email(email).await
     }
 
-    pub async fn create_user(&self, request: &mut CreateUserRequest) -> Result<User, sqlx::Error> {
-        request.password = hash_password(&request.password).await;
-        self.repository.create_user(&request).await
+    /// Creates a user whose password meets the password policy. The request
+    /// carries the password as typed; only its hash is stored.
+    pub async fn create_user(
+        &self,
+        request: &CreateUserRequest,
+        role: Role,
+        email_verified: bool,
+    ) -> Result<User, ServiceError> {
+        PasswordPolicy::from_env().check(
+            &request.password,
+            &[&request.email, &request.given_name, &request.family_name],
+        )?;
+
+        let request = CreateUserRequest {
+            password: hash_password(&request.password).await?,
+            ..request.clone()
+        };
+
+        Ok(self
+            .repository
+            .create_user(&request, role, email_verified)
+            .await?)
+    }
+
+    /// Issues the token for an email verification link, valid for two days.
+    pub fn create_verification_token(&self, user: &User) -> String {
+        let claims = VerificationClaims {
+            sub: user.id,
+            email: user.email.clone(),
+            exp: Utc::now()
+                .checked_add_signed(Duration::days(2))
+                .expect("valid timestamp")
+                .timestamp() as usize,
+        };
+        let key = EncodingKey::from_secret(derived_secret("verify").as_bytes());
+
+        encode(&Header::new(Algorithm::HS256), &claims, &key)
+            .expect("failed to sign verification token")
+    }
+
+    pub async fn verify_email(&self, token: &str) -> Result<User, ServiceError> {
+        let key = DecodingKey::from_secret(derived_secret("verify").as_bytes());
+        let invalid = || {
+            ServiceError::Invalid(
+                "This verification link is invalid or has expired. Sign in to get a new one."
+                    .to_string(),
+            )
+        };
+
+        let claims = decode::<VerificationClaims>(token, &key, &Validation::default())
+            .map_err(|_| invalid())?
+            .claims;
+        let user = match self.repository.find_by_id(claims.sub).await {
+            Ok(user) if user.email == claims.email => user,
+            Ok(_) | Err(sqlx::Error::RowNotFound) => return Err(invalid()),
+            Err(err) => return Err(err.into()),
+        };
+
+        self.repository.mark_email_verified(user.id).await?;
+
+        Ok(user)
     }
 }
@@ -0,0 +1,97 @@
+use chrono::Duration;
+
+pub struct SessionRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl SessionRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        SessionRepository { db: db.clone() }
+    }
+
+    pub async fn create_session(
+        &self,
+        id: &str,
+        user_id: i64,
+        lifetime: Duration,
+    ) -> Result<(), sqlx::Error> {
+        let lifetime = format!("+{} seconds", lifetime.num_seconds());
+        sqlx::query!(
+            "INSERT INTO sessions (id, user_id, expires_at) VALUES (?, ?, datetime('now', ?))",
+            id,
+            user_id,
+            lifetime
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether the session exists for this user and has neither expired nor
+    /// been revoked.
+    pub async fn is_active(&self, id: &str, user_id: i64) -> Result<bool, sqlx::Error> {
+        let session = sqlx::query!(
+            r#"
+            SELECT id FROM sessions
+            WHERE id = ? AND user_id = ? AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            "#,
+            id,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(session.is_some())
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP WHERE id = ? AND revoked_at IS NULL",
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke_all_for_user(&self, user_id: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = ? AND revoked_at IS NULL",
+            user_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Revokes every session the user has except `keep`, the one they are
+    /// using.
+    pub async fn revoke_others(&self, user_id: i64, keep: &str) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = ? AND id != ? AND revoked_at IS NULL
+            "#,
+            user_id,
+            keep
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Forgets sessions that can no longer be used.
+    pub async fn delete_expired(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM sessions WHERE expires_at <= CURRENT_TIMESTAMP OR revoked_at IS NOT NULL"
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
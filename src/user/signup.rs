@@ -0,0 +1,318 @@
+use std::env;
+
+use chrono::Duration;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    services::error::{ServiceError, authorize},
+    user::{
+        CreateUserRequest, Invite, NewInviteRequest, User,
+        repository::UserRepository,
+        role::{Permission, Role},
+        service::UserService,
+    },
+};
+
+/// How long an invite link can be used for.
+pub const INVITE_LIFETIME: Duration = Duration::days(7);
+
+/// Who may create an account, set with `SIGNUP_MODE`. The first account on a
+/// fresh install can always be created, whatever the mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignupMode {
+    /// Anyone can sign up.
+    Open,
+    /// Only people with an invite can sign up. This is the default.
+    Invite,
+    /// Nobody can sign up.
+    Closed,
+}
+
+impl SignupMode {
+    pub fn from_env() -> Self {
+        match env::var("SIGNUP_MODE").as_deref() {
+            Ok("open") => SignupMode::Open,
+            Ok("closed") => SignupMode::Closed,
+            _ => SignupMode::Invite,
+        }
+    }
+}
+
+pub struct InviteRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl InviteRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        InviteRepository { db: db.clone() }
+    }
+
+    pub async fn find_all_pending(&self) -> Result<Vec<Invite>, sqlx::Error> {
+        let invites = sqlx::query!(
+            r#"
+            SELECT i.id, i.email, i.role, i.expires_at, i.created_at,
+                   u.given_name || ' ' || u.family_name AS "invited_by?: String"
+            FROM invites i
+            LEFT JOIN users u ON u.id = i.invited_by
+            WHERE i.accepted_at IS NULL AND i.revoked_at IS NULL
+              AND i.expires_at > CURRENT_TIMESTAMP
+            ORDER BY i.id DESC
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(invites
+            .into_iter()
+            .map(|i| Invite {
+                id: i.id,
+                email: i.email,
+                role: Role::from(i.role),
+                invited_by: i.invited_by,
+                expires_at: i.expires_at.to_string(),
+                created_at: i.created_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// Looks up an invite that can still be accepted by the hash of its token.
+    pub async fn find_pending_by_hash(&self, token_hash: &str) -> Result<Invite, sqlx::Error> {
+        let invite = sqlx::query!(
+            r#"
+            SELECT i.id, i.email, i.role, i.expires_at, i.created_at,
+                   u.given_name || ' ' || u.family_name AS "invited_by?: String"
+            FROM invites i
+            LEFT JOIN users u ON u.id = i.invited_by
+            WHERE i.token_hash = ? AND i.accepted_at IS NULL AND i.revoked_at IS NULL
+              AND i.expires_at > CURRENT_TIMESTAMP
+            "#,
+            token_hash
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(Invite {
+            id: invite.id.expect("id should not be null"),
+            email: invite.email,
+            role: Role::from(invite.role),
+            invited_by: invite.invited_by,
+            expires_at: invite.expires_at.to_string(),
+            created_at: invite.created_at.to_string(),
+        })
+    }
+
+    /// Stores a new invite, revoking any earlier one for the same email so
+    /// that only the newest link works.
+    pub async fn create_invite(
+        &self,
+        email: &str,
+        role: Role,
+        token_hash: &str,
+        invited_by: i64,
+        lifetime: Duration,
+    ) -> Result<i64, sqlx::Error> {
+        let role = role.as_i64();
+        let lifetime = format!("+{} seconds", lifetime.num_seconds());
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE invites SET revoked_at = CURRENT_TIMESTAMP
+            WHERE email = ? COLLATE NOCASE AND accepted_at IS NULL AND revoked_at IS NULL
+            "#,
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO invites (email, role, token_hash, invited_by, expires_at)
+            VALUES (?, ?, ?, ?, datetime('now', ?))
+            RETURNING id
+            "#,
+            email,
+            role,
+            token_hash,
+            invited_by,
+            lifetime
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(id)
+    }
+
+    pub async fn mark_accepted(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE invites SET accepted_at = CURRENT_TIMESTAMP WHERE id = ?",
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke(&self, id: i64) -> Result<(), sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE invites SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = ? AND accepted_at IS NULL AND revoked_at IS NULL
+            "#,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct SignupService {
+    user_repository: UserRepository,
+    user_service: UserService,
+    invite_repository: InviteRepository,
+    mode: SignupMode,
+}
+
+impl SignupService {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        SignupService {
+            user_repository: UserRepository::new(db),
+            user_service: UserService::new(UserRepository::new(db)),
+            invite_repository: InviteRepository::new(db),
+            mode: SignupMode::from_env(),
+        }
+    }
+
+    pub fn mode(&self) -> SignupMode {
+        self.mode
+    }
+
+    /// Whether someone without an invite may sign up right now.
+    pub async fn is_open(&self) -> Result<bool, sqlx::Error> {
+        Ok(self.mode == SignupMode::Open || !self.user_repository.has_users().await?)
+    }
+
+    /// The invite behind a link, as long as it can still be accepted.
+    pub async fn find_invite(&self, token: &str) -> Result<Invite, sqlx::Error> {
+        self.invite_repository
+            .find_pending_by_hash(&hash_token(token))
+            .await
+    }
+
+    /// Creates an account, subject to the signup mode. Signing up through an
+    /// invite gives the invite's role and counts as having verified the email.
+    pub async fn register(&self, request: &mut CreateUserRequest) -> Result<User, ServiceError> {
+        let token = request.invite.take().filter(|token| !token.is_empty());
+        let invite = match token {
+            Some(token) => match self.find_invite(&token).await {
+                Ok(invite) => Some(invite),
+                Err(sqlx::Error::RowNotFound) => {
+                    return Err(ServiceError::Invalid(
+                        "This invite has expired or has already been used.".to_string(),
+                    ));
+                }
+                Err(err) => return Err(err.into()),
+            },
+            None => None,
+        };
+
+        let has_users = self.user_repository.has_users().await?;
+        if has_users {
+            match (self.mode, &invite) {
+                (SignupMode::Closed, _) => {
+                    return Err(ServiceError::Invalid("Signing up is closed.".to_string()));
+                }
+                (SignupMode::Invite, None) => {
+                    return Err(ServiceError::Invalid(
+                        "You need an invite to sign up.".to_string(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(invite) = &invite
+            && !invite.email.eq_ignore_ascii_case(request.email.trim())
+        {
+            return Err(ServiceError::Invalid(format!(
+                "This invite is for {}.",
+                invite.email
+            )));
+        }
+
+        let role = invite.as_ref().map_or(Role::Editor, |invite| invite.role);
+        let user = self
+            .user_service
+            .create_user(request, role, invite.is_some())
+            .await?;
+
+        if let Some(invite) = invite {
+            self.invite_repository.mark_accepted(invite.id).await?;
+        }
+
+        Ok(user)
+    }
+
+    pub async fn pending_invites(&self) -> Result<Vec<Invite>, sqlx::Error> {
+        self.invite_repository.find_all_pending().await
+    }
+
+    /// Invites someone to sign up with the given role. Returns the invite
+    /// and the token for its link, which is only ever available here.
+    pub async fn create_invite(
+        &self,
+        user: &User,
+        request: NewInviteRequest,
+    ) -> Result<(Invite, String), ServiceError> {
+        authorize(user, Permission::InviteUsers)?;
+        if self.mode == SignupMode::Closed {
+            return Err(ServiceError::Invalid(
+                "Signing up is closed, so invites can't be used.".to_string(),
+            ));
+        }
+
+        let email = request.email.trim();
+        match self.user_repository.find_by_email(email).await {
+            Ok(_) => {
+                return Err(ServiceError::Invalid(format!(
+                    "{} already has an account.",
+                    email
+                )));
+            }
+            Err(sqlx::Error::RowNotFound) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        self.invite_repository
+            .create_invite(
+                email,
+                request.role,
+                &hash_token(&token),
+                user.id,
+                INVITE_LIFETIME,
+            )
+            .await?;
+        let invite = self.find_invite(&token).await?;
+
+        Ok((invite, token))
+    }
+
+    pub async fn revoke_invite(&self, user: &User, id: i64) -> Result<(), ServiceError> {
+        authorize(user, Permission::InviteUsers)?;
+        Ok(self.invite_repository.revoke(id).await?)
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,320 @@
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    services::error::ServiceError,
+    user::{
+        CreatedToken, NewTokenRequest, PersonalAccessToken, User, role::Permission,
+        two_factor::TwoFactorService,
+    },
+};
+
+/// Every token starts with this so that leaked tokens are easy to grep for,
+/// and can't be mistaken for an app's API key.
+const TOKEN_PREFIX: &str = "wfp_";
+
+/// How long, in days, a token can be made to last.
+pub const EXPIRY_CHOICES: [i64; 4] = [7, 30, 90, 365];
+
+/// What a personal access token may change. Every token can read whatever
+/// its owner can, scopes only add the right to make changes, and never more
+/// than the owner's role allows.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Edit and publish content.
+    Content,
+    /// Create and delete pages.
+    Pages,
+    /// Manage apps, their API keys and members.
+    Apps,
+    /// Invite people and manage users and security settings.
+    Admin,
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 4] = [
+        TokenScope::Content,
+        TokenScope::Pages,
+        TokenScope::Apps,
+        TokenScope::Admin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Content => "content",
+            TokenScope::Pages => "pages",
+            TokenScope::Apps => "apps",
+            TokenScope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        TokenScope::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == scope)
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        let scope = match permission {
+            Permission::EditContent | Permission::PublishContent => TokenScope::Content,
+            Permission::CreatePages | Permission::DeletePages => TokenScope::Pages,
+            Permission::ManageApps | Permission::ManageApiKeys | Permission::ManageMembers => {
+                TokenScope::Apps
+            }
+            Permission::InviteUsers | Permission::ManageSecurity | Permission::ManageUsers => {
+                TokenScope::Admin
+            }
+        };
+
+        *self == scope
+    }
+}
+
+fn join_scopes(scopes: &[TokenScope]) -> String {
+    scopes
+        .iter()
+        .map(TokenScope::as_str)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn split_scopes(scopes: &str) -> Vec<TokenScope> {
+    scopes
+        .split_whitespace()
+        .filter_map(TokenScope::parse)
+        .collect()
+}
+
+pub struct TokenRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl TokenRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        TokenRepository { db: db.clone() }
+    }
+
+    pub async fn find_all_by_user_id(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<PersonalAccessToken>, sqlx::Error> {
+        let tokens = sqlx::query!(
+            r#"
+            SELECT id AS "id!", name, prefix, scopes, expires_at, last_used_at, revoked_at, created_at
+            FROM personal_access_tokens WHERE user_id = ?
+            ORDER BY revoked_at IS NOT NULL, expires_at <= CURRENT_TIMESTAMP, id DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(tokens
+            .into_iter()
+            .map(|t| PersonalAccessToken {
+                id: t.id,
+                name: t.name,
+                prefix: t.prefix,
+                scopes: split_scopes(&t.scopes),
+                expires_at: t.expires_at.to_string(),
+                last_used_at: t.last_used_at.map(|t| t.to_string()),
+                revoked_at: t.revoked_at.map(|t| t.to_string()),
+                created_at: t.created_at.to_string(),
+            })
+            .collect())
+    }
+
+    /// Looks up a token that has neither expired nor been revoked by the
+    /// hash of its secret. Returns its id, owner and scopes.
+    pub async fn find_active_by_hash(
+        &self,
+        token_hash: &str,
+    ) -> Result<(i64, i64, Vec<TokenScope>), sqlx::Error> {
+        let token = sqlx::query!(
+            r#"
+            SELECT id AS "id!", user_id, scopes FROM personal_access_tokens
+            WHERE token_hash = ? AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            "#,
+            token_hash
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok((token.id, token.user_id, split_scopes(&token.scopes)))
+    }
+
+    pub async fn create_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        prefix: &str,
+        token_hash: &str,
+        scopes: &[TokenScope],
+        lifetime: Duration,
+    ) -> Result<PersonalAccessToken, sqlx::Error> {
+        let scopes = join_scopes(scopes);
+        let lifetime = format!("+{} seconds", lifetime.num_seconds());
+        let token = sqlx::query!(
+            r#"
+            INSERT INTO personal_access_tokens (user_id, name, prefix, token_hash, scopes, expires_at)
+            VALUES (?, ?, ?, ?, ?, datetime('now', ?))
+            RETURNING id AS "id!", name, prefix, scopes, expires_at, last_used_at, revoked_at, created_at
+            "#,
+            user_id,
+            name,
+            prefix,
+            token_hash,
+            scopes,
+            lifetime
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(PersonalAccessToken {
+            id: token.id,
+            name: token.name,
+            prefix: token.prefix,
+            scopes: split_scopes(&token.scopes),
+            expires_at: token.expires_at.to_string(),
+            last_used_at: token.last_used_at.map(|t| t.to_string()),
+            revoked_at: token.revoked_at.map(|t| t.to_string()),
+            created_at: token.created_at.to_string(),
+        })
+    }
+
+    pub async fn touch(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE personal_access_tokens SET last_used_at = CURRENT_TIMESTAMP WHERE id = ?",
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke(&self, id: i64, user_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE personal_access_tokens SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = ? AND user_id = ? AND revoked_at IS NULL
+            "#,
+            id,
+            user_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke_all_for_user(&self, user_id: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE personal_access_tokens SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = ? AND revoked_at IS NULL",
+            user_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Personal access tokens let people's own scripts act as them, within the
+/// token's scopes, by sending `Authorization: Bearer <token>`.
+pub struct TokenService {
+    repository: TokenRepository,
+    two_factor_service: TwoFactorService,
+}
+
+impl TokenService {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        TokenService {
+            repository: TokenRepository::new(db),
+            two_factor_service: TwoFactorService::new(db),
+        }
+    }
+
+    pub async fn tokens(&self, user: &User) -> Result<Vec<PersonalAccessToken>, sqlx::Error> {
+        self.repository.find_all_by_user_id(user.id).await
+    }
+
+    pub async fn create_token(
+        &self,
+        user: &User,
+        request: &NewTokenRequest,
+    ) -> Result<CreatedToken, ServiceError> {
+        // a token outlives the session that made it, and the management API
+        // would otherwise let people put off setting up two-factor for good
+        if self.two_factor_service.must_enrol(user).await? {
+            return Err(ServiceError::Invalid(
+                "Set up two-factor authentication before creating a token.".to_string(),
+            ));
+        }
+
+        let name = request.name.trim();
+        if name.is_empty() || name.chars().count() > 50 {
+            return Err(ServiceError::Invalid(
+                "Give the token a name of up to 50 characters.".to_string(),
+            ));
+        }
+        if !EXPIRY_CHOICES.contains(&request.expires_in_days) {
+            return Err(ServiceError::Invalid(
+                "Choose when the token expires.".to_string(),
+            ));
+        }
+
+        let secret = format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            hex::encode(rand::random::<[u8; 32]>())
+        );
+        let prefix = secret[..TOKEN_PREFIX.len() + 8].to_string();
+        let scopes: Vec<TokenScope> = TokenScope::ALL
+            .into_iter()
+            .filter(|scope| request.scopes.contains(scope))
+            .collect();
+        let token = self
+            .repository
+            .create_token(
+                user.id,
+                name,
+                &prefix,
+                &hash_token(&secret),
+                &scopes,
+                Duration::days(request.expires_in_days),
+            )
+            .await?;
+
+        Ok(CreatedToken { token, secret })
+    }
+
+    pub async fn revoke(&self, user: &User, id: i64) -> Result<(), sqlx::Error> {
+        self.repository.revoke(id, user.id).await
+    }
+
+    /// Resolves a presented token to its owner's id and the token's scopes,
+    /// recording when it was last used. Fails with `RowNotFound` for tokens
+    /// that don't exist, have expired or were revoked.
+    pub async fn authenticate(&self, secret: &str) -> Result<(i64, Vec<TokenScope>), sqlx::Error> {
+        if !secret.starts_with(TOKEN_PREFIX) {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        let (id, user_id, scopes) = self
+            .repository
+            .find_active_by_hash(&hash_token(secret))
+            .await?;
+        self.repository.touch(id).await?;
+
+        Ok((user_id, scopes))
+    }
+}
+
+// tokens carry 256 bits of randomness, so a plain sha256 is enough, the same
+// as for API keys
+fn hash_token(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
@@ -0,0 +1,526 @@
+use chrono::{Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use qrcode::{QrCode, render::svg};
+use sha2::{Digest, Sha256};
+use totp_rs::{Secret, TOTP};
+
+use crate::{
+    services::error::{ServiceError, authorize},
+    user::{
+        TwoFactorClaims, TwoFactorEnrolment, TwoFactorStatus, User,
+        auth::derived_secret,
+        role::{Permission, Role},
+    },
+};
+
+/// How long someone has to enter their code once their password checks out.
+pub const CHALLENGE_LIFETIME: Duration = Duration::minutes(5);
+
+/// How many recovery codes are handed out at a time.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const ISSUER: &str = "Wordford";
+const STEP_SECS: u64 = 30;
+
+struct TwoFactorSecret {
+    secret: String,
+    last_used_step: i64,
+    confirmed: bool,
+}
+
+pub struct TwoFactorRepository {
+    db: sqlx::SqlitePool,
+}
+
+impl TwoFactorRepository {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        TwoFactorRepository { db: db.clone() }
+    }
+
+    async fn find_secret(&self, user_id: i64) -> Result<Option<TwoFactorSecret>, sqlx::Error> {
+        let secret = sqlx::query!(
+            r#"
+            SELECT secret, last_used_step, confirmed_at IS NOT NULL AS "confirmed!: bool"
+            FROM two_factor_secrets WHERE user_id = ?
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(secret.map(|row| TwoFactorSecret {
+            secret: row.secret,
+            last_used_step: row.last_used_step,
+            confirmed: row.confirmed,
+        }))
+    }
+
+    /// Stores a secret that still has to be confirmed, replacing one the user
+    /// didn't finish setting up. A confirmed secret is left alone.
+    pub async fn save_pending_secret(&self, user_id: i64, secret: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO two_factor_secrets (user_id, secret) VALUES (?, ?)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = excluded.secret, last_used_step = 0, created_at = CURRENT_TIMESTAMP
+            WHERE two_factor_secrets.confirmed_at IS NULL
+            "#,
+            user_id,
+            secret
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Turns two-factor authentication on and hands out a fresh set of
+    /// recovery codes.
+    pub async fn confirm(
+        &self,
+        user_id: i64,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE two_factor_secrets SET confirmed_at = CURRENT_TIMESTAMP, last_used_step = ?
+            WHERE user_id = ?
+            "#,
+            step,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        replace_recovery_codes(&mut tx, user_id, recovery_code_hashes).await?;
+
+        tx.commit().await
+    }
+
+    /// Moves the last used time step forward. Returns false when a code for
+    /// this step or a later one was already accepted.
+    pub async fn record_step(&self, user_id: i64, step: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE two_factor_secrets SET last_used_step = ? WHERE user_id = ? AND last_used_step < ?",
+            step,
+            user_id,
+            step
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn replace_recovery_codes(
+        &self,
+        user_id: i64,
+        code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.db.begin().await?;
+        replace_recovery_codes(&mut tx, user_id, code_hashes).await?;
+        tx.commit().await
+    }
+
+    /// Marks an unused recovery code as used. Returns false if there was no
+    /// such code.
+    pub async fn use_recovery_code(
+        &self,
+        user_id: i64,
+        code_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE recovery_codes SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = ? AND code_hash = ? AND used_at IS NULL
+            "#,
+            user_id,
+            code_hash
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn count_recovery_codes(&self, user_id: i64) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM recovery_codes WHERE user_id = ? AND used_at IS NULL",
+            user_id
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    pub async fn is_enabled(&self, user_id: i64) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM two_factor_secrets WHERE user_id = ? AND confirmed_at IS NOT NULL
+            ) AS "enabled!: bool"
+            "#,
+            user_id
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    /// Whether the user's role requires two-factor authentication and they
+    /// haven't set it up yet.
+    pub async fn must_enrol(&self, user_id: i64, role: Role) -> Result<bool, sqlx::Error> {
+        let role = role.as_i64();
+        sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM two_factor_required_roles WHERE role = ?)
+               AND NOT EXISTS(
+                   SELECT 1 FROM two_factor_secrets WHERE user_id = ? AND confirmed_at IS NOT NULL
+               ) AS "must_enrol!: bool"
+            "#,
+            role,
+            user_id
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    /// Turns two-factor authentication off, throwing away the secret and any
+    /// recovery codes.
+    pub async fn delete(&self, user_id: i64) -> Result<(), sqlx::Error> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM two_factor_secrets WHERE user_id = ?", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    pub async fn required_roles(&self) -> Result<Vec<Role>, sqlx::Error> {
+        let roles = sqlx::query_scalar!("SELECT role FROM two_factor_required_roles ORDER BY role")
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(roles.into_iter().map(Role::from).collect())
+    }
+
+    pub async fn set_required_roles(&self, roles: &[Role]) -> Result<(), sqlx::Error> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!("DELETE FROM two_factor_required_roles")
+            .execute(&mut *tx)
+            .await?;
+        for role in roles {
+            let role = role.as_i64();
+            sqlx::query!(
+                "INSERT INTO two_factor_required_roles (role) VALUES (?)",
+                role
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+}
+
+async fn replace_recovery_codes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user_id: i64,
+    code_hashes: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+        .execute(&mut **tx)
+        .await?;
+    for code_hash in code_hashes {
+        sqlx::query!(
+            "INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)",
+            user_id,
+            code_hash
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub struct TwoFactorService {
+    repository: TwoFactorRepository,
+}
+
+impl TwoFactorService {
+    pub fn new(db: &sqlx::SqlitePool) -> Self {
+        TwoFactorService {
+            repository: TwoFactorRepository::new(db),
+        }
+    }
+
+    pub async fn status(&self, user: &User) -> Result<TwoFactorStatus, sqlx::Error> {
+        Ok(TwoFactorStatus {
+            enabled: self.repository.is_enabled(user.id).await?,
+            required: self.is_required(user.role).await?,
+            recovery_codes_left: self.repository.count_recovery_codes(user.id).await?,
+        })
+    }
+
+    pub async fn is_enabled(&self, user_id: i64) -> Result<bool, sqlx::Error> {
+        self.repository.is_enabled(user_id).await
+    }
+
+    pub async fn must_enrol(&self, user: &User) -> Result<bool, sqlx::Error> {
+        self.repository.must_enrol(user.id, user.role).await
+    }
+
+    /// Generates a new secret for the user to add to their authenticator app.
+    /// It only takes effect once `confirm_enrolment` sees a code from it.
+    pub async fn begin_enrolment(&self, user: &User) -> Result<TwoFactorEnrolment, ServiceError> {
+        if self.repository.is_enabled(user.id).await? {
+            return Err(ServiceError::Invalid(
+                "Two-factor authentication is already on.".to_string(),
+            ));
+        }
+
+        let totp = totp(rand::random::<[u8; 20]>().to_vec(), &user.email);
+        self.repository
+            .save_pending_secret(user.id, &totp.get_secret_base32())
+            .await?;
+
+        Ok(enrolment(&totp))
+    }
+
+    /// The enrolment the user started but hasn't confirmed yet, if any.
+    pub async fn pending_enrolment(
+        &self,
+        user: &User,
+    ) -> Result<Option<TwoFactorEnrolment>, sqlx::Error> {
+        let enrolment = match self.repository.find_secret(user.id).await? {
+            Some(secret) if !secret.confirmed => Secret::Encoded(secret.secret)
+                .to_bytes()
+                .ok()
+                .map(|bytes| enrolment(&totp(bytes, &user.email))),
+            _ => None,
+        };
+
+        Ok(enrolment)
+    }
+
+    /// Turns two-factor authentication on once the user proves their app
+    /// generates the right codes, and returns their recovery codes.
+    pub async fn confirm_enrolment(
+        &self,
+        user: &User,
+        code: &str,
+    ) -> Result<Vec<String>, ServiceError> {
+        let secret = match self.repository.find_secret(user.id).await? {
+            Some(secret) if !secret.confirmed => secret,
+            Some(_) => {
+                return Err(ServiceError::Invalid(
+                    "Two-factor authentication is already on.".to_string(),
+                ));
+            }
+            None => {
+                return Err(ServiceError::Invalid(
+                    "Start setting up two-factor authentication first.".to_string(),
+                ));
+            }
+        };
+
+        let Some(step) = matching_step(&secret, &normalize_code(code)) else {
+            return Err(invalid_code());
+        };
+
+        let codes = new_recovery_codes();
+        let hashes: Vec<String> = codes.iter().map(|code| hash_code(code)).collect();
+        self.repository.confirm(user.id, step, &hashes).await?;
+
+        Ok(codes)
+    }
+
+    /// Checks a code from the user's authenticator app, or one of their
+    /// recovery codes. Either can only be used once.
+    pub async fn verify_code(&self, user_id: i64, code: &str) -> Result<bool, sqlx::Error> {
+        let code = normalize_code(code);
+        let secret = match self.repository.find_secret(user_id).await? {
+            Some(secret) if secret.confirmed => secret,
+            _ => return Ok(false),
+        };
+
+        if is_totp_code(&code) {
+            return match matching_step(&secret, &code) {
+                Some(step) => self.repository.record_step(user_id, step).await,
+                None => Ok(false),
+            };
+        }
+
+        self.repository
+            .use_recovery_code(user_id, &hash_code(&code))
+            .await
+    }
+
+    /// Throws away the user's recovery codes and returns a new set.
+    pub async fn regenerate_recovery_codes(
+        &self,
+        user: &User,
+        code: &str,
+    ) -> Result<Vec<String>, ServiceError> {
+        if !self.verify_code(user.id, code).await? {
+            return Err(invalid_code());
+        }
+
+        let codes = new_recovery_codes();
+        let hashes: Vec<String> = codes.iter().map(|code| hash_code(code)).collect();
+        self.repository
+            .replace_recovery_codes(user.id, &hashes)
+            .await?;
+
+        Ok(codes)
+    }
+
+    pub async fn disable(&self, user: &User, code: &str) -> Result<(), ServiceError> {
+        if self.is_required(user.role).await? {
+            return Err(ServiceError::Invalid(
+                "Your role requires two-factor authentication, so it can't be turned off."
+                    .to_string(),
+            ));
+        }
+        if !self.verify_code(user.id, code).await? {
+            return Err(invalid_code());
+        }
+
+        self.repository.delete(user.id).await?;
+
+        Ok(())
+    }
+
+    pub async fn required_roles(&self) -> Result<Vec<Role>, sqlx::Error> {
+        self.repository.required_roles().await
+    }
+
+    pub async fn set_required_roles(
+        &self,
+        user: &User,
+        roles: &[Role],
+    ) -> Result<(), ServiceError> {
+        authorize(user, Permission::ManageSecurity)?;
+        self.repository.set_required_roles(roles).await?;
+
+        Ok(())
+    }
+
+    async fn is_required(&self, role: Role) -> Result<bool, sqlx::Error> {
+        Ok(self.repository.required_roles().await?.contains(&role))
+    }
+
+    /// Issues the token that carries someone from the password step of
+    /// signing in to the code step.
+    pub fn create_challenge(&self, user_id: i64) -> String {
+        let claims = TwoFactorClaims {
+            sub: user_id,
+            exp: Utc::now()
+                .checked_add_signed(CHALLENGE_LIFETIME)
+                .expect("valid timestamp")
+                .timestamp() as usize,
+        };
+        let key = EncodingKey::from_secret(derived_secret("two-factor").as_bytes());
+
+        encode(&Header::new(Algorithm::HS256), &claims, &key)
+            .expect("failed to sign two-factor challenge")
+    }
+
+    /// The user a challenge was issued for, if it is genuine and hasn't expired.
+    pub fn decode_challenge(&self, challenge: &str) -> Option<i64> {
+        let key = DecodingKey::from_secret(derived_secret("two-factor").as_bytes());
+
+        decode::<TwoFactorClaims>(challenge, &key, &Validation::default())
+            .ok()
+            .map(|token| token.claims.sub)
+    }
+}
+
+fn totp(secret: Vec<u8>, account_name: &str) -> TOTP {
+    // no skew, `matching_step` checks the neighbouring steps itself so it
+    // knows which one a code belongs to
+    TOTP::new(
+        totp_rs::Algorithm::SHA1,
+        6,
+        0,
+        STEP_SECS,
+        secret,
+        Some(ISSUER.to_string()),
+        account_name.replace(':', ""),
+    )
+    .expect("secret is long enough")
+}
+
+/// The time step `code` was generated for, allowing for a step of clock
+/// drift either way. Steps that were already used don't match.
+fn matching_step(secret: &TwoFactorSecret, code: &str) -> Option<i64> {
+    if !is_totp_code(code) {
+        return None;
+    }
+
+    let bytes = Secret::Encoded(secret.secret.clone()).to_bytes().ok()?;
+    let totp = totp(bytes, "");
+    let current = Utc::now().timestamp() as u64 / STEP_SECS;
+
+    [current - 1, current, current + 1]
+        .into_iter()
+        .find(|step| *step as i64 > secret.last_used_step && totp.check(code, step * STEP_SECS))
+        .map(|step| step as i64)
+}
+
+fn enrolment(totp: &TOTP) -> TwoFactorEnrolment {
+    TwoFactorEnrolment {
+        secret: totp.get_secret_base32(),
+        qr_code: qr_code_svg(&totp.get_url()),
+    }
+}
+
+fn qr_code_svg(url: &str) -> String {
+    let svg = QrCode::new(url.as_bytes())
+        .expect("otpauth url fits in a QR code")
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    // drop the XML declaration so the SVG can be placed inline
+    match svg.find("<svg") {
+        Some(start) => svg[start..].to_string(),
+        None => svg,
+    }
+}
+
+fn new_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = hex::encode(rand::random::<[u8; 5]>());
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Codes are compared without spaces or dashes and ignoring case, so they
+/// can be typed however they were written down.
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+fn is_totp_code(code: &str) -> bool {
+    code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn hash_code(code: &str) -> String {
+    hex::encode(Sha256::digest(normalize_code(code).as_bytes()))
+}
+
+fn invalid_code() -> ServiceError {
+    ServiceError::Invalid("That code isn't right. Please try again.".to_string())
+}
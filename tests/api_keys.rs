@@ -0,0 +1,213 @@
+//! Issuing app API keys, and checking them against the app and scope a
+//! request needs.
+
+use axum::http::StatusCode;
+use sqlx::SqlitePool;
+use wordford::{
+    extractors::api_key::AppApiKey,
+    models::{
+        api_key::{ApiKeyScope, NewApiKeyRequest},
+        app::CreateAppForm,
+    },
+    repositories::{api_keys::ApiKeyRepository, apps::AppRepository},
+    services::{api_keys::ApiKeyService, error::ServiceError},
+    user::{User, role::Role},
+};
+
+mod common;
+
+fn service(db: &SqlitePool) -> ApiKeyService {
+    ApiKeyService::new(ApiKeyRepository::new(db))
+}
+
+async fn create_app(db: &SqlitePool, owner: &User, name: &str) -> i64 {
+    AppRepository::new(db)
+        .create_app(
+            CreateAppForm {
+                name: name.to_string(),
+                description: String::new(),
+                url: "https://example.com".to_string(),
+            },
+            &owner.id,
+            Role::Admin,
+        )
+        .await
+        .unwrap()
+        .id
+}
+
+fn request(scope: ApiKeyScope) -> NewApiKeyRequest {
+    NewApiKeyRequest {
+        name: " website ".to_string(),
+        scope,
+    }
+}
+
+#[tokio::test]
+async fn only_a_hash_of_the_key_is_stored() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let app_id = create_app(&db, &admin, "Blog").await;
+
+    let created = service(&db)
+        .create_api_key(&admin, &app_id, request(ApiKeyScope::Read))
+        .await
+        .unwrap();
+
+    assert!(created.token.starts_with("wf_"));
+    assert_eq!(created.token.len(), 3 + 64);
+    assert_eq!(created.key.prefix, created.token[..11]);
+    assert_eq!(created.key.name, "website");
+
+    let stored: String = sqlx::query_scalar("SELECT key_hash FROM api_keys WHERE id = ?")
+        .bind(created.key.id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_ne!(stored, created.token);
+    assert!(!stored.contains(&created.token[3..]));
+    assert_eq!(stored.len(), 64);
+    assert!(stored.chars().all(|c| c.is_ascii_hexdigit()));
+}
+
+#[tokio::test]
+async fn a_key_authenticates_until_it_is_revoked() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let app_id = create_app(&db, &admin, "Blog").await;
+    let service = service(&db);
+    let created = service
+        .create_api_key(&admin, &app_id, request(ApiKeyScope::Read))
+        .await
+        .unwrap();
+    assert!(created.key.last_used_at.is_none());
+
+    let key = service.authenticate(&created.token).await.unwrap();
+    assert_eq!(key.id, created.key.id);
+    assert_eq!(key.app_id, app_id);
+    let used = service.find_all_by_app_id(&app_id).await.unwrap();
+    assert!(used[0].last_used_at.is_some());
+
+    service
+        .revoke_api_key(&admin, &app_id, &created.key.id)
+        .await
+        .unwrap();
+    assert!(matches!(
+        service.authenticate(&created.token).await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn unknown_or_unprefixed_keys_are_rejected() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let app_id = create_app(&db, &admin, "Blog").await;
+    let service = service(&db);
+    let created = service
+        .create_api_key(&admin, &app_id, request(ApiKeyScope::Read))
+        .await
+        .unwrap();
+
+    let mut wrong = created.token.clone();
+    wrong.pop();
+    wrong.push(if created.token.ends_with('0') {
+        '1'
+    } else {
+        '0'
+    });
+    assert!(service.authenticate(&wrong).await.is_err());
+    // the bare secret without the prefix is not a key
+    assert!(service.authenticate(&created.token[3..]).await.is_err());
+    assert!(service.authenticate("").await.is_err());
+}
+
+#[tokio::test]
+async fn another_app_cannot_revoke_a_key() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let app_id = create_app(&db, &admin, "Blog").await;
+    let other_id = create_app(&db, &admin, "Shop").await;
+    let service = service(&db);
+    let created = service
+        .create_api_key(&admin, &app_id, request(ApiKeyScope::Read))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        service
+            .revoke_api_key(&admin, &other_id, &created.key.id)
+            .await,
+        Err(ServiceError::Database(sqlx::Error::RowNotFound))
+    ));
+
+    assert!(service.authenticate(&created.token).await.is_ok());
+}
+
+#[test]
+fn write_keys_can_also_read() {
+    assert!(ApiKeyScope::Read.allows(ApiKeyScope::Read));
+    assert!(!ApiKeyScope::Read.allows(ApiKeyScope::Write));
+    assert!(ApiKeyScope::Write.allows(ApiKeyScope::Read));
+    assert!(ApiKeyScope::Write.allows(ApiKeyScope::Write));
+}
+
+#[tokio::test]
+async fn a_key_only_grants_its_own_app_and_scope() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let app_id = create_app(&db, &admin, "Blog").await;
+    let other_id = create_app(&db, &admin, "Shop").await;
+    let service = service(&db);
+    let read = service
+        .create_api_key(&admin, &app_id, request(ApiKeyScope::Read))
+        .await
+        .unwrap();
+    let write = service
+        .create_api_key(&admin, &app_id, request(ApiKeyScope::Write))
+        .await
+        .unwrap();
+
+    let read = AppApiKey(read.key);
+    assert!(read.require(&app_id, ApiKeyScope::Read).is_ok());
+    let err = read.require(&app_id, ApiKeyScope::Write).unwrap_err();
+    assert_eq!(err.status, StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "insufficient_scope");
+    let err = read.require(&other_id, ApiKeyScope::Read).unwrap_err();
+    assert_eq!(err.status, StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "forbidden");
+
+    let write = AppApiKey(write.key);
+    assert!(write.require(&app_id, ApiKeyScope::Read).is_ok());
+    assert!(write.require(&app_id, ApiKeyScope::Write).is_ok());
+    assert!(write.require(&other_id, ApiKeyScope::Write).is_err());
+}
+
+#[tokio::test]
+async fn only_admins_manage_keys() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let publisher = User {
+        role: Role::Publisher,
+        ..admin.clone()
+    };
+    let app_id = create_app(&db, &admin, "Blog").await;
+    let service = service(&db);
+
+    assert!(matches!(
+        service
+            .create_api_key(&publisher, &app_id, request(ApiKeyScope::Read))
+            .await,
+        Err(ServiceError::Forbidden(_))
+    ));
+    let created = service
+        .create_api_key(&admin, &app_id, request(ApiKeyScope::Read))
+        .await
+        .unwrap();
+    assert!(matches!(
+        service
+            .revoke_api_key(&publisher, &app_id, &created.key.id)
+            .await,
+        Err(ServiceError::Forbidden(_))
+    ));
+}
@@ -0,0 +1,64 @@
+// each test binary only uses some of these
+#![allow(dead_code)]
+
+use std::sync::{Arc, Once};
+
+use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
+use tera::Tera;
+use wordford::{
+    AppState,
+    mailer::LogMailer,
+    user::{
+        CreateUserRequest, User, oidc::ProviderMetadataCache, repository::UserRepository,
+        role::Role,
+    },
+};
+
+/// A fresh, migrated database that lives as long as the pool.
+pub async fn database() -> SqlitePool {
+    static ENV: Once = Once::new();
+    // every test sets the same values before anything reads them
+    ENV.call_once(|| unsafe {
+        std::env::set_var("JWT_SECRET", "test");
+        std::env::set_var("APP_URL", "https://wordford.test");
+    });
+
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&db).await.unwrap();
+
+    db
+}
+
+/// Enough state to serve the JSON APIs, which render no templates and
+/// send no mail.
+pub fn state(db: &SqlitePool) -> Arc<AppState> {
+    Arc::new(AppState {
+        db: db.clone(),
+        tera: Arc::new(Tera::default()),
+        mailer: Arc::new(LogMailer { dir: None }),
+        oidc_metadata: ProviderMetadataCache::default(),
+    })
+}
+
+/// The first account, which is always made an admin.
+pub async fn admin(db: &SqlitePool) -> User {
+    create_user(db, "admin@wordford.test", true).await
+}
+
+pub async fn create_user(db: &SqlitePool, email: &str, verified: bool) -> User {
+    let request = CreateUserRequest {
+        email: email.to_string(),
+        password: "not a real hash".to_string(),
+        given_name: "Ada".to_string(),
+        family_name: "Lovelace".to_string(),
+        invite: None,
+    };
+    UserRepository::new(db)
+        .create_user(&request, Role::Editor, verified)
+        .await
+        .unwrap()
+}
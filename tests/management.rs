@@ -0,0 +1,339 @@
+//! The management API, and how service failures map to status codes.
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    http::{
+        Method, Request, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+    },
+};
+use serde_json::{Value, json};
+use sqlx::SqlitePool;
+use tower::ServiceExt;
+use wordford::{
+    models::api_key::{ApiKeyScope, NewApiKeyRequest},
+    repositories::api_keys::ApiKeyRepository,
+    routes::api,
+    services::api_keys::ApiKeyService,
+    user::{
+        NewTokenRequest, User,
+        tokens::{TokenScope, TokenService},
+    },
+};
+
+mod common;
+
+struct Client {
+    app: Router,
+    token: String,
+}
+
+impl Client {
+    async fn new(db: &SqlitePool, user: &User, scopes: &[TokenScope]) -> Self {
+        let request = NewTokenRequest {
+            name: "tests".to_string(),
+            scopes: scopes.to_vec(),
+            expires_in_days: 7,
+        };
+        let created = TokenService::new(db)
+            .create_token(user, &request)
+            .await
+            .unwrap();
+
+        Client {
+            app: api::routes().with_state(common::state(db)),
+            token: created.secret,
+        }
+    }
+
+    /// A client using a key for `app_id` rather than a personal access token.
+    async fn with_key(db: &SqlitePool, admin: &User, app_id: i64, scope: ApiKeyScope) -> Self {
+        let request = NewApiKeyRequest {
+            name: "website".to_string(),
+            scope,
+        };
+        let created = ApiKeyService::new(ApiKeyRepository::new(db))
+            .create_api_key(admin, &app_id, request)
+            .await
+            .unwrap();
+
+        Client {
+            app: api::routes().with_state(common::state(db)),
+            token: created.token,
+        }
+    }
+
+    async fn send(&self, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("/api/v1/manage{uri}"))
+            .header(AUTHORIZATION, format!("Bearer {}", self.token));
+        let request = match body {
+            Some(body) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+
+        let response = self.app.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    async fn get(&self, uri: &str) -> (StatusCode, Value) {
+        self.send(Method::GET, uri, None).await
+    }
+
+    async fn post(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.send(Method::POST, uri, Some(body)).await
+    }
+}
+
+async fn admin_client(db: &SqlitePool) -> Client {
+    let admin = common::admin(db).await;
+    Client::new(db, &admin, &TokenScope::ALL).await
+}
+
+#[tokio::test]
+async fn creates_apps_pages_and_content() {
+    let db = common::database().await;
+    let client = admin_client(&db).await;
+
+    let (status, app) = client.post("/apps", json!({ "name": "Blog" })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, page) = client
+        .post(
+            &format!("/apps/{}/pages", app["id"]),
+            json!({ "name": "About us" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(page["name"], "about_us");
+    let (status, content) = client
+        .post(
+            &format!("/pages/{}/content", page["id"]),
+            json!({ "name": "intro", "body": "<p>Hi</p>" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, fetched) = client.get(&format!("/content/{}", content["id"])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched["body"], "<p>Hi</p>");
+
+    let (status, _) = client
+        .send(Method::DELETE, &format!("/apps/{}", app["id"]), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn missing_things_are_404s() {
+    let db = common::database().await;
+    let client = admin_client(&db).await;
+
+    let (status, body) = client.get("/apps/999").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "app_not_found");
+    let (_, body) = client.get("/pages/999").await;
+    assert_eq!(body["error"]["code"], "page_not_found");
+    let (_, body) = client.get("/content/999").await;
+    assert_eq!(body["error"]["code"], "content_not_found");
+}
+
+#[tokio::test]
+async fn apps_the_user_is_not_a_member_of_are_404s() {
+    let db = common::database().await;
+    let admin = admin_client(&db).await;
+    let (_, app) = admin.post("/apps", json!({ "name": "Blog" })).await;
+    let editor = common::create_user(&db, "editor@wordford.test", true).await;
+    let client = Client::new(&db, &editor, &TokenScope::ALL).await;
+
+    let (status, body) = client.get(&format!("/apps/{}", app["id"])).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "app_not_found");
+}
+
+#[tokio::test]
+async fn invalid_fields_are_422s() {
+    let db = common::database().await;
+    let client = admin_client(&db).await;
+
+    let (status, body) = client
+        .post("/apps", json!({ "name": " ", "url": "ftp://example.com" }))
+        .await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "validation_failed");
+    let fields: Vec<&str> = body["error"]["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["name", "url"]);
+
+    // a body that doesn't fit the request at all
+    let (status, body) = client.post("/apps", json!({ "title": "Blog" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "invalid_body");
+}
+
+#[tokio::test]
+async fn taken_names_are_409s() {
+    let db = common::database().await;
+    let client = admin_client(&db).await;
+    let (_, app) = client.post("/apps", json!({ "name": "Blog" })).await;
+    let pages = format!("/apps/{}/pages", app["id"]);
+    client.post(&pages, json!({ "name": "home" })).await;
+
+    let (status, body) = client.post("/apps", json!({ "name": "Blog" })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"]["code"], "name_taken");
+    assert_eq!(body["error"]["fields"][0]["field"], "name");
+
+    let (status, _) = client.post(&pages, json!({ "name": "home" })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn tokens_without_the_scope_are_403s() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let client = Client::new(&db, &admin, &[TokenScope::Content]).await;
+
+    let (status, body) = client.post("/apps", json!({ "name": "Blog" })).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["code"], "forbidden");
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_401s() {
+    let db = common::database().await;
+    let client = Client {
+        token: "wfp_0000".to_string(),
+        ..admin_client(&db).await
+    };
+
+    let (status, body) = client.get("/apps").await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"]["code"], "unauthorized");
+}
+
+/// Creates two apps as `admin`, returning their ids.
+async fn two_apps(db: &SqlitePool, admin: &User) -> (i64, i64) {
+    let client = Client::new(db, admin, &TokenScope::ALL).await;
+    let (_, blog) = client.post("/apps", json!({ "name": "Blog" })).await;
+    let (_, shop) = client.post("/apps", json!({ "name": "Shop" })).await;
+
+    (blog["id"].as_i64().unwrap(), shop["id"].as_i64().unwrap())
+}
+
+#[tokio::test]
+async fn write_keys_manage_their_own_app() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let (blog, _) = two_apps(&db, &admin).await;
+    let client = Client::with_key(&db, &admin, blog, ApiKeyScope::Write).await;
+
+    let (status, page) = client
+        .post(&format!("/apps/{blog}/pages"), json!({ "name": "home" }))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, content) = client
+        .post(
+            &format!("/pages/{}/content", page["id"]),
+            json!({ "name": "intro", "body": "<p>Hi</p>" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = client
+        .send(
+            Method::PATCH,
+            &format!("/content/{}", content["id"]),
+            Some(json!({ "body": "<p>Hello</p>" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // nobody is recorded as the author
+    let authors: Vec<Option<i64>> =
+        sqlx::query_scalar("SELECT user_id FROM content_versions WHERE content_id = ?")
+            .bind(content["id"].as_i64())
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert!(!authors.is_empty());
+    assert!(authors.iter().all(Option::is_none));
+}
+
+#[tokio::test]
+async fn keys_only_see_their_own_app() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let (blog, shop) = two_apps(&db, &admin).await;
+    let (_, page) = Client::new(&db, &admin, &TokenScope::ALL)
+        .await
+        .post(&format!("/apps/{shop}/pages"), json!({ "name": "home" }))
+        .await;
+    let client = Client::with_key(&db, &admin, blog, ApiKeyScope::Write).await;
+
+    let (status, apps) = client.get("/apps").await;
+    assert_eq!(status, StatusCode::OK);
+    let ids: Vec<i64> = apps
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|app| app["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, [blog]);
+
+    let (status, body) = client.get(&format!("/apps/{shop}")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "app_not_found");
+    let (status, body) = client
+        .post(
+            &format!("/pages/{}/content", page["id"]),
+            json!({ "name": "intro", "body": "<p>Hi</p>" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "page_not_found");
+}
+
+#[tokio::test]
+async fn keys_cannot_manage_apps_themselves() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let (blog, _) = two_apps(&db, &admin).await;
+    let client = Client::with_key(&db, &admin, blog, ApiKeyScope::Write).await;
+    let uri = format!("/apps/{blog}");
+
+    let (status, _) = client.post("/apps", json!({ "name": "Docs" })).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = client
+        .send(Method::PATCH, &uri, Some(json!({ "name": "Journal" })))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = client.send(Method::DELETE, &uri, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn read_keys_are_403s() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let (blog, _) = two_apps(&db, &admin).await;
+    let client = Client::with_key(&db, &admin, blog, ApiKeyScope::Read).await;
+
+    let (status, body) = client.get(&format!("/apps/{blog}")).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["code"], "insufficient_scope");
+}
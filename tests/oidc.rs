@@ -0,0 +1,390 @@
+//! Signing in through a stand-in identity provider, served on a local port.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use axum::{
+    Form, Json, Router,
+    extract::State,
+    routing::{get, post},
+};
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use openidconnect::url::Url;
+use serde_json::{Value, json};
+use sqlx::SqlitePool;
+use wordford::{
+    services::error::ServiceError,
+    user::{
+        User,
+        auth::{AuthService, IdentityLogin},
+        oidc::{OidcConfig, OidcService, ProviderMetadataCache},
+        repository::UserRepository,
+        role::Role,
+    },
+};
+
+mod common;
+
+const CLIENT_ID: &str = "wordford";
+
+struct Provider {
+    issuer: String,
+    discoveries: AtomicUsize,
+    /// The ID token claims to hand out for each authorization code.
+    codes: Mutex<HashMap<String, Value>>,
+}
+
+impl Provider {
+    async fn start() -> Arc<Provider> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let provider = Arc::new(Provider {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            discoveries: AtomicUsize::new(0),
+            codes: Mutex::new(HashMap::new()),
+        });
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(provider.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        provider
+    }
+}
+
+async fn discovery(State(provider): State<Arc<Provider>>) -> Json<Value> {
+    provider.discoveries.fetch_add(1, Ordering::SeqCst);
+    let issuer = &provider.issuer;
+
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    }))
+}
+
+async fn jwks() -> Json<Value> {
+    Json(serde_json::from_str(include_str!("fixtures/oidc_jwks.json")).unwrap())
+}
+
+async fn token(
+    State(provider): State<Arc<Provider>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Json<Value> {
+    let claims = provider
+        .codes
+        .lock()
+        .unwrap()
+        .remove(&form["code"])
+        .unwrap();
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("test".to_string());
+    let key = EncodingKey::from_rsa_pem(include_bytes!("fixtures/oidc_key.pem")).unwrap();
+
+    Json(json!({
+        "access_token": "access",
+        "token_type": "Bearer",
+        "id_token": encode(&header, &claims, &key).unwrap(),
+    }))
+}
+
+struct Setup {
+    db: SqlitePool,
+    provider: Arc<Provider>,
+    service: OidcService,
+}
+
+async fn setup() -> Setup {
+    setup_with(|_| {}).await
+}
+
+/// Like `setup`, but lets the test change the configuration first.
+async fn setup_with(configure: impl FnOnce(&mut OidcConfig)) -> Setup {
+    let db = common::database().await;
+
+    let provider = Provider::start().await;
+    let mut config = OidcConfig {
+        issuer_url: provider.issuer.clone(),
+        client_id: CLIENT_ID.to_string(),
+        client_secret: Some("secret".to_string()),
+        redirect_url: "http://localhost:3000/signin/oidc/callback".to_string(),
+        provider_name: "Test IdP".to_string(),
+        groups_claim: "groups".to_string(),
+        role_mapping: Vec::new(),
+        default_role: Role::Viewer,
+        create_users: true,
+    };
+    configure(&mut config);
+    let service = OidcService::new(&db, config, &ProviderMetadataCache::default());
+
+    Setup {
+        db,
+        provider,
+        service,
+    }
+}
+
+impl Setup {
+    /// Goes through the whole flow as someone the provider describes with
+    /// `claims`.
+    async fn sign_in(&self, subject: &str, claims: Value) -> Result<User, ServiceError> {
+        let (url, flow) = self.service.begin("/apps").await?;
+        let params: HashMap<String, String> = Url::parse(&url)
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+
+        let mut id_token = json!({
+            "iss": self.provider.issuer,
+            "aud": CLIENT_ID,
+            "sub": subject,
+            "nonce": params["nonce"],
+            "iat": Utc::now().timestamp(),
+            "exp": Utc::now().timestamp() + 300,
+            "given_name": "Ada",
+            "family_name": "Lovelace",
+        });
+        id_token
+            .as_object_mut()
+            .unwrap()
+            .extend(claims.as_object().unwrap().clone());
+        let code = hex::encode(rand::random::<[u8; 8]>());
+        self.provider
+            .codes
+            .lock()
+            .unwrap()
+            .insert(code.clone(), id_token);
+
+        let (user, return_to) = self
+            .service
+            .complete(&flow, &params["state"], &code)
+            .await?;
+        assert_eq!(return_to, "/apps");
+        Ok(user)
+    }
+
+    async fn create_user(&self, email: &str, verified: bool) -> User {
+        common::create_user(&self.db, email, verified).await
+    }
+}
+
+fn message(result: Result<User, ServiceError>) -> String {
+    match result {
+        Err(ServiceError::Invalid(message)) => message,
+        other => panic!("expected the sign in to be refused, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn creates_an_account_for_a_verified_email() {
+    let setup = setup().await;
+    // otherwise the new account would be the first, and made an admin
+    setup.create_user("grace@example.com", true).await;
+
+    let user = setup
+        .sign_in(
+            "ada",
+            json!({ "email": "ada@example.com", "email_verified": true }),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(user.email, "ada@example.com");
+    assert_eq!(user.role, Role::Viewer);
+    assert!(user.is_verified());
+}
+
+#[tokio::test]
+async fn links_an_existing_account_for_a_verified_email() {
+    let setup = setup().await;
+    let existing = setup.create_user("ada@example.com", false).await;
+
+    let user = setup
+        .sign_in(
+            "ada",
+            json!({ "email": "ada@example.com", "email_verified": true }),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(user.id, existing.id);
+    let user = UserRepository::new(&setup.db)
+        .find_by_id(existing.id)
+        .await
+        .unwrap();
+    assert!(user.is_verified());
+}
+
+#[tokio::test]
+async fn refuses_to_link_an_unverified_email() {
+    let setup = setup().await;
+    let existing = setup.create_user("ada@example.com", true).await;
+
+    for claims in [
+        json!({ "email": "ada@example.com", "email_verified": false }),
+        json!({ "email": "ada@example.com" }),
+    ] {
+        let result = setup.sign_in("mallory", claims).await;
+        assert_eq!(
+            message(result),
+            "Verify your email address with Test IdP first."
+        );
+    }
+
+    // once the account is linked to its owner's identity, other identities
+    // with the same unverified email still can't get in
+    let user = setup
+        .sign_in(
+            "ada",
+            json!({ "email": "ada@example.com", "email_verified": true }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(user.id, existing.id);
+    let result = setup
+        .sign_in("mallory", json!({ "email": "ada@example.com" }))
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn refuses_to_create_an_account_for_an_unverified_email() {
+    let setup = setup().await;
+
+    let result = setup
+        .sign_in("ada", json!({ "email": "ada@example.com" }))
+        .await;
+
+    assert_eq!(
+        message(result),
+        "Verify your email address with Test IdP first."
+    );
+    let found = UserRepository::new(&setup.db)
+        .find_by_email("ada@example.com")
+        .await;
+    assert!(matches!(found, Err(sqlx::Error::RowNotFound)));
+}
+
+#[tokio::test]
+async fn a_linked_identity_signs_in_without_the_email_claims() {
+    let setup = setup().await;
+    let created = setup
+        .sign_in(
+            "ada",
+            json!({ "email": "ada@example.com", "email_verified": true }),
+        )
+        .await
+        .unwrap();
+
+    let user = setup
+        .sign_in("ada", json!({ "email": "ada@example.com" }))
+        .await
+        .unwrap();
+
+    assert_eq!(user.id, created.id);
+}
+
+#[tokio::test]
+async fn discovers_the_provider_once() {
+    let setup = setup().await;
+
+    for _ in 0..3 {
+        setup
+            .sign_in(
+                "ada",
+                json!({ "email": "ada@example.com", "email_verified": true }),
+            )
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(setup.provider.discoveries.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn still_asks_for_a_two_factor_code() {
+    let setup = setup().await;
+    let user = setup
+        .sign_in(
+            "ada",
+            json!({ "email": "ada@example.com", "email_verified": true }),
+        )
+        .await
+        .unwrap();
+    sqlx::query(
+        "INSERT INTO two_factor_secrets (user_id, secret, confirmed_at) VALUES (?, 'JBSWY3DPEHPK3PXP', CURRENT_TIMESTAMP)",
+    )
+    .bind(user.id)
+    .execute(&setup.db)
+    .await
+    .unwrap();
+
+    let outcome = AuthService::new(setup.db.clone())
+        .login_with_identity(&user)
+        .await
+        .unwrap();
+
+    assert!(matches!(outcome, IdentityLogin::TwoFactor(_)));
+}
+
+#[tokio::test]
+async fn groups_never_demote_the_last_admin() {
+    let setup = setup_with(|config| {
+        config.role_mapping = vec![("cms-admins".to_string(), Role::Admin)];
+    })
+    .await;
+    let admin = setup.create_user("ada@example.com", true).await;
+    let claims = json!({
+        "email": "ada@example.com",
+        "email_verified": true,
+        "groups": ["cms-editors"],
+    });
+
+    let user = setup.sign_in("ada", claims.clone()).await.unwrap();
+    assert_eq!(user.id, admin.id);
+    assert_eq!(user.role, Role::Admin);
+
+    // once there's another admin, their groups decide again
+    let grace = setup.create_user("grace@example.com", true).await;
+    UserRepository::new(&setup.db)
+        .update_role(grace.id, Role::Admin)
+        .await
+        .unwrap();
+    let user = setup.sign_in("ada", claims).await.unwrap();
+    assert_eq!(user.role, Role::Viewer);
+}
+
+#[tokio::test]
+async fn only_creates_accounts_when_allowed() {
+    let setup = setup_with(|config| config.create_users = false).await;
+    setup.create_user("grace@example.com", true).await;
+
+    let result = setup
+        .sign_in(
+            "ada",
+            json!({ "email": "ada@example.com", "email_verified": true }),
+        )
+        .await;
+
+    assert_eq!(
+        message(result),
+        "There's no account for ada@example.com. Ask an admin for one."
+    );
+    let found = UserRepository::new(&setup.db)
+        .find_by_email("ada@example.com")
+        .await;
+    assert!(matches!(found, Err(sqlx::Error::RowNotFound)));
+}
@@ -0,0 +1,313 @@
+//! Registering and signing in with passkeys made by a software
+//! authenticator, which does what a browser and security key would.
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ciborium::Value;
+use ring::{
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use wordford::{
+    services::error::ServiceError,
+    user::{
+        NewPasskeyRequest, PasskeySignInRequest, User, auth::AuthService, passkey::PasskeyService,
+    },
+};
+
+mod common;
+
+/// Matches the `APP_URL` the tests run with.
+const ORIGIN: &str = "https://wordford.test";
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+struct Authenticator {
+    key_pair: EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+}
+
+impl Authenticator {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+
+        Authenticator {
+            key_pair,
+            credential_id: rand::random::<[u8; 16]>().to_vec(),
+            sign_count: 0,
+        }
+    }
+
+    fn credential_id(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.credential_id)
+    }
+
+    /// The public key as COSE, with its key type, algorithm and curve.
+    fn cose_key(&self, key_type: i64, algorithm: i64, curve: i64) -> Vec<u8> {
+        // an uncompressed point: 0x04, then x and y
+        let point = self.key_pair.public_key().as_ref();
+        let key = Value::Map(vec![
+            (1.into(), key_type.into()),
+            (3.into(), algorithm.into()),
+            ((-1).into(), curve.into()),
+            ((-2).into(), Value::Bytes(point[1..33].to_vec())),
+            ((-3).into(), Value::Bytes(point[33..].to_vec())),
+        ]);
+
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&key, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn register(&self, challenge: &str, cose_key: Vec<u8>) -> NewPasskeyRequest {
+        let mut authenticator_data = authenticator_data(
+            FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL,
+            self.sign_count,
+        );
+        authenticator_data.extend([0; 16]);
+        authenticator_data.extend((self.credential_id.len() as u16).to_be_bytes());
+        authenticator_data.extend(&self.credential_id);
+        authenticator_data.extend(cose_key);
+
+        let attestation_object = Value::Map(vec![
+            ("fmt".into(), "none".into()),
+            ("attStmt".into(), Value::Map(Vec::new())),
+            ("authData".into(), Value::Bytes(authenticator_data)),
+        ]);
+        let mut attestation_bytes = Vec::new();
+        ciborium::into_writer(&attestation_object, &mut attestation_bytes).unwrap();
+
+        NewPasskeyRequest {
+            name: "Laptop".to_string(),
+            client_data_json: client_data("webauthn.create", challenge, ORIGIN),
+            attestation_object: URL_SAFE_NO_PAD.encode(attestation_bytes),
+        }
+    }
+
+    fn sign_in(&mut self, challenge: &str, origin: &str) -> PasskeySignInRequest {
+        self.sign_count += 1;
+        let authenticator_data =
+            authenticator_data(FLAG_USER_PRESENT | FLAG_USER_VERIFIED, self.sign_count);
+        let client_data_json = client_data("webauthn.get", challenge, origin);
+
+        let mut message = authenticator_data.clone();
+        message.extend(Sha256::digest(
+            URL_SAFE_NO_PAD.decode(&client_data_json).unwrap(),
+        ));
+        let signature = self.key_pair.sign(&SystemRandom::new(), &message).unwrap();
+
+        PasskeySignInRequest {
+            id: self.credential_id(),
+            client_data_json,
+            authenticator_data: URL_SAFE_NO_PAD.encode(authenticator_data),
+            signature: URL_SAFE_NO_PAD.encode(signature.as_ref()),
+            return_to: None,
+        }
+    }
+}
+
+fn authenticator_data(flags: u8, sign_count: u32) -> Vec<u8> {
+    let mut data = Sha256::digest("wordford.test").to_vec();
+    data.push(flags);
+    data.extend(sign_count.to_be_bytes());
+    data
+}
+
+fn client_data(kind: &str, challenge: &str, origin: &str) -> String {
+    let client_data = json!({ "type": kind, "challenge": challenge, "origin": origin });
+    URL_SAFE_NO_PAD.encode(client_data.to_string())
+}
+
+struct Setup {
+    db: SqlitePool,
+    service: PasskeyService,
+    user: User,
+}
+
+async fn setup() -> Setup {
+    let db = common::database().await;
+    let service = PasskeyService::new(&db);
+    let user = common::create_user(&db, "ada@example.com", true).await;
+
+    Setup { db, service, user }
+}
+
+impl Setup {
+    async fn register(
+        &self,
+        authenticator: &Authenticator,
+        cose_key: Vec<u8>,
+    ) -> Result<(), ServiceError> {
+        let options = self.service.registration_options(&self.user).await.unwrap();
+        let challenge = options["challenge"].as_str().unwrap();
+
+        self.service
+            .register(&self.user, &authenticator.register(challenge, cose_key))
+            .await
+    }
+
+    async fn sign_in_request(&self, authenticator: &mut Authenticator) -> PasskeySignInRequest {
+        let options = self.service.authentication_options().await.unwrap();
+        authenticator.sign_in(options["challenge"].as_str().unwrap(), ORIGIN)
+    }
+}
+
+fn message<T: std::fmt::Debug>(result: Result<T, ServiceError>) -> String {
+    match result {
+        Err(ServiceError::Invalid(message)) => message,
+        other => panic!("expected the passkey to be refused, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn registers_and_signs_in() {
+    let setup = setup().await;
+    let mut authenticator = Authenticator::new();
+
+    setup
+        .register(&authenticator, authenticator.cose_key(2, -7, 1))
+        .await
+        .unwrap();
+    let passkeys = setup.service.passkeys(&setup.user).await.unwrap();
+    assert_eq!(passkeys.len(), 1);
+    assert_eq!(passkeys[0].credential_id, authenticator.credential_id());
+
+    for _ in 0..2 {
+        let request = setup.sign_in_request(&mut authenticator).await;
+        let user_id = setup.service.authenticate(&request).await.unwrap();
+        assert_eq!(user_id, setup.user.id);
+    }
+}
+
+#[tokio::test]
+async fn refuses_keys_whose_type_or_curve_doesnt_match_the_algorithm() {
+    let setup = setup().await;
+    let authenticator = Authenticator::new();
+
+    for (key_type, algorithm, curve) in [
+        // OKP, which Ed25519 keys use
+        (1, -7, 1),
+        // RSA
+        (3, -7, 1),
+        // P-384
+        (2, -7, 2),
+        // Ed25519
+        (2, -7, 6),
+        // ES384
+        (2, -35, 1),
+    ] {
+        let result = setup
+            .register(
+                &authenticator,
+                authenticator.cose_key(key_type, algorithm, curve),
+            )
+            .await;
+        assert_eq!(message(result), "Passkeys of this kind aren't supported.");
+    }
+    assert!(
+        setup
+            .service
+            .passkeys(&setup.user)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn refuses_a_signature_from_another_key() {
+    let setup = setup().await;
+    let mut authenticator = Authenticator::new();
+    setup
+        .register(&authenticator, authenticator.cose_key(2, -7, 1))
+        .await
+        .unwrap();
+
+    let mut impostor = Authenticator {
+        credential_id: authenticator.credential_id.clone(),
+        ..Authenticator::new()
+    };
+    let request = setup.sign_in_request(&mut impostor).await;
+    assert_eq!(
+        message(setup.service.authenticate(&request).await),
+        "Your passkey's response couldn't be verified."
+    );
+
+    // the real one still works
+    let request = setup.sign_in_request(&mut authenticator).await;
+    assert!(setup.service.authenticate(&request).await.is_ok());
+}
+
+#[tokio::test]
+async fn refuses_another_origin_and_a_used_challenge() {
+    let setup = setup().await;
+    let mut authenticator = Authenticator::new();
+    setup
+        .register(&authenticator, authenticator.cose_key(2, -7, 1))
+        .await
+        .unwrap();
+
+    let options = setup.service.authentication_options().await.unwrap();
+    let challenge = options["challenge"].as_str().unwrap();
+    let request = authenticator.sign_in(challenge, "https://evil.test");
+    assert!(setup.service.authenticate(&request).await.is_err());
+
+    let request = setup.sign_in_request(&mut authenticator).await;
+    setup.service.authenticate(&request).await.unwrap();
+    assert_eq!(
+        message(setup.service.authenticate(&request).await),
+        "That took too long. Please try again."
+    );
+}
+
+#[tokio::test]
+async fn refuses_a_counter_that_goes_backwards() {
+    let setup = setup().await;
+    let mut authenticator = Authenticator::new();
+    setup
+        .register(&authenticator, authenticator.cose_key(2, -7, 1))
+        .await
+        .unwrap();
+    authenticator.sign_count = 10;
+    let request = setup.sign_in_request(&mut authenticator).await;
+    setup.service.authenticate(&request).await.unwrap();
+
+    // a clone of the passkey that was copied before that sign in
+    authenticator.sign_count = 5;
+    let request = setup.sign_in_request(&mut authenticator).await;
+    assert!(
+        message(setup.service.authenticate(&request).await)
+            .starts_with("This passkey can't be trusted anymore.")
+    );
+}
+
+#[tokio::test]
+async fn needs_a_verified_email_to_sign_in() {
+    let setup = setup().await;
+    let mut authenticator = Authenticator::new();
+    setup
+        .register(&authenticator, authenticator.cose_key(2, -7, 1))
+        .await
+        .unwrap();
+    sqlx::query("UPDATE users SET email_verified_at = NULL WHERE id = ?")
+        .bind(setup.user.id)
+        .execute(&setup.db)
+        .await
+        .unwrap();
+
+    let request = setup.sign_in_request(&mut authenticator).await;
+    let result = AuthService::new(setup.db.clone())
+        .login_with_passkey(&request)
+        .await;
+
+    assert!(message(result).starts_with("Please verify your email address"));
+}
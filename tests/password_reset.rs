@@ -0,0 +1,36 @@
+//! Choosing a new password with a reset link.
+
+use wordford::{services::error::ServiceError, user::password_reset::PasswordResetService};
+
+mod common;
+
+#[tokio::test]
+async fn refuses_passwords_with_the_users_name_and_keeps_the_link() {
+    let db = common::database().await;
+    common::create_user(&db, "ada@wordford.test", true).await;
+    let service = PasswordResetService::new(&db);
+    let (_, token) = service
+        .issue_token("ada@wordford.test")
+        .await
+        .unwrap()
+        .unwrap();
+
+    let password = "lovelace analytical engine";
+    let result = service.reset_password(&token, password, password).await;
+
+    match result {
+        Err(ServiceError::Invalid(message)) => assert_eq!(
+            message,
+            "Your password shouldn't contain your name or email address."
+        ),
+        other => panic!("expected the password to be refused, got {:?}", other),
+    }
+    assert!(service.is_valid_token(&token).await.unwrap());
+
+    let password = "correct horse battery staple";
+    service
+        .reset_password(&token, password, password)
+        .await
+        .unwrap();
+    assert!(!service.is_valid_token(&token).await.unwrap());
+}
@@ -0,0 +1,174 @@
+//! Signing out revokes the session behind a token, on one device or all.
+
+use std::env;
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode, header::AUTHORIZATION},
+};
+use jsonwebtoken::{DecodingKey, Validation, decode};
+use sqlx::SqlitePool;
+use tower::ServiceExt;
+use wordford::user::{
+    CreateUserRequest, NewTokenRequest,
+    auth::{AuthService, LoginOutcome, UserClaims},
+    password::hash_password,
+    repository::UserRepository,
+    role::Role,
+    routes,
+    session::SessionRepository,
+    tokens::{TokenScope, TokenService},
+};
+
+mod common;
+
+const PASSWORD: &str = "correct horse battery staple";
+const IP: &str = "127.0.0.1";
+
+async fn sign_up(db: &SqlitePool, email: &str) {
+    let request = CreateUserRequest {
+        email: email.to_string(),
+        password: hash_password(PASSWORD).await.unwrap(),
+        given_name: "Grace".to_string(),
+        family_name: "Hopper".to_string(),
+        invite: None,
+    };
+    UserRepository::new(db)
+        .create_user(&request, Role::Editor, true)
+        .await
+        .unwrap();
+}
+
+async fn sign_in(auth: &AuthService, email: &str) -> UserClaims {
+    let LoginOutcome::Success(token) = auth.login(email, PASSWORD, IP).await.unwrap() else {
+        panic!("{email} should be able to sign in");
+    };
+    let secret = env::var("JWT_SECRET").unwrap();
+    decode::<UserClaims>(
+        &token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .unwrap()
+    .claims
+}
+
+#[tokio::test]
+async fn each_sign_in_gets_its_own_session() {
+    let db = common::database().await;
+    sign_up(&db, "grace@wordford.test").await;
+    let auth = AuthService::new(db.clone());
+
+    let laptop = sign_in(&auth, "grace@wordford.test").await;
+    let phone = sign_in(&auth, "grace@wordford.test").await;
+
+    assert_ne!(laptop.jti, phone.jti);
+    assert!(auth.is_session_active(&laptop).await.unwrap());
+    assert!(auth.is_session_active(&phone).await.unwrap());
+    assert!(matches!(
+        auth.login("grace@wordford.test", "wrong password", IP)
+            .await
+            .unwrap(),
+        LoginOutcome::InvalidCredentials
+    ));
+}
+
+#[tokio::test]
+async fn signing_out_only_ends_that_session() {
+    let db = common::database().await;
+    sign_up(&db, "grace@wordford.test").await;
+    let auth = AuthService::new(db.clone());
+    let laptop = sign_in(&auth, "grace@wordford.test").await;
+    let phone = sign_in(&auth, "grace@wordford.test").await;
+
+    auth.logout(&laptop.jti).await.unwrap();
+
+    assert!(!auth.is_session_active(&laptop).await.unwrap());
+    assert!(auth.is_session_active(&phone).await.unwrap());
+}
+
+#[tokio::test]
+async fn signing_out_everywhere_leaves_other_users_signed_in() {
+    let db = common::database().await;
+    sign_up(&db, "grace@wordford.test").await;
+    sign_up(&db, "alan@wordford.test").await;
+    let auth = AuthService::new(db.clone());
+    let laptop = sign_in(&auth, "grace@wordford.test").await;
+    let phone = sign_in(&auth, "grace@wordford.test").await;
+    let other = sign_in(&auth, "alan@wordford.test").await;
+
+    let user_id = laptop.sub.parse().unwrap();
+    assert_eq!(auth.logout_everywhere(user_id).await.unwrap(), 2);
+
+    assert!(!auth.is_session_active(&laptop).await.unwrap());
+    assert!(!auth.is_session_active(&phone).await.unwrap());
+    assert!(auth.is_session_active(&other).await.unwrap());
+}
+
+#[tokio::test]
+async fn personal_access_tokens_cannot_sign_out_everywhere() {
+    let db = common::database().await;
+    sign_up(&db, "grace@wordford.test").await;
+    let auth = AuthService::new(db.clone());
+    let laptop = sign_in(&auth, "grace@wordford.test").await;
+    let user = UserRepository::new(&db)
+        .find_by_email("grace@wordford.test")
+        .await
+        .unwrap();
+    let request = NewTokenRequest {
+        name: "script".to_string(),
+        scopes: TokenScope::ALL.to_vec(),
+        expires_in_days: 7,
+    };
+    let token = TokenService::new(&db)
+        .create_token(&user, &request)
+        .await
+        .unwrap();
+
+    let response = routes::routes()
+        .with_state(common::state(&db))
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/signout/everywhere")
+                .header(AUTHORIZATION, format!("Bearer {}", token.secret))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(auth.is_session_active(&laptop).await.unwrap());
+}
+
+#[tokio::test]
+async fn a_session_belongs_to_one_user() {
+    let db = common::database().await;
+    sign_up(&db, "grace@wordford.test").await;
+    sign_up(&db, "alan@wordford.test").await;
+    let auth = AuthService::new(db.clone());
+    let grace = sign_in(&auth, "grace@wordford.test").await;
+    let alan = sign_in(&auth, "alan@wordford.test").await;
+
+    // a token re-signed with someone else's id does not carry their session
+    let forged = UserClaims {
+        sub: alan.sub.clone(),
+        ..grace
+    };
+    assert!(!auth.is_session_active(&forged).await.unwrap());
+}
+
+#[tokio::test]
+async fn revoked_sessions_are_cleaned_up() {
+    let db = common::database().await;
+    sign_up(&db, "grace@wordford.test").await;
+    let auth = AuthService::new(db.clone());
+    let laptop = sign_in(&auth, "grace@wordford.test").await;
+    let phone = sign_in(&auth, "grace@wordford.test").await;
+    auth.logout(&laptop.jti).await.unwrap();
+
+    let sessions = SessionRepository::new(&db);
+    assert_eq!(sessions.delete_expired().await.unwrap(), 1);
+    assert!(auth.is_session_active(&phone).await.unwrap());
+}
@@ -0,0 +1,132 @@
+//! Signing up through invites, and the first account becoming an admin.
+
+use wordford::{
+    services::error::ServiceError,
+    user::{CreateUserRequest, NewInviteRequest, role::Role, signup::SignupService},
+};
+
+mod common;
+
+fn request(email: &str, invite: Option<&str>) -> CreateUserRequest {
+    CreateUserRequest {
+        email: email.to_string(),
+        password: "correct horse battery staple".to_string(),
+        given_name: "Grace".to_string(),
+        family_name: "Hopper".to_string(),
+        invite: invite.map(str::to_string),
+    }
+}
+
+fn invite(email: &str, role: Role) -> NewInviteRequest {
+    NewInviteRequest {
+        email: email.to_string(),
+        role,
+    }
+}
+
+#[tokio::test]
+async fn the_first_account_needs_no_invite_and_becomes_an_admin() {
+    let db = common::database().await;
+    let signup = SignupService::new(&db);
+    assert!(signup.is_open().await.unwrap());
+
+    let first = signup
+        .register(&mut request("grace@wordford.test", None))
+        .await
+        .unwrap();
+
+    assert_eq!(first.role, Role::Admin);
+    assert!(first.email_verified_at.is_none());
+    assert!(!signup.is_open().await.unwrap());
+    let second = signup
+        .register(&mut request("alan@wordford.test", None))
+        .await;
+    assert!(matches!(second, Err(ServiceError::Invalid(_))));
+}
+
+#[tokio::test]
+async fn accepting_an_invite_gives_its_role_and_verifies_the_email() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let signup = SignupService::new(&db);
+    let (_, token) = signup
+        .create_invite(&admin, invite("grace@wordford.test", Role::Publisher))
+        .await
+        .unwrap();
+
+    let user = signup
+        .register(&mut request("Grace@wordford.test ", Some(&token)))
+        .await
+        .unwrap();
+
+    assert_eq!(user.role, Role::Publisher);
+    assert!(user.email_verified_at.is_some());
+    assert!(signup.pending_invites().await.unwrap().is_empty());
+    // an invite only works once
+    let again = signup
+        .register(&mut request("grace@wordford.test", Some(&token)))
+        .await;
+    assert!(matches!(again, Err(ServiceError::Invalid(_))));
+}
+
+#[tokio::test]
+async fn an_invite_only_works_for_its_own_email() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let signup = SignupService::new(&db);
+    let (_, token) = signup
+        .create_invite(&admin, invite("grace@wordford.test", Role::Editor))
+        .await
+        .unwrap();
+
+    let result = signup
+        .register(&mut request("alan@wordford.test", Some(&token)))
+        .await;
+
+    assert!(matches!(result, Err(ServiceError::Invalid(_))));
+    assert_eq!(signup.pending_invites().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn revoked_and_replaced_invites_cannot_be_used() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let signup = SignupService::new(&db);
+    let (revoked, revoked_token) = signup
+        .create_invite(&admin, invite("grace@wordford.test", Role::Editor))
+        .await
+        .unwrap();
+    signup.revoke_invite(&admin, revoked.id).await.unwrap();
+    let (_, old_token) = signup
+        .create_invite(&admin, invite("alan@wordford.test", Role::Editor))
+        .await
+        .unwrap();
+    let (_, new_token) = signup
+        .create_invite(&admin, invite("alan@wordford.test", Role::Viewer))
+        .await
+        .unwrap();
+
+    assert!(signup.find_invite(&revoked_token).await.is_err());
+    assert!(signup.find_invite(&old_token).await.is_err());
+    assert_eq!(
+        signup.find_invite(&new_token).await.unwrap().role,
+        Role::Viewer
+    );
+}
+
+#[tokio::test]
+async fn only_admins_invite_and_never_existing_accounts() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let editor = common::create_user(&db, "editor@wordford.test", true).await;
+    let signup = SignupService::new(&db);
+
+    let by_editor = signup
+        .create_invite(&editor, invite("grace@wordford.test", Role::Editor))
+        .await;
+    assert!(matches!(by_editor, Err(ServiceError::Forbidden(_))));
+    let existing = signup
+        .create_invite(&admin, invite("editor@wordford.test", Role::Editor))
+        .await;
+    assert!(matches!(existing, Err(ServiceError::Invalid(_))));
+}
@@ -0,0 +1,182 @@
+//! Personal access tokens, and how their scopes narrow what a user can do.
+
+use wordford::{
+    services::error::{ServiceError, authorize},
+    user::{
+        NewTokenRequest, User,
+        password::hash_password,
+        profile::ProfileService,
+        repository::UserRepository,
+        role::Permission,
+        tokens::{TokenScope, TokenService},
+    },
+};
+
+mod common;
+
+fn request(scopes: &[TokenScope]) -> NewTokenRequest {
+    NewTokenRequest {
+        name: " deploy script ".to_string(),
+        scopes: scopes.to_vec(),
+        expires_in_days: 30,
+    }
+}
+
+fn with_scopes(user: &User, scopes: &[TokenScope]) -> User {
+    User {
+        token_scopes: Some(scopes.to_vec()),
+        ..user.clone()
+    }
+}
+
+#[tokio::test]
+async fn a_token_authenticates_as_its_owner_with_its_scopes() {
+    let db = common::database().await;
+    let user = common::admin(&db).await;
+    let tokens = TokenService::new(&db);
+
+    let created = tokens
+        .create_token(&user, &request(&[TokenScope::Pages, TokenScope::Content]))
+        .await
+        .unwrap();
+
+    assert!(created.secret.starts_with("wfp_"));
+    assert_eq!(created.token.prefix, created.secret[..12]);
+    assert_eq!(created.token.name, "deploy script");
+    let stored: String =
+        sqlx::query_scalar("SELECT token_hash FROM personal_access_tokens WHERE id = ?")
+            .bind(created.token.id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert_ne!(stored, created.secret);
+
+    let (user_id, scopes) = tokens.authenticate(&created.secret).await.unwrap();
+    assert_eq!(user_id, user.id);
+    assert_eq!(scopes, vec![TokenScope::Content, TokenScope::Pages]);
+    assert!(
+        tokens.tokens(&user).await.unwrap()[0]
+            .last_used_at
+            .is_some()
+    );
+}
+
+#[tokio::test]
+async fn revoked_and_unknown_tokens_are_rejected() {
+    let db = common::database().await;
+    let user = common::admin(&db).await;
+    let other = common::create_user(&db, "other@wordford.test", true).await;
+    let tokens = TokenService::new(&db);
+    let created = tokens
+        .create_token(&user, &request(&[TokenScope::Content]))
+        .await
+        .unwrap();
+
+    // only the owner can revoke a token
+    tokens.revoke(&other, created.token.id).await.unwrap();
+    assert!(tokens.authenticate(&created.secret).await.is_ok());
+    tokens.revoke(&user, created.token.id).await.unwrap();
+    assert!(tokens.authenticate(&created.secret).await.is_err());
+
+    assert!(tokens.authenticate(&created.secret[4..]).await.is_err());
+    // an app API key is not a personal access token
+    assert!(
+        tokens
+            .authenticate(&format!("wf_{}", &created.secret[4..]))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn tokens_need_a_name_and_a_known_expiry() {
+    let db = common::database().await;
+    let user = common::admin(&db).await;
+    let tokens = TokenService::new(&db);
+
+    let unnamed = NewTokenRequest {
+        name: "  ".to_string(),
+        ..request(&[])
+    };
+    assert!(matches!(
+        tokens.create_token(&user, &unnamed).await,
+        Err(ServiceError::Invalid(_))
+    ));
+    let forever = NewTokenRequest {
+        expires_in_days: 10_000,
+        ..request(&[])
+    };
+    assert!(matches!(
+        tokens.create_token(&user, &forever).await,
+        Err(ServiceError::Invalid(_))
+    ));
+}
+
+#[tokio::test]
+async fn scopes_only_narrow_what_the_role_allows() {
+    let db = common::database().await;
+    let admin = common::admin(&db).await;
+    let editor = common::create_user(&db, "editor@wordford.test", true).await;
+
+    let content = with_scopes(&admin, &[TokenScope::Content]);
+    assert!(authorize(&content, Permission::EditContent).is_ok());
+    assert!(authorize(&content, Permission::PublishContent).is_ok());
+    assert!(matches!(
+        authorize(&content, Permission::CreatePages),
+        Err(ServiceError::Forbidden(Permission::CreatePages))
+    ));
+    assert!(authorize(&content, Permission::ManageUsers).is_err());
+
+    let unscoped = with_scopes(&admin, &[]);
+    assert!(!unscoped.permissions().edit_content);
+    assert!(authorize(&unscoped, Permission::EditContent).is_err());
+
+    // a scope never grants more than the owner's role
+    let editor_admin = with_scopes(&editor, &[TokenScope::Admin, TokenScope::Content]);
+    assert!(authorize(&editor_admin, Permission::EditContent).is_ok());
+    assert!(authorize(&editor_admin, Permission::PublishContent).is_err());
+    assert!(authorize(&editor_admin, Permission::ManageUsers).is_err());
+
+    // signed in with a session, there are no scopes to narrow anything
+    assert!(admin.token_scopes.is_none());
+    assert!(authorize(&admin, Permission::ManageUsers).is_ok());
+}
+
+#[test]
+fn form_fields_become_a_request() {
+    let request = NewTokenRequest::from_pairs(vec![
+        ("name".to_string(), "CI".to_string()),
+        ("scopes".to_string(), "content".to_string()),
+        ("scopes".to_string(), "everything".to_string()),
+        ("scopes".to_string(), "pages".to_string()),
+        ("expires_in_days".to_string(), "90".to_string()),
+    ]);
+
+    assert_eq!(request.name, "CI");
+    assert_eq!(request.scopes, vec![TokenScope::Content, TokenScope::Pages]);
+    assert_eq!(request.expires_in_days, 90);
+}
+
+#[tokio::test]
+async fn changing_the_password_revokes_tokens() {
+    let db = common::database().await;
+    let user = common::admin(&db).await;
+    let old_password = "correct horse battery staple";
+    UserRepository::new(&db)
+        .update_password(user.id, &hash_password(old_password).await.unwrap())
+        .await
+        .unwrap();
+    let tokens = TokenService::new(&db);
+    let created = tokens
+        .create_token(&user, &request(&[TokenScope::Content]))
+        .await
+        .unwrap();
+
+    let new_password = "a much longer passphrase";
+    ProfileService::new(&db)
+        .change_password(&user, "session", old_password, new_password, new_password)
+        .await
+        .unwrap();
+
+    assert!(tokens.authenticate(&created.secret).await.is_err());
+}
@@ -0,0 +1,168 @@
+//! Authenticator app codes and recovery codes, each of which only works once.
+
+use chrono::Utc;
+use sqlx::SqlitePool;
+use totp_rs::{Algorithm, Secret, TOTP};
+use wordford::{
+    services::error::ServiceError,
+    user::{
+        CreateUserRequest, User,
+        auth::{AuthService, LoginOutcome},
+        password::hash_password,
+        repository::UserRepository,
+        role::Role,
+        two_factor::{RECOVERY_CODE_COUNT, TwoFactorService},
+    },
+};
+
+mod common;
+
+const PASSWORD: &str = "correct horse battery staple";
+const IP: &str = "127.0.0.1";
+
+/// Stands in for the user's authenticator app.
+struct App(TOTP);
+
+impl App {
+    fn new(secret: &str) -> Self {
+        let bytes = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+        App(TOTP::new(Algorithm::SHA1, 6, 0, 30, bytes, None, "test".to_string()).unwrap())
+    }
+
+    /// The code shown `steps` time steps from now.
+    fn code(&self, steps: i64) -> String {
+        self.0
+            .generate((Utc::now().timestamp() + steps * 30) as u64)
+    }
+}
+
+/// Turns two-factor authentication on for a new user, returning their app
+/// and recovery codes.
+async fn enrol(db: &SqlitePool, user: &User) -> (App, Vec<String>) {
+    let two_factor = TwoFactorService::new(db);
+    let enrolment = two_factor.begin_enrolment(user).await.unwrap();
+    let app = App::new(&enrolment.secret);
+    let recovery_codes = two_factor
+        .confirm_enrolment(user, &app.code(0))
+        .await
+        .unwrap();
+
+    (app, recovery_codes)
+}
+
+#[tokio::test]
+async fn enrolment_needs_a_code_from_the_new_secret() {
+    let db = common::database().await;
+    let user = common::admin(&db).await;
+    let two_factor = TwoFactorService::new(&db);
+    let enrolment = two_factor.begin_enrolment(&user).await.unwrap();
+    let app = App::new(&enrolment.secret);
+
+    // a code from well outside the allowed clock drift
+    let wrong = two_factor.confirm_enrolment(&user, &app.code(5)).await;
+    assert!(matches!(wrong, Err(ServiceError::Invalid(_))));
+    assert!(!two_factor.is_enabled(user.id).await.unwrap());
+
+    let codes = two_factor
+        .confirm_enrolment(&user, &app.code(0))
+        .await
+        .unwrap();
+    assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+    let status = two_factor.status(&user).await.unwrap();
+    assert!(status.enabled);
+    assert_eq!(status.recovery_codes_left, RECOVERY_CODE_COUNT as i64);
+}
+
+#[tokio::test]
+async fn an_app_code_cannot_be_replayed() {
+    let db = common::database().await;
+    let user = common::admin(&db).await;
+    let (app, _) = enrol(&db, &user).await;
+    let two_factor = TwoFactorService::new(&db);
+
+    // the code used to confirm enrolment is already spent
+    assert!(!two_factor.verify_code(user.id, &app.code(0)).await.unwrap());
+
+    let next = app.code(1);
+    assert!(two_factor.verify_code(user.id, &next).await.unwrap());
+    assert!(!two_factor.verify_code(user.id, &next).await.unwrap());
+}
+
+#[tokio::test]
+async fn a_recovery_code_can_only_be_used_once() {
+    let db = common::database().await;
+    let user = common::admin(&db).await;
+    let (_, codes) = enrol(&db, &user).await;
+    let two_factor = TwoFactorService::new(&db);
+
+    // however it was written down
+    let typed = format!(" {} ", codes[0].replace('-', "").to_uppercase());
+    assert!(two_factor.verify_code(user.id, &typed).await.unwrap());
+    assert!(!two_factor.verify_code(user.id, &codes[0]).await.unwrap());
+    assert!(
+        !two_factor
+            .verify_code(user.id, "abcde-12345")
+            .await
+            .unwrap()
+    );
+
+    let status = two_factor.status(&user).await.unwrap();
+    assert_eq!(status.recovery_codes_left, RECOVERY_CODE_COUNT as i64 - 1);
+}
+
+#[tokio::test]
+async fn regenerating_recovery_codes_replaces_the_old_ones() {
+    let db = common::database().await;
+    let user = common::admin(&db).await;
+    let (_, old) = enrol(&db, &user).await;
+    let two_factor = TwoFactorService::new(&db);
+
+    let new = two_factor
+        .regenerate_recovery_codes(&user, &old[0])
+        .await
+        .unwrap();
+
+    assert!(!two_factor.verify_code(user.id, &old[1]).await.unwrap());
+    assert!(two_factor.verify_code(user.id, &new[0]).await.unwrap());
+}
+
+#[tokio::test]
+async fn signing_in_asks_for_a_code_before_starting_a_session() {
+    let db = common::database().await;
+    let request = CreateUserRequest {
+        email: "grace@wordford.test".to_string(),
+        password: hash_password(PASSWORD).await.unwrap(),
+        given_name: "Grace".to_string(),
+        family_name: "Hopper".to_string(),
+        invite: None,
+    };
+    let user = UserRepository::new(&db)
+        .create_user(&request, Role::Editor, true)
+        .await
+        .unwrap();
+    let (_, codes) = enrol(&db, &user).await;
+    let auth = AuthService::new(db.clone());
+
+    let LoginOutcome::TwoFactorRequired(challenge) =
+        auth.login(&user.email, PASSWORD, IP).await.unwrap()
+    else {
+        panic!("a code should be asked for");
+    };
+
+    let wrong = auth
+        .complete_two_factor(&challenge, "abcde-12345", IP)
+        .await;
+    assert!(matches!(wrong, Err(ServiceError::Invalid(_))));
+    let forged = auth
+        .complete_two_factor(&format!("{challenge}x"), &codes[0], IP)
+        .await;
+    assert!(matches!(forged, Err(ServiceError::Invalid(_))));
+    assert!(
+        auth.complete_two_factor(&challenge, &codes[0], IP)
+            .await
+            .is_ok()
+    );
+    // the challenge can be reused, but the code cannot
+    let replayed = auth.complete_two_factor(&challenge, &codes[0], IP).await;
+    assert!(matches!(replayed, Err(ServiceError::Invalid(_))));
+}
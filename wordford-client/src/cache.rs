@@ -0,0 +1,44 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use reqwest::header::HeaderValue;
+
+/// A response kept for revalidation, with the `ETag` the server gave it.
+#[derive(Clone)]
+pub struct Cached {
+    pub etag: HeaderValue,
+    pub body: Arc<[u8]>,
+}
+
+/// Responses by URL. Clones of a client share one cache.
+#[derive(Clone, Default)]
+pub struct Cache {
+    entries: Arc<Mutex<HashMap<String, Cached>>>,
+}
+
+impl Cache {
+    pub fn get(&self, url: &str) -> Option<Cached> {
+        self.entries
+            .lock()
+            .expect("cache lock poisoned")
+            .get(url)
+            .cloned()
+    }
+
+    pub fn insert(&self, url: &str, etag: HeaderValue, body: impl Into<Arc<[u8]>>) {
+        let cached = Cached {
+            etag,
+            body: body.into(),
+        };
+        self.entries
+            .lock()
+            .expect("cache lock poisoned")
+            .insert(url.to_string(), cached);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().expect("cache lock poisoned").clear();
+    }
+}
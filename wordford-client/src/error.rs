@@ -0,0 +1,84 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The base URL given to the client can't be used.
+    InvalidUrl(String),
+    /// The request couldn't be sent, or the response couldn't be read.
+    Http(reqwest::Error),
+    /// The server refused the request. `code` is the API's error code, such
+    /// as `page_not_found`.
+    Api {
+        status: StatusCode,
+        code: String,
+        message: String,
+    },
+    /// The response, or a page's content, didn't fit the type asked for.
+    Decode(serde_json::Error),
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    code: String,
+    message: String,
+}
+
+impl Error {
+    /// Reads the API's error body, falling back to the status when the
+    /// response has none, like the 404 for a page id that doesn't exist.
+    pub(crate) fn from_response(status: StatusCode, body: &[u8]) -> Self {
+        match serde_json::from_slice::<ErrorBody>(body) {
+            Ok(ErrorBody { error }) => Error::Api {
+                status,
+                code: error.code,
+                message: error.message,
+            },
+            Err(_) => Error::Api {
+                status,
+                code: status.as_str().to_string(),
+                message: String::from_utf8_lossy(body).into_owned(),
+            },
+        }
+    }
+
+    /// True when the app or page asked for doesn't exist, or has nothing
+    /// published.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Api { status, .. } if *status == StatusCode::NOT_FOUND)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Decode(err)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidUrl(url) => write!(f, "invalid base URL: {}", url),
+            Error::Http(err) => err.fmt(f),
+            Error::Api {
+                status,
+                code,
+                message,
+            } => write!(f, "{} ({}): {}", status, code, message),
+            Error::Decode(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
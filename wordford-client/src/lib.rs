@@ -0,0 +1,204 @@
+//! A typed client for the Wordford delivery API.
+//!
+//! ```no_run
+//! use serde::Deserialize;
+//! use wordford_client::Client;
+//!
+//! #[derive(Deserialize)]
+//! struct Homepage {
+//!     title: String,
+//!     intro: Option<String>,
+//! }
+//!
+//! # async fn example() -> Result<(), wordford_client::Error> {
+//! let client = Client::builder("https://wordford.example")
+//!     .api_key("wf_...")
+//!     .build()?;
+//!
+//! let homepage: Homepage = client.get("Wordford", "homepage").await?;
+//! let page = client.page("Wordford", "about").await?;
+//! println!("{} {:?}", homepage.title, page.content.get("team"));
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::{
+    StatusCode, Url,
+    header::{ETAG, IF_NONE_MATCH},
+};
+use serde::de::DeserializeOwned;
+
+use crate::cache::Cache;
+
+mod cache;
+mod error;
+
+pub use error::Error;
+pub use wordford_models::{DeliveredPage, DeliveredPages, PageContent};
+
+/// Clones share their connection pool and cache.
+#[derive(Clone)]
+pub struct Client {
+    base_url: Url,
+    http: reqwest::Client,
+    api_key: Option<String>,
+    cache: Option<Cache>,
+}
+
+pub struct ClientBuilder {
+    base_url: String,
+    api_key: Option<String>,
+    cache: bool,
+    http: Option<reqwest::Client>,
+}
+
+impl ClientBuilder {
+    /// Sends `key` as `Authorization: Bearer <key>`. Every request for
+    /// content needs an API key for the app the page belongs to.
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Whether responses are kept in memory and revalidated with the server
+    /// instead of downloaded again. On by default.
+    pub fn cache(mut self, enabled: bool) -> Self {
+        self.cache = enabled;
+        self
+    }
+
+    /// Uses an existing `reqwest` client, e.g. one with custom timeouts.
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    pub fn build(self) -> Result<Client, Error> {
+        let base_url = Url::parse(&self.base_url)
+            .ok()
+            .filter(|url| !url.cannot_be_a_base())
+            .ok_or(Error::InvalidUrl(self.base_url))?;
+
+        Ok(Client {
+            base_url,
+            http: self.http.unwrap_or_default(),
+            api_key: self.api_key,
+            cache: self.cache.then(Cache::default),
+        })
+    }
+}
+
+impl Client {
+    /// A client for the Wordford server at `base_url`, with caching and
+    /// without an API key. Use `builder` to set one.
+    pub fn new(base_url: impl Into<String>) -> Result<Self, Error> {
+        Client::builder(base_url).build()
+    }
+
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            base_url: base_url.into(),
+            api_key: None,
+            cache: true,
+            http: None,
+        }
+    }
+
+    /// Fetches the published content of an app's page by name.
+    pub async fn page(&self, app: &str, page: &str) -> Result<DeliveredPage, Error> {
+        let url = self.url(&["api", "v1", "apps", app, "pages", page]);
+
+        self.fetch(url).await
+    }
+
+    /// Fetches several pages of an app at once. Pages that don't exist, or
+    /// have nothing published, are listed in `missing`.
+    pub async fn pages(&self, app: &str, names: &[&str]) -> Result<DeliveredPages, Error> {
+        let mut url = self.url(&["api", "v1", "apps", app, "pages"]);
+        url.query_pairs_mut().append_pair("names", &names.join(","));
+
+        self.fetch(url).await
+    }
+
+    /// Fetches the published content of a page by its id. The API key must
+    /// belong to the page's app.
+    pub async fn page_content(&self, page_id: i64) -> Result<PageContent, Error> {
+        let url = self.url(&["pages", &page_id.to_string(), "content"]);
+
+        self.fetch(url).await
+    }
+
+    /// Fetches a page by name and deserializes its content into `T`, with
+    /// one field per content entry. Entries `T` doesn't mention are ignored,
+    /// so pages can gain content without breaking older clients.
+    pub async fn get<T: DeserializeOwned>(&self, app: &str, page: &str) -> Result<T, Error> {
+        let page = self.page(app, page).await?;
+
+        into_typed(page.content)
+    }
+
+    /// Like `get`, for a page fetched by id.
+    pub async fn get_by_id<T: DeserializeOwned>(&self, page_id: i64) -> Result<T, Error> {
+        into_typed(self.page_content(page_id).await?)
+    }
+
+    /// Forgets every cached response.
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("base URL was checked when building the client")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    /// GETs `url`, asking the server whether the cached copy is still
+    /// current when there is one, and only downloading it again if not.
+    async fn fetch<T: DeserializeOwned>(&self, url: Url) -> Result<T, Error> {
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(url.as_str()));
+
+        let mut request = self.http.get(url.clone());
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        if let Some(cached) = &cached {
+            request = request.header(IF_NONE_MATCH, &cached.etag);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED
+            && let Some(cached) = cached
+        {
+            return Ok(serde_json::from_slice(&cached.body)?);
+        }
+
+        let etag = response.headers().get(ETAG).cloned();
+        let body = response.bytes().await?;
+        if !status.is_success() {
+            return Err(Error::from_response(status, &body));
+        }
+
+        let value = serde_json::from_slice(&body)?;
+        if let (Some(cache), Some(etag)) = (&self.cache, etag) {
+            cache.insert(url.as_str(), etag, body.as_ref());
+        }
+
+        Ok(value)
+    }
+}
+
+fn into_typed<T: DeserializeOwned>(content: PageContent) -> Result<T, Error> {
+    let value = serde_json::to_value(content)?;
+
+    Ok(serde_json::from_value(value)?)
+}
@@ -0,0 +1,347 @@
+//! The client against a stand-in for the delivery API, served on a local
+//! port.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{AUTHORIZATION, ETAG, IF_NONE_MATCH},
+    },
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Deserialize;
+use serde_json::json;
+use wordford_client::{Client, DeliveredPage, DeliveredPages, Error, PageContent};
+
+const API_KEY: &str = "wf_test";
+
+#[derive(Default)]
+struct Server {
+    url: String,
+    /// The content of the homepage, and the version it's tagged with.
+    homepage: Mutex<(PageContent, u32)>,
+    /// The `If-None-Match` header of each request, in order.
+    requests: Mutex<Vec<Option<String>>>,
+}
+
+impl Server {
+    async fn start() -> Arc<Server> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = Arc::new(Server {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            homepage: Mutex::new((
+                content(&[("title", "Welcome"), ("intro", "Hello there")]),
+                1,
+            )),
+            ..Default::default()
+        });
+
+        let app = Router::new()
+            .route("/api/v1/apps/{app}/pages/{page}", get(page))
+            .route("/api/v1/apps/{app}/pages", get(pages))
+            .route("/pages/{id}/content", get(page_content))
+            .with_state(server.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        server
+    }
+
+    fn client(&self) -> Client {
+        Client::builder(&self.url).api_key(API_KEY).build().unwrap()
+    }
+
+    fn publish(&self, homepage: PageContent) {
+        let mut current = self.homepage.lock().unwrap();
+        *current = (homepage, current.1 + 1);
+    }
+
+    fn requests(&self) -> Vec<Option<String>> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn content(entries: &[(&str, &str)]) -> PageContent {
+    entries
+        .iter()
+        .map(|(name, body)| (name.to_string(), body.to_string()))
+        .collect()
+}
+
+fn api_error(status: StatusCode, code: &str, message: &str) -> Response {
+    let body = json!({ "error": { "code": code, "message": message } });
+
+    (status, Json(body)).into_response()
+}
+
+/// Does what the API's auth and ETag middleware would, then calls `respond`.
+fn respond(
+    server: &Server,
+    headers: &HeaderMap,
+    respond: impl FnOnce(&PageContent) -> Response,
+) -> Response {
+    let if_none_match = headers
+        .get(IF_NONE_MATCH)
+        .map(|value| value.to_str().unwrap().to_string());
+    server.requests.lock().unwrap().push(if_none_match.clone());
+
+    let expected = format!("Bearer {}", API_KEY);
+    if headers.get(AUTHORIZATION) != Some(&HeaderValue::from_str(&expected).unwrap()) {
+        return api_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_api_key",
+            "The API key is missing or invalid.",
+        );
+    }
+
+    let (homepage, version) = &*server.homepage.lock().unwrap();
+    let tag = format!("\"v{}\"", version);
+    if if_none_match.as_deref() == Some(tag.as_str()) {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, tag)]).into_response();
+    }
+
+    let mut response = respond(homepage);
+    if response.status() == StatusCode::OK {
+        response
+            .headers_mut()
+            .insert(ETAG, HeaderValue::from_str(&tag).unwrap());
+    }
+    response
+}
+
+async fn page(
+    State(server): State<Arc<Server>>,
+    Path((app, page)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&server, &headers, |homepage| match page.as_str() {
+        "homepage" => Json(DeliveredPage {
+            app,
+            page,
+            content: homepage.clone(),
+        })
+        .into_response(),
+        "broken" => Json(json!({ "unexpected": true })).into_response(),
+        _ => api_error(
+            StatusCode::NOT_FOUND,
+            "page_not_found",
+            "This page doesn't exist.",
+        ),
+    })
+}
+
+#[derive(Deserialize)]
+struct PagesQuery {
+    names: String,
+}
+
+async fn pages(
+    State(server): State<Arc<Server>>,
+    Path(app): Path<String>,
+    Query(query): Query<PagesQuery>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&server, &headers, |homepage| {
+        let (found, missing): (Vec<_>, Vec<_>) =
+            query.names.split(',').partition(|name| *name == "homepage");
+
+        Json(DeliveredPages {
+            app,
+            pages: found
+                .into_iter()
+                .map(|name| (name.to_string(), homepage.clone()))
+                .collect::<BTreeMap<_, _>>(),
+            missing: missing.into_iter().map(str::to_string).collect(),
+        })
+        .into_response()
+    })
+}
+
+async fn page_content(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&server, &headers, |homepage| match id {
+        1 => Json(homepage.clone()).into_response(),
+        // like the real route, which has no error body for this
+        _ => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Homepage {
+    title: String,
+    intro: Option<String>,
+    footer: Option<String>,
+}
+
+#[tokio::test]
+async fn fetches_a_page() {
+    let server = Server::start().await;
+
+    let page = server.client().page("Wordford", "homepage").await.unwrap();
+
+    assert_eq!(page.app, "Wordford");
+    assert_eq!(page.page, "homepage");
+    assert_eq!(page.content["title"], "Welcome");
+}
+
+#[tokio::test]
+async fn fetches_several_pages() {
+    let server = Server::start().await;
+
+    let pages = server
+        .client()
+        .pages("Wordford", &["homepage", "about"])
+        .await
+        .unwrap();
+
+    assert_eq!(pages.pages["homepage"]["title"], "Welcome");
+    assert_eq!(pages.missing, ["about"]);
+}
+
+#[tokio::test]
+async fn deserializes_a_page_into_a_type() {
+    let server = Server::start().await;
+    let client = server.client();
+
+    let expected = Homepage {
+        title: "Welcome".to_string(),
+        intro: Some("Hello there".to_string()),
+        footer: None,
+    };
+    let homepage: Homepage = client.get("Wordford", "homepage").await.unwrap();
+    assert_eq!(homepage, expected);
+    let homepage: Homepage = client.get_by_id(1).await.unwrap();
+    assert_eq!(homepage, expected);
+}
+
+#[tokio::test]
+async fn reports_content_that_doesnt_fit_the_type() {
+    let server = Server::start().await;
+    server.publish(content(&[("intro", "No title")]));
+
+    let result = server
+        .client()
+        .get::<Homepage>("Wordford", "homepage")
+        .await;
+
+    assert!(matches!(result, Err(Error::Decode(_))));
+    let result = server.client().page("Wordford", "broken").await;
+    assert!(matches!(result, Err(Error::Decode(_))));
+}
+
+#[tokio::test]
+async fn revalidates_cached_pages() {
+    let server = Server::start().await;
+    let client = server.client();
+
+    for _ in 0..3 {
+        let page = client.page("Wordford", "homepage").await.unwrap();
+        assert_eq!(page.content["title"], "Welcome");
+    }
+    server.publish(content(&[("title", "Welcome back")]));
+    let page = client.page("Wordford", "homepage").await.unwrap();
+    assert_eq!(page.content["title"], "Welcome back");
+    let page = client.page("Wordford", "homepage").await.unwrap();
+    assert_eq!(page.content["title"], "Welcome back");
+
+    let v1 = Some("\"v1\"".to_string());
+    let v2 = Some("\"v2\"".to_string());
+    assert_eq!(server.requests(), [None, v1.clone(), v1.clone(), v1, v2]);
+}
+
+#[tokio::test]
+async fn caches_each_url_separately() {
+    let server = Server::start().await;
+    let client = server.client();
+
+    client.page("Wordford", "homepage").await.unwrap();
+    client.page_content(1).await.unwrap();
+    client.page_content(1).await.unwrap();
+
+    let v1 = Some("\"v1\"".to_string());
+    assert_eq!(server.requests(), [None, None, v1]);
+}
+
+#[tokio::test]
+async fn downloads_again_without_a_cache() {
+    let server = Server::start().await;
+    let client = Client::builder(&server.url)
+        .api_key(API_KEY)
+        .cache(false)
+        .build()
+        .unwrap();
+
+    client.page("Wordford", "homepage").await.unwrap();
+    client.page("Wordford", "homepage").await.unwrap();
+
+    assert_eq!(server.requests(), [None, None]);
+}
+
+#[tokio::test]
+async fn clearing_the_cache_downloads_again() {
+    let server = Server::start().await;
+    let client = server.client();
+
+    client.page("Wordford", "homepage").await.unwrap();
+    client.clone().clear_cache();
+    client.page("Wordford", "homepage").await.unwrap();
+
+    assert_eq!(server.requests(), [None, None]);
+}
+
+#[tokio::test]
+async fn reads_the_apis_errors() {
+    let server = Server::start().await;
+
+    let err = server.client().page("Wordford", "about").await.unwrap_err();
+    assert!(err.is_not_found());
+    match err {
+        Error::Api {
+            status,
+            code,
+            message,
+        } => {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(code, "page_not_found");
+            assert_eq!(message, "This page doesn't exist.");
+        }
+        other => panic!("expected an API error, got {:?}", other),
+    }
+
+    let err = Client::new(&server.url)
+        .unwrap()
+        .page("Wordford", "homepage")
+        .await
+        .unwrap_err();
+    assert!(!err.is_not_found());
+    assert!(matches!(
+        err,
+        Error::Api { status: StatusCode::UNAUTHORIZED, ref code, .. } if code == "invalid_api_key"
+    ));
+}
+
+#[tokio::test]
+async fn falls_back_to_the_status_without_an_error_body() {
+    let server = Server::start().await;
+
+    let err = server.client().page_content(2).await.unwrap_err();
+
+    assert!(err.is_not_found());
+    assert_eq!(err.to_string(), "404 Not Found (404): ");
+}
+
+#[test]
+fn refuses_a_url_that_cant_be_a_base() {
+    for url in ["not a url", "mailto:ada@example.com"] {
+        assert!(matches!(Client::new(url), Err(Error::InvalidUrl(bad)) if bad == url));
+    }
+}
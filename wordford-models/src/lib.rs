@@ -0,0 +1,39 @@
+//! What the Wordford delivery API sends, shared by the server that builds
+//! these and the clients that read them.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Published content of a page, keyed by content name. Ordered, so the same
+/// content always serializes to the same bytes and keeps its ETag.
+pub type PageContent = BTreeMap<String, String>;
+
+// a type alias can't carry a schema of its own, so this stands in for
+// `PageContent` in the API documentation
+/// Published content of a page, keyed by content name.
+#[cfg(feature = "openapi")]
+#[derive(utoipa::ToSchema)]
+#[schema(as = PageContent)]
+pub struct PageContentSchema(pub BTreeMap<String, String>);
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeliveredPage {
+    pub app: String,
+    pub page: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = PageContentSchema))]
+    pub content: PageContent,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeliveredPages {
+    pub app: String,
+    #[cfg_attr(
+        feature = "openapi",
+        schema(value_type = BTreeMap<String, PageContentSchema>)
+    )]
+    pub pages: BTreeMap<String, PageContent>,
+    pub missing: Vec<String>,
+}